@@ -1,55 +1,547 @@
-use crate::audio::{Audio, interleave_stereo};
+use crate::audio::autotune::{self, AutotuneError, ShiftEngine, pyin::PYINData};
+use crate::audio::playback::{
+    self, OutputLevels, PREVIEW_BUFFER_FRAMES, PlaybackSource, PlaybackState, PreviewSink,
+    PreviewSource, RING_BUFFER_FRAMES,
+};
+use crate::audio::{
+    Audio, LOW_MONO_COMPATIBILITY, file::AudioFileData, monitor::Monitor, recorder::Recorder,
+};
 use crate::gui::components::track;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Number of output frames a full-scale (1.0) volume change is spread over,
+/// ~46ms at 44.1kHz. Smaller changes take proportionally fewer frames.
+const VOLUME_RAMP_FRAMES: f32 = 2048.0;
+
+/// Frames per CPAL output callback unless another size is requested
+pub const DEFAULT_BUFFER_SIZE: u32 = 512;
+
+/// Number of commands the AudioController's channel holds before senders have to wait
+pub const COMMAND_CHANNEL_CAPACITY: usize = 100;
 
 /// Commands sent to the AudioController for processing
 /// Each command represents an action to be performed on the audio playback system
 /**
 - SendTrack(Audio, u32): Send audio data to be played on a specific track index.
-- RemoveTrack(u32): Remove the audio track at the specified index.
+- RemoveTrack(u32): Remove the audio track with the given id.
+- ReorderTracks(Vec<u32>): Set the order (by id) tracks are mixed in, matching the TrackManager.
 - ClearBuffer: Clear the current audio buffer.
 - Play: Start audio playback.
 - Stop: Stop audio playback.
 - SetReadPosition(usize): Set the current read position in the audio buffer.
+- BroadcastPosition: Send the current read position to the TrackManager.
+- BroadcastLevels: Send the peak/RMS level of the latest output block to the TrackManager.
 - SetVolume(f32): Set the playback volume.
-- Shutdown: Shut down the audio controller and stop playback.
+- SetPlaybackRate(f32): Play the mix faster or slower (varispeed, the pitch follows the rate),
+  clamped to 0.25-4.0. Defaults to 1.0.
+- SetTrackPan(u32, f32): Set the pan (-1.0 left to 1.0 right) of the track with the given id.
+- SetStereoWidth(f32): Set the stereo width of the mix (0.0 mono, 1.0 unchanged, >1.0 wider).
+- SetCompareOriginal(u32, bool): While set, play the track without autotune for A/B listening.
+- SetBufferSize(u32): Reopen the output stream with the given number of frames per callback.
+- SetShiftEngine(ShiftEngine): Select the algorithm tracks are autotuned with.
+- RenderStems(PathBuf): Write every track, autotuned and panned, to its own WAV file
+  (track_<id>.wav) in the given directory.
+- PrerenderAll: Render every track (including autotune) and the mix again. Like every remix
+  this runs on a background task and the current mix keeps playing until it is done.
+- PlayTone(f32, u32): Play a short sine of the given frequency (Hz) and duration (ms) on top
+  of the output, whether or not playback is running.
+- ClearClipIndicators: Reset the master and per-track "clipped since last reset" flags.
+- StartRecording: Start capturing audio from the default input device.
+- StopRecording: Stop capturing and hand the recording back to the TrackManager as a new clip.
+- SetMonitorMode(bool): While on, play the default input device back live, autotuned to the
+  nearest semitone, on top of the output. Reports whether monitoring is on afterwards to
+  the TrackManager, it stays off if the input can't be opened.
+- Shutdown: Stop playback, pause the output stream and end the run loop.
 */
 #[derive(Debug)]
 pub enum AudioCommand {
     SendTrack(Audio, u32),
     RemoveTrack(u32),
+    ReorderTracks(Vec<u32>),
     ClearBuffer,
     Play,
     Stop,
     SetReadPosition(usize),
     BroadcastPosition,
+    BroadcastLevels,
     SetVolume(f32),
+    SetPlaybackRate(f32),
+    SetTrackPan(u32, f32),
+    SetStereoWidth(f32),
+    SetCompareOriginal(u32, bool),
+    SetBufferSize(u32),
+    SetShiftEngine(ShiftEngine),
+    RenderStems(PathBuf),
+    PrerenderAll,
+    PlayTone(f32, u32),
+    ClearClipIndicators,
+    StartRecording,
+    StopRecording,
+    SetMonitorMode(bool),
     Shutdown,
 }
 
+/// Peak-hold clip indicators: each flag is set once a sample goes past full scale (±1.0)
+/// and stays set until cleared. Clones share the same flags.
+#[derive(Debug, Default, Clone)]
+pub struct ClipIndicators {
+    /// Set from the CPAL callback, after the master volume is applied
+    master: Arc<AtomicBool>,
+    /// Set in `render_mix` for each rendered track
+    tracks: Arc<Mutex<HashMap<u32, bool>>>,
+}
+
+impl ClipIndicators {
+    pub fn master_clipped(&self) -> bool {
+        self.master.load(Ordering::Relaxed)
+    }
+
+    pub fn track_clipped(&self, id: u32) -> bool {
+        self.tracks
+            .lock()
+            .map(|tracks| tracks.get(&id).copied().unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// Records whether the rendered audio of a track clips
+    fn record_track(&self, id: u32, audio: &Audio) {
+        if exceeds_full_scale(audio.left()) || exceeds_full_scale(audio.right()) {
+            match self.tracks.lock() {
+                Ok(mut tracks) => {
+                    tracks.insert(id, true);
+                }
+                Err(e) => error!("Track clip indicator mutex poisoned: {e}"),
+            }
+        }
+    }
+
+    fn remove_track(&self, id: u32) {
+        if let Ok(mut tracks) = self.tracks.lock() {
+            tracks.remove(&id);
+        }
+    }
+
+    fn clear(&self) {
+        self.master.store(false, Ordering::Relaxed);
+        if let Ok(mut tracks) = self.tracks.lock() {
+            tracks.clear();
+        }
+    }
+}
+
+fn exceeds_full_scale(samples: &[f32]) -> bool {
+    samples.iter().any(|s| s.abs() > 1.0)
+}
+
+/// Caches the last PSOLA output of each track, so remixing (e.g. toggling autotune
+/// bypass, panning another track) only re-runs PSOLA when its inputs actually change.
+#[derive(Debug, Default)]
+struct ShiftCache {
+    entries: HashMap<u32, (u64, Audio)>,
+    /// Algorithm used for new computations, part of the cache key
+    engine: ShiftEngine,
+    /// Number of times PSOLA actually ran, for diagnostics
+    computations: usize,
+}
+
+impl ShiftCache {
+    /// Returns the shifted audio for a track, from the cache if the track's samples, desired
+    /// F0, tune mask, PYIN data, format and the engine are unchanged since it was last computed.
+    fn get_or_compute(&mut self, id: u32, audio: &Audio) -> Result<Audio, AutotuneError> {
+        let pyin = audio.get_pyin().ok_or(AutotuneError::MissingPyin)?;
+        let desired_f0 = audio
+            .desired_f0
+            .as_ref()
+            .ok_or(AutotuneError::MissingDesiredF0)?;
+        let key = shift_cache_key(audio, &pyin, desired_f0, self.engine);
+        if let Some((cached_key, shifted)) = self.entries.get(&id)
+            && *cached_key == key
+        {
+            debug!(track_id = id, "AudioController: Using cached shifted audio");
+            return Ok(shifted.clone());
+        }
+        let shifted = autotune::compute_shifted_audio_with_engine(audio, self.engine)?;
+        self.computations += 1;
+        self.entries.insert(id, (key, shifted.clone()));
+        Ok(shifted)
+    }
+
+    /// Drops the entries of tracks not in `ids`, e.g. removed ones
+    fn retain(&mut self, ids: &[u32]) {
+        self.entries.retain(|id, _| ids.contains(id));
+    }
+}
+
+/// Renders a track for mixing: its autotuned audio if a desired F0 is set, otherwise (or
+/// when `compare_original` is set for A/B listening, or tuning fails) the original audio.
+fn render_track(
+    shift_cache: &mut ShiftCache,
+    id: u32,
+    track: &Audio,
+    compare_original: bool,
+) -> Audio {
+    if compare_original {
+        debug!("AudioController: A/B compare active, adding original track");
+        return track.clone();
+    }
+    if let Some(desired_f0) = &track.desired_f0 {
+        debug!(
+            "AudioController: Autotuning track with desired F0 of length {}",
+            desired_f0.len()
+        );
+        match shift_cache.get_or_compute(id, track) {
+            Ok(shifted_audio) => shifted_audio,
+            Err(AutotuneError::MissingPyin) => {
+                // Analysis is still running, the track is remixed once it is sent again
+                debug!("AudioController: PYIN data not ready, adding original track");
+                track.clone()
+            }
+            Err(e) => {
+                error!(
+                    "AudioController: Autotuning failed, adding original track: {}",
+                    e
+                );
+                track.clone()
+            }
+        }
+    } else {
+        debug!("AudioController: No desired F0, adding original track");
+        track.clone()
+    }
+}
+
+/// `render_track` followed by the track's pan
+fn render_panned_track(
+    shift_cache: &mut ShiftCache,
+    id: u32,
+    track: &Audio,
+    track_pans: &HashMap<u32, f32>,
+    compare_original: bool,
+) -> Audio {
+    let mut rendered = render_track(shift_cache, id, track, compare_original);
+    let pan = track_pans.get(&id).copied().unwrap_or(0.0);
+    if pan != 0.0 {
+        rendered.apply_pan(pan);
+    }
+    rendered
+}
+
+/// Renders every track on its own (autotune and pan applied, without the master stereo
+/// width or volume) and writes it to `dir/track_<id>.wav`, creating `dir` if needed.
+/// Returns the paths written, in mixing order.
+fn render_stems<'a>(
+    tracks: impl IntoIterator<Item = (u32, &'a Audio)>,
+    track_pans: &HashMap<u32, f32>,
+    shift_cache: &mut ShiftCache,
+    dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for (id, track) in tracks {
+        let rendered = render_panned_track(shift_cache, id, track, track_pans, false);
+        let path = dir.join(format!("track_{}.wav", id));
+        let compatibility = rendered.mono_compatibility();
+        if compatibility < LOW_MONO_COMPATIBILITY {
+            warn!(
+                compatibility,
+                "AudioController: Stem {} partly cancels out when played in mono",
+                path.display()
+            );
+        }
+        AudioFileData::from_audio(&rendered).save(&path)?;
+        debug!("AudioController: Wrote stem {}", path.display());
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Renders `tracks` (in mixing order) and sums them into one buffer, applying each track's
+/// pan. The master stereo width is applied on playback, see `PlaybackState`. The mix runs at
+/// the sample rate of the first track, tracks at another rate are left out of it.
+fn render_mix<'a>(
+    tracks: impl IntoIterator<Item = (u32, &'a Audio)>,
+    track_pans: &HashMap<u32, f32>,
+    compare_original: &HashSet<u32>,
+    shift_cache: &mut ShiftCache,
+    clip_indicators: &ClipIndicators,
+) -> Audio {
+    let rendered: Vec<Audio> = tracks
+        .into_iter()
+        .map(|(id, track)| {
+            let rendered = render_panned_track(
+                shift_cache,
+                id,
+                track,
+                track_pans,
+                compare_original.contains(&id),
+            );
+            clip_indicators.record_track(id, &rendered);
+            rendered
+        })
+        .collect();
+    let sample_rate = rendered.first().map_or(44100, Audio::sample_rate);
+    let clips: Vec<(&Audio, f32)> = rendered
+        .iter()
+        .filter(|track| {
+            let matches = track.sample_rate() == sample_rate;
+            if !matches {
+                error!(
+                    "AudioController: Leaving a {} Hz track out of the {} Hz mix",
+                    track.sample_rate(),
+                    sample_rate
+                );
+            }
+            matches
+        })
+        .map(|track| (track, 1.0))
+        .collect();
+    Audio::mix(&clips, sample_rate).unwrap_or_else(|e| {
+        error!("AudioController: Failed to mix tracks: {}", e);
+        Audio::new(sample_rate, Vec::new(), Vec::new())
+    })
+}
+
+/// Everything needed to mix the tracks away from the AudioController, so a slow render
+/// (PSOLA on every track of a freshly loaded project) does not hold up the command loop.
+struct MixSnapshot {
+    tracks: Vec<(u32, Audio)>,
+    track_pans: HashMap<u32, f32>,
+    compare_original: HashSet<u32>,
+    engine: ShiftEngine,
+}
+
+/// Where `MixSnapshot::spawn_render` puts the finished mix, shared with the AudioController
+#[derive(Clone)]
+struct MixTarget {
+    audio_buffer: Arc<Mutex<Audio>>,
+    playback: Arc<PlaybackState>,
+    clip_indicators: ClipIndicators,
+    /// Generation of the latest mix requested. Only a render of that generation is swapped
+    /// in, so an older render finishing late can't overwrite a newer mix.
+    latest_generation: Arc<AtomicU64>,
+}
+
+impl MixTarget {
+    fn is_latest(&self, generation: u64) -> bool {
+        self.latest_generation.load(Ordering::Acquire) == generation
+    }
+}
+
+impl MixSnapshot {
+    /// Renders the mix of `generation` on a blocking task and swaps it into the target's
+    /// audio buffer in one go, so playback continues from the previous mix until the new one
+    /// is complete. Renders overtaken by a newer generation are dropped, before rendering if
+    /// possible, and are never swapped in.
+    fn spawn_render(
+        self,
+        generation: u64,
+        shift_cache: Arc<Mutex<ShiftCache>>,
+        target: MixTarget,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn_blocking(move || {
+            let time_start = std::time::Instant::now();
+            let mut shift_cache = match shift_cache.lock() {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("AudioController: Shift cache mutex poisoned: {e}");
+                    return;
+                }
+            };
+            if !target.is_latest(generation) {
+                debug!(generation, "AudioController: Skipping stale mix render");
+                return;
+            }
+            shift_cache.engine = self.engine;
+            let ids: Vec<u32> = self.tracks.iter().map(|(id, _)| *id).collect();
+            shift_cache.retain(&ids);
+            let mixed_audio = render_mix(
+                self.tracks.iter().map(|(id, track)| (*id, track)),
+                &self.track_pans,
+                &self.compare_original,
+                &mut shift_cache,
+                &target.clip_indicators,
+            );
+            drop(shift_cache);
+            match target.audio_buffer.lock() {
+                // Checked with the buffer locked, so no newer mix can land in between
+                Ok(_) if !target.is_latest(generation) => {
+                    debug!(generation, "AudioController: Dropping stale mix render");
+                    return;
+                }
+                Ok(mut buffer) => *buffer = mixed_audio,
+                Err(e) => {
+                    error!("AudioController: Audio buffer mutex poisoned: {e}");
+                    return;
+                }
+            }
+            // Drop what was already queued from the old mix so the change is heard right away
+            target.playback.seek(target.playback.position());
+            debug!(
+                "AudioController: Mixing {} tracks took {:?}",
+                self.tracks.len(),
+                time_start.elapsed()
+            );
+        })
+    }
+
+    /// Writes every track to its own WAV file in `dir` on a blocking task, see `render_stems`
+    fn spawn_render_stems(
+        self,
+        shift_cache: Arc<Mutex<ShiftCache>>,
+        dir: PathBuf,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn_blocking(move || {
+            let mut shift_cache = match shift_cache.lock() {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("AudioController: Shift cache mutex poisoned: {e}");
+                    return;
+                }
+            };
+            shift_cache.engine = self.engine;
+            match render_stems(
+                self.tracks.iter().map(|(id, track)| (*id, track)),
+                &self.track_pans,
+                &mut shift_cache,
+                &dir,
+            ) {
+                Ok(paths) => info!(
+                    "AudioController: Wrote {} stems to {}",
+                    paths.len(),
+                    dir.display()
+                ),
+                Err(e) => error!(
+                    "AudioController: Failed to write stems to {}: {}",
+                    dir.display(),
+                    e
+                ),
+            }
+        })
+    }
+}
+
+/// Hash of everything the PSOLA output of a track depends on. The samples are covered by
+/// their analysis generation, which every edit bumps, rather than hashed one by one.
+fn shift_cache_key(audio: &Audio, pyin: &PYINData, desired_f0: &[f32], engine: ShiftEngine) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    engine.hash(&mut hasher);
+    audio.sample_rate().hash(&mut hasher);
+    audio.length().hash(&mut hasher);
+    audio.analysis_generation().hash(&mut hasher);
+    for values in [desired_f0, pyin.f0(), pyin.voiced_prob()] {
+        values.len().hash(&mut hasher);
+        for value in values {
+            value.to_bits().hash(&mut hasher);
+        }
+    }
+    pyin.voiced_flag().hash(&mut hasher);
+    audio.tune_mask.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// An output stream opened by `AudioController::open_output_stream`
+struct OutputStream {
+    /// None for a controller built without an output device, in tests
+    stream: Option<cpal::Stream>,
+    /// Queues sounds mixed on top of the playback, e.g. preview tones
+    preview: PreviewSink,
+    /// The monitor's own queue, mixed in like `preview`. Moved into the input callback of
+    /// the first monitor session, so None once that started (or without an output device).
+    monitor: Option<PreviewSink>,
+    sample_rate: u32,
+}
+
 /// Controller for managing audio playback using CPAL
 /// It handles commands to play, stop, and manipulate audio tracks
 /// and mixes multiple audio tracks into a single output buffer.
+/// Dropping the controller closes its CPAL streams, which stops any audio still playing.
 pub struct AudioController {
     receiver: tokio::sync::mpsc::Receiver<AudioCommand>,
     track_manager_sender: tokio::sync::mpsc::Sender<track::TrackManagerCommand>,
     tracks: HashMap<u32, Audio>,
+    track_order: Vec<u32>,
+    track_pans: HashMap<u32, f32>,
+    /// Only locked by render tasks, never on the command loop
+    shift_cache: Arc<Mutex<ShiftCache>>,
+    shift_engine: ShiftEngine,
+    /// Generation of the latest mix requested, see `MixSnapshot::spawn_render`
+    mix_generation: Arc<AtomicU64>,
+    compare_original: HashSet<u32>,
+    /// The current mix. Only the controller and the playback feeder thread lock it, the
+    /// output callback reads from the feeder's ring buffer instead
     audio_buffer: Arc<Mutex<Audio>>,
-    volume: Arc<Mutex<f32>>,
-    position: Arc<Mutex<usize>>,
-    playing: Arc<Mutex<bool>>,
-    _stream: cpal::Stream,
+    playback: Arc<PlaybackState>,
+    clip_indicators: ClipIndicators,
+    recorder: Option<Recorder>,
+    input_stream: Option<cpal::Stream>,
+    /// Input stream played back through the `Monitor` while monitoring
+    monitor_stream: Option<cpal::Stream>,
+    buffer_size: u32,
+    output_stream: OutputStream,
 }
 
 impl AudioController {
     pub fn new(
         receiver: tokio::sync::mpsc::Receiver<AudioCommand>,
         track_manager_sender: tokio::sync::mpsc::Sender<track::TrackManagerCommand>,
+    ) -> anyhow::Result<Self> {
+        Self::with_buffer_size(receiver, track_manager_sender, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Like `new`, requesting `buffer_size` frames per output callback. Smaller buffers lower
+    /// the latency but glitch sooner on slow devices. Falls back to the device's default
+    /// buffer size if it does not support the requested one.
+    pub fn with_buffer_size(
+        receiver: tokio::sync::mpsc::Receiver<AudioCommand>,
+        track_manager_sender: tokio::sync::mpsc::Sender<track::TrackManagerCommand>,
+        buffer_size: u32,
     ) -> anyhow::Result<Self> {
         info!("Initializing AudioController");
+        let audio_buffer = Arc::new(Mutex::new(Audio::new(44100, Vec::new(), Vec::new())));
+        let playback = Arc::new(PlaybackState::default());
+        let clip_indicators = ClipIndicators::default();
+
+        let output_stream = Self::open_output_stream(
+            buffer_size,
+            &audio_buffer,
+            &playback,
+            &clip_indicators.master,
+        )?;
+        Ok(Self {
+            receiver,
+            audio_buffer,
+            playback,
+            tracks: HashMap::new(),
+            track_order: Vec::new(),
+            track_pans: HashMap::new(),
+            shift_cache: Arc::new(Mutex::new(ShiftCache::default())),
+            shift_engine: ShiftEngine::default(),
+            mix_generation: Arc::new(AtomicU64::new(0)),
+            compare_original: HashSet::new(),
+            track_manager_sender,
+            clip_indicators,
+            recorder: None,
+            input_stream: None,
+            monitor_stream: None,
+            buffer_size,
+            output_stream,
+        })
+    }
+
+    /// Opens and starts a stereo f32 output stream on the default output device, along with
+    /// a feeder thread that streams the shared audio buffer to its callback
+    fn open_output_stream(
+        buffer_size: u32,
+        audio_buffer: &Arc<Mutex<Audio>>,
+        playback: &Arc<PlaybackState>,
+        master_clipped: &Arc<AtomicBool>,
+    ) -> anyhow::Result<OutputStream> {
         let host = cpal::default_host();
         debug!(audio_host = ?host.id(), "Using audio host");
         let device = host
@@ -58,268 +550,556 @@ impl AudioController {
         let supported_config = device.default_output_config()?;
         debug!("Default output config: {:?}", supported_config);
         let sample_format = supported_config.sample_format();
+        if sample_format != cpal::SampleFormat::F32 {
+            return Err(anyhow::anyhow!(
+                "Unsupported sample format: {sample_format:?}"
+            ));
+        }
         let mut config = supported_config.config();
-        config.buffer_size = cpal::BufferSize::Fixed(512);
+        config.buffer_size = choose_buffer_size(supported_config.buffer_size(), buffer_size);
         debug!("CPAL StreamConfig: {:?}", config);
         let channels = config.channels as usize;
         if channels != 2 {
             return Err(anyhow::anyhow!("expected stereo output, got {channels}"));
         }
 
-        let volume = Arc::new(Mutex::new(1.0f32));
-        let position = Arc::new(Mutex::new(0usize));
-        let audio_buffer = Arc::new(Mutex::new(Audio::new(44100, Vec::new(), Vec::new())));
-        let playing = Arc::new(Mutex::new(false));
-
-        let shared_volume = Arc::clone(&volume);
-        let shared_position = Arc::clone(&position);
-        let audio_for_callback = Arc::clone(&audio_buffer);
-        let playing_for_callback = Arc::clone(&playing);
-
-        let stream = match sample_format {
-            cpal::SampleFormat::F32 => device.build_output_stream(
-                &config,
+        // Leave room for a few callbacks so the feeder thread can fall behind briefly
+        let ring_frames = RING_BUFFER_FRAMES.max(buffer_size as usize * 4);
+        let build = |config: &cpal::StreamConfig| {
+            let (feeder, mut source) = playback::playback_ring(
+                Arc::clone(audio_buffer),
+                Arc::clone(playback),
+                ring_frames,
+            );
+            let (preview, mut preview_source) = playback::preview_ring(PREVIEW_BUFFER_FRAMES);
+            let (monitor, mut monitor_source) = playback::preview_ring(PREVIEW_BUFFER_FRAMES);
+            let playback_for_callback = Arc::clone(playback);
+            let master_clipped_for_callback = Arc::clone(master_clipped);
+            // Gain actually applied to the output, ramps towards `volume` to avoid zipper noise
+            let mut current_volume = playback.volume();
+            let stream = device.build_output_stream(
+                config,
                 move |output: &mut [f32], _| {
                     Self::fill_output_buffer(
-                        &audio_for_callback,
-                        &shared_position,
-                        &shared_volume,
-                        &playing_for_callback,
+                        &mut source,
+                        &mut preview_source,
+                        &mut monitor_source,
+                        &playback_for_callback,
+                        &mut current_volume,
+                        &master_clipped_for_callback,
                         output,
-                        channels,
                     );
                 },
                 move |err| {
                     info!("CPAL stream error: {err}");
                 },
                 None,
-            )?,
-            other => {
-                return Err(anyhow::anyhow!("Unsupported sample format: {other:?}"));
+            )?;
+            // The feeder exits by itself once the stream (and with it the source) is dropped
+            feeder.spawn();
+            Ok::<_, cpal::BuildStreamError>((stream, preview, monitor))
+        };
+        let (stream, preview, monitor) = match build(&config) {
+            Ok(stream) => stream,
+            Err(e) if config.buffer_size != cpal::BufferSize::Default => {
+                info!(
+                    "Buffer size of {buffer_size} frames rejected ({e}), using the device default"
+                );
+                config.buffer_size = cpal::BufferSize::Default;
+                build(&config)?
             }
+            Err(e) => return Err(e.into()),
         };
         stream.play()?;
-        Ok(Self {
-            receiver,
-            audio_buffer,
-            volume,
-            tracks: HashMap::new(),
-            track_manager_sender,
-            position,
-            playing,
-            _stream: stream,
+        Ok(OutputStream {
+            stream: Some(stream),
+            preview,
+            monitor: Some(monitor),
+            sample_rate: config.sample_rate,
         })
     }
 
+    /// Reopens the output stream with a new buffer size. Playback state, position and the
+    /// mix are shared with the new stream, so playback carries on where it was. A preview tone
+    /// still sounding is cut off. If the new stream cannot be opened the current one is kept.
+    fn set_buffer_size(&mut self, buffer_size: u32) -> anyhow::Result<()> {
+        self.reopen_output_stream(buffer_size)?;
+        // The monitor feeds the old stream's monitor queue, move it over to the new one
+        if self.stop_monitor() {
+            let restarted = self.start_monitor();
+            self.broadcast_monitoring();
+            restarted?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the output stream with a new one of `buffer_size` frames per callback,
+    /// leaving the monitor's input stream alone
+    fn reopen_output_stream(&mut self, buffer_size: u32) -> anyhow::Result<()> {
+        self.output_stream = Self::open_output_stream(
+            buffer_size,
+            &self.audio_buffer,
+            &self.playback,
+            &self.clip_indicators.master,
+        )?;
+        self.buffer_size = buffer_size;
+        Ok(())
+    }
+
+    /// Get the requested number of frames per output callback
+    pub fn buffer_size(&self) -> u32 {
+        self.buffer_size
+    }
+
     /// Get the current volume level
     pub fn get_volume(&self) -> f32 {
-        *self.volume.lock().unwrap()
+        self.playback.volume()
     }
 
     /// Check if audio is currently playing
     pub fn is_playing(&self) -> bool {
-        *self.playing.lock().unwrap()
+        self.playback.is_playing()
     }
 
     /// Get the current read position in the audio buffer
     pub fn get_position(&self) -> usize {
-        *self.position.lock().unwrap()
+        self.playback.position()
     }
 
-    /// Fills the output buffer with audio data from the shared audio buffer
-    /// Applies volume control and handles playback state
-    /// This function is called within the CPAL audio callback
-    fn fill_output_buffer(
-        audio_for_callback: &Arc<Mutex<Audio>>,
-        shared_position: &Arc<Mutex<usize>>,
-        shared_volume: &Arc<Mutex<f32>>,
-        playing: &Arc<Mutex<bool>>,
-        output: &mut [f32],
-        channels: usize,
-    ) {
-        // Panicking out of a callback is bad, so handle mutex poisoning gracefully
-        let audio_lock = match audio_for_callback.lock() {
-            Ok(g) => g,
-            Err(e) => {
-                error!("audio_for_callback mutex poisoned: {e}");
-                for s in output.iter_mut() {
-                    *s = 0.0;
-                }
-                return;
-            }
-        };
-        let mut pos = match shared_position.lock() {
-            Ok(g) => g,
-            Err(e) => {
-                error!("shared_position mutex poisoned: {e}");
-                for s in output.iter_mut() {
-                    *s = 0.0;
-                }
-                return;
-            }
-        };
-        let vol = match shared_volume.lock() {
-            Ok(g) => *g,
-            Err(e) => {
-                error!("shared_volume mutex poisoned: {e}");
-                for s in output.iter_mut() {
-                    *s = 0.0;
-                }
-                return;
-            }
-        };
-        let is_playing = match playing.lock() {
-            Ok(g) => *g,
-            Err(e) => {
-                error!("playing mutex poisoned: {e}");
-                for s in output.iter_mut() {
-                    *s = 0.0;
-                }
-                return;
-            }
-        };
+    /// Check if the master output has clipped since the indicators were last cleared
+    pub fn master_clipped(&self) -> bool {
+        self.clip_indicators.master_clipped()
+    }
 
-        // Always clear the buffer first
-        for sample in output.iter_mut() {
-            *sample = 0.0;
+    /// Check if the track with the given id has clipped since the indicators were last cleared
+    pub fn track_clipped(&self, id: u32) -> bool {
+        self.clip_indicators.track_clipped(id)
+    }
+
+    /// Check if audio is currently being recorded
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Check if the input device is being monitored
+    pub fn is_monitoring(&self) -> bool {
+        self.monitor_stream.is_some()
+    }
+
+    /// Stops playback and any recording and pauses the output stream, so audio stops as soon
+    /// as the run loop ends rather than when the controller is dropped.
+    fn shutdown(&mut self) {
+        if self.stop_recording().is_some() {
+            debug!("AudioController: Discarding recording on shutdown");
         }
+        self.stop_monitor();
+        Self::stop_playback(&self.playback);
+        if let Some(stream) = &self.output_stream.stream
+            && let Err(e) = stream.pause()
+        {
+            error!("AudioController: Failed to pause output stream: {}", e);
+        }
+    }
+
+    /// Halts playback, the output callback renders silence from its next block on
+    fn stop_playback(playback: &PlaybackState) {
+        playback.set_playing(false);
+    }
+
+    /// Opens a CPAL input stream on the default input device and starts
+    /// accumulating its samples into a new `Recorder`
+    fn start_recording(&mut self) -> anyhow::Result<()> {
+        let (device, config) = Self::default_input()?;
+        let recorder = Recorder::start(config.sample_rate, config.channels as usize);
+        let recorder_for_callback = recorder.clone();
+        let stream = device.build_input_stream(
+            &config,
+            move |input: &[f32], _| {
+                recorder_for_callback.push_input(input);
+            },
+            move |err| {
+                info!("CPAL input stream error: {err}");
+            },
+            None,
+        )?;
+        stream.play()?;
+        self.recorder = Some(recorder);
+        self.input_stream = Some(stream);
+        Ok(())
+    }
+
+    /// Closes the input stream and returns the recorded audio, if a recording was running
+    fn stop_recording(&mut self) -> Option<Audio> {
+        // Dropping the stream stops the capture callback before the buffer is drained
+        self.input_stream = None;
+        self.recorder.take().map(|recorder| recorder.stop())
+    }
 
-        if !is_playing {
-            return;
+    /// The default input device and its default config, which must deliver f32 samples
+    fn default_input() -> anyhow::Result<(cpal::Device, cpal::StreamConfig)> {
+        let host = cpal::default_host();
+        let device = host
+            .default_input_device()
+            .ok_or_else(|| anyhow::anyhow!("No input device available"))?;
+        let supported_config = device.default_input_config()?;
+        debug!("Default input config: {:?}", supported_config);
+        let sample_format = supported_config.sample_format();
+        if sample_format != cpal::SampleFormat::F32 {
+            return Err(anyhow::anyhow!(
+                "Unsupported input sample format: {sample_format:?}"
+            ));
         }
+        Ok((device, supported_config.config()))
+    }
 
-        let audio = &*audio_lock;
-        let left = &audio.left;
-        let right = &audio.right;
+    /// Opens an input stream on the default input device that autotunes each block with a
+    /// `Monitor` and queues the result on the output's monitor queue. The queue goes with
+    /// the input callback, so from the second session on the output stream is reopened for
+    /// a fresh one, cutting off any preview tone.
+    fn start_monitor(&mut self) -> anyhow::Result<()> {
+        let (device, config) = Self::default_input()?;
+        if config.sample_rate != self.output_stream.sample_rate {
+            return Err(anyhow::anyhow!(
+                "Input sample rate {} differs from the output's {}",
+                config.sample_rate,
+                self.output_stream.sample_rate
+            ));
+        }
+        if self.output_stream.monitor.is_none() {
+            self.reopen_output_stream(self.buffer_size)?;
+        }
+        let mut sink = self
+            .output_stream
+            .monitor
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Output stream has no monitor queue"))?;
+        let mut monitor = Monitor::new(config.sample_rate, config.channels as usize);
+        // Reused between callbacks, grows at most to a block plus the monitor's latency
+        let mut output = Vec::with_capacity(PREVIEW_BUFFER_FRAMES);
+        let stream = device.build_input_stream(
+            &config,
+            move |input: &[f32], _| {
+                output.clear();
+                monitor.process(input, &mut output);
+                sink.play(&output);
+            },
+            move |err| {
+                info!("CPAL monitor stream error: {err}");
+            },
+            None,
+        )?;
+        stream.play()?;
+        self.monitor_stream = Some(stream);
+        Ok(())
+    }
 
-        let frames_out = output.len() / channels;
-        let remaining_frames = left.len().min(right.len()).saturating_sub(*pos);
-        let frames_to_write = frames_out.min(remaining_frames);
+    /// Closes the monitor's input stream, returns whether monitoring was on
+    fn stop_monitor(&mut self) -> bool {
+        self.monitor_stream.take().is_some()
+    }
 
-        if frames_to_write == 0 {
-            return;
+    /// Tells the TrackManager whether the input is actually being monitored
+    fn broadcast_monitoring(&self) {
+        let monitoring = self.is_monitoring();
+        if let Err(e) = self
+            .track_manager_sender
+            .try_send(track::TrackManagerCommand::SetMonitoring(monitoring))
+        {
+            error!("AudioController: Failed to send monitoring state: {}", e);
         }
+    }
 
-        let start = *pos;
-        let end = start + frames_to_write;
-        interleave_stereo(
-            &left[start..end],
-            &right[start..end],
-            &mut output[..frames_to_write * channels],
-        );
+    /// Queues a sine of `frequency` Hz lasting `duration_ms` on the output stream
+    fn play_tone(&mut self, frequency: f32, duration_ms: u32) {
+        let output = &mut self.output_stream;
+        let tone = playback::synthesize_tone(frequency, duration_ms, output.sample_rate);
+        let queued = output.preview.play(&tone);
+        if queued < tone.len() {
+            debug!(
+                queued,
+                length = tone.len(),
+                "AudioController: Preview buffer full, tone cut short"
+            );
+        }
+    }
 
-        if vol != 1.0 {
-            for s in &mut output[..frames_to_write * channels] {
-                *s *= vol;
+    /// Fills the interleaved stereo output buffer from the playback ring buffer, applies the
+    /// stereo width to it and mixes any queued preview and monitor sound on top
+    /// Applies volume control and handles playback state, and records the block's levels
+    /// The applied gain `current_volume` ramps towards the target volume instead of jumping.
+    /// This function is called within the CPAL audio callback, so it must not lock or allocate
+    fn fill_output_buffer(
+        source: &mut PlaybackSource,
+        preview: &mut PreviewSource,
+        monitor: &mut PreviewSource,
+        playback: &PlaybackState,
+        current_volume: &mut f32,
+        master_clipped: &AtomicBool,
+        output: &mut [f32],
+    ) {
+        let vol = playback.volume();
+        let frames_written = if playback.is_playing() {
+            source.read(output)
+        } else {
+            output.fill(0.0);
+            // The mix is not audible, so the gain can jump straight to the target
+            *current_volume = vol;
+            0
+        };
+        let width = playback.stereo_width();
+        if frames_written > 0 && width != 1.0 {
+            for frame in output[..frames_written * 2].chunks_exact_mut(2) {
+                let mid = (frame[0] + frame[1]) * 0.5;
+                let side = (frame[0] - frame[1]) * 0.5 * width;
+                frame[0] = mid + side;
+                frame[1] = mid - side;
             }
         }
+        let frames_written = frames_written
+            .max(preview.mix_into(output))
+            .max(monitor.mix_into(output));
+        if frames_written > 0 {
+            let written = &mut output[..frames_written * 2];
 
-        *pos += frames_to_write;
+            if vol != 1.0 || *current_volume != vol {
+                let max_step = 1.0 / VOLUME_RAMP_FRAMES;
+                for frame in written.chunks_mut(2) {
+                    let delta = (vol - *current_volume).clamp(-max_step, max_step);
+                    *current_volume += delta;
+                    for s in frame {
+                        *s *= *current_volume;
+                    }
+                }
+            }
 
-        if *pos > left.len().min(right.len()) {
-            *pos = 0;
+            if exceeds_full_scale(written) {
+                master_clipped.store(true, Ordering::Relaxed);
+            }
         }
+        playback.set_levels(OutputLevels::measure(output));
     }
 
     /// Mixes all tracks into the audio buffer, applying autotuning if desired F0 is provided.
     /// This function should be called whenever tracks are added, removed, or modified.
-    /// It locks the audio buffer mutex to update the mixed audio.
-    fn mix_tracks(&mut self) {
-        let time_start = std::time::Instant::now();
-
-        let mut mixed_audio = Audio::new(44100, Vec::new(), Vec::new());
-        for key in &self.tracks.keys().cloned().collect::<Vec<u32>>() {
-            let track = &self.tracks[key];
-            if let Some(desired_f0) = &track.desired_f0 {
-                debug!(
-                    "AudioController: Autotuning track with desired F0 of length {}",
-                    desired_f0.len()
-                );
-                match crate::audio::autotune::compute_shifted_audio(track) {
-                    Ok(shifted_audio) => {
-                        let result = mixed_audio.add_audio_at(0, &shifted_audio);
-                        if let Err(e) = result {
-                            error!("AudioController: Failed to add autotuned track: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        error!(
-                            "AudioController: Autotuning failed, adding original track: {}",
-                            e
-                        );
-                        let result = mixed_audio.add_audio_at(0, track);
-                        if let Err(e) = result {
-                            error!("AudioController: Failed to add track: {}", e);
-                        }
-                    }
-                }
-            } else {
-                debug!("AudioController: No desired F0, adding original track");
-                let result = mixed_audio.add_audio_at(0, track);
-                if let Err(e) = result {
-                    error!("AudioController: Failed to add track: {}", e);
-                }
-            }
-        }
-        *self.audio_buffer.lock().unwrap() = mixed_audio;
+    /// Only tracks whose tuning inputs changed are re-shifted, the rest come from the shift cache.
+    /// The mix is rendered on a blocking task and replaces the audio buffer once it is done,
+    /// unless another mix was requested in the meantime.
+    fn mix_tracks(&mut self) -> tokio::task::JoinHandle<()> {
+        let generation = self.mix_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let target = MixTarget {
+            audio_buffer: Arc::clone(&self.audio_buffer),
+            playback: Arc::clone(&self.playback),
+            clip_indicators: self.clip_indicators.clone(),
+            latest_generation: Arc::clone(&self.mix_generation),
+        };
+        self.mix_snapshot()
+            .spawn_render(generation, Arc::clone(&self.shift_cache), target)
+    }
 
-        let duration = time_start.elapsed();
-        debug!(
-            "AudioController: Mixing {} tracks took {:?}",
-            self.tracks.len(),
-            duration
-        );
+    /// Snapshot of the current tracks and mix settings, for rendering off the command loop
+    fn mix_snapshot(&self) -> MixSnapshot {
+        MixSnapshot {
+            tracks: self
+                .track_order
+                .iter()
+                .filter_map(|id| self.tracks.get(id).map(|track| (*id, track.clone())))
+                .collect(),
+            track_pans: self.track_pans.clone(),
+            compare_original: self.compare_original.clone(),
+            engine: self.shift_engine,
+        }
     }
 
     /// Main loop processing incoming audio commands
     pub async fn run(&mut self) {
-        while let Some(command) = self.receiver.recv().await {
-            match command {
+        // Command received while coalescing that still has to be handled
+        let mut pending = None;
+        loop {
+            let command = match pending.take() {
+                Some(command) => command,
+                None => match self.receiver.recv().await {
+                    Some(command) => command,
+                    None => break,
+                },
+            };
+            match coalesce_command(command, &mut self.receiver, &mut pending) {
                 AudioCommand::SendTrack(data, id) => {
                     debug!("AudioController: SendAudio command received");
-                    self.tracks.insert(id, data);
+                    if self.tracks.insert(id, data).is_none() {
+                        self.track_order.push(id);
+                    }
                     self.mix_tracks();
                 }
                 AudioCommand::RemoveTrack(id) => {
                     debug!("AudioController: RemoteTrack command received: {}", id);
-                    if (id as usize) < self.tracks.len() {
-                        self.tracks.remove(&id);
+                    if self.tracks.remove(&id).is_some() {
+                        self.track_order.retain(|&track_id| track_id != id);
+                        self.track_pans.remove(&id);
+                        self.clip_indicators.remove_track(id);
+                        self.compare_original.remove(&id);
                     } else {
-                        error!("AudioController: RemoteTrack id out of bounds: {}", id);
+                        error!("AudioController: RemoteTrack unknown id: {}", id);
                     }
                     self.mix_tracks();
                 }
+                AudioCommand::ReorderTracks(order) => {
+                    debug!(
+                        "AudioController: ReorderTracks command received: {:?}",
+                        order
+                    );
+                    self.track_order = reorder_track_ids(&self.track_order, &order);
+                    self.mix_tracks();
+                }
                 AudioCommand::SetReadPosition(position) => {
                     debug!(
                         "AudioController: SetReadPosition command received: {}",
                         position
                     );
-                    *self.position.lock().unwrap() = position;
+                    self.playback.seek(position);
                 }
                 AudioCommand::Play => {
                     debug!("AudioController: Play command received");
-                    if self.playing.lock().unwrap().clone() {
+                    if self.playback.is_playing() {
                         debug!("AudioController: Already playing, ignoring Play command");
                         continue;
                     }
-                    *self.playing.lock().unwrap() = true;
+                    self.playback.set_playing(true);
                 }
                 AudioCommand::Stop => {
                     debug!("AudioController: Stop command received");
-                    *self.playing.lock().unwrap() = false;
+                    self.playback.set_playing(false);
                 }
                 AudioCommand::SetVolume(volume) => {
-                    *self.volume.lock().unwrap() = volume;
+                    self.playback.set_volume(volume);
+                }
+                AudioCommand::SetPlaybackRate(rate) => {
+                    debug!(
+                        "AudioController: SetPlaybackRate command received: {}",
+                        rate
+                    );
+                    self.playback.set_playback_rate(rate);
+                }
+                AudioCommand::SetTrackPan(id, pan) => {
+                    debug!(
+                        "AudioController: SetTrackPan command received: {} {}",
+                        id, pan
+                    );
+                    self.track_pans.insert(id, pan.clamp(-1.0, 1.0));
+                    self.mix_tracks();
+                }
+                AudioCommand::SetStereoWidth(width) => {
+                    debug!(
+                        "AudioController: SetStereoWidth command received: {}",
+                        width
+                    );
+                    self.playback.set_stereo_width(width);
+                }
+                AudioCommand::SetCompareOriginal(id, enabled) => {
+                    debug!(
+                        "AudioController: SetCompareOriginal command received: {} {}",
+                        id, enabled
+                    );
+                    let changed = if enabled {
+                        self.compare_original.insert(id)
+                    } else {
+                        self.compare_original.remove(&id)
+                    };
+                    if changed {
+                        self.mix_tracks();
+                    }
+                }
+                AudioCommand::SetBufferSize(buffer_size) => {
+                    debug!(
+                        "AudioController: SetBufferSize command received: {}",
+                        buffer_size
+                    );
+                    if let Err(e) = self.set_buffer_size(buffer_size) {
+                        error!("AudioController: Failed to change buffer size: {}", e);
+                    }
+                }
+                AudioCommand::SetShiftEngine(engine) => {
+                    debug!(
+                        "AudioController: SetShiftEngine command received: {:?}",
+                        engine
+                    );
+                    if std::mem::replace(&mut self.shift_engine, engine) != engine {
+                        self.mix_tracks();
+                    }
+                }
+                AudioCommand::RenderStems(dir) => {
+                    debug!(
+                        "AudioController: RenderStems command received: {}",
+                        dir.display()
+                    );
+                    self.mix_snapshot()
+                        .spawn_render_stems(Arc::clone(&self.shift_cache), dir);
+                }
+                AudioCommand::PrerenderAll => {
+                    debug!("AudioController: PrerenderAll command received");
+                    self.mix_tracks();
+                }
+                AudioCommand::PlayTone(frequency, duration_ms) => {
+                    debug!(
+                        "AudioController: PlayTone command received: {} Hz, {} ms",
+                        frequency, duration_ms
+                    );
+                    self.play_tone(frequency, duration_ms);
+                }
+                AudioCommand::ClearClipIndicators => {
+                    debug!("AudioController: ClearClipIndicators command received");
+                    self.clip_indicators.clear();
+                }
+                AudioCommand::StartRecording => {
+                    debug!("AudioController: StartRecording command received");
+                    if self.is_recording() {
+                        debug!(
+                            "AudioController: Already recording, ignoring StartRecording command"
+                        );
+                        continue;
+                    }
+                    if let Err(e) = self.start_recording() {
+                        error!("AudioController: Failed to start recording: {}", e);
+                    }
+                }
+                AudioCommand::StopRecording => {
+                    debug!("AudioController: StopRecording command received");
+                    if let Some(audio) = self.stop_recording() {
+                        debug!(
+                            length = audio.length(),
+                            "AudioController: Recording finished"
+                        );
+                        if let Err(e) = self
+                            .track_manager_sender
+                            .try_send(track::TrackManagerCommand::AddRecording(audio))
+                        {
+                            error!("AudioController: Failed to send recording: {}", e);
+                        }
+                    }
+                }
+                AudioCommand::SetMonitorMode(enabled) => {
+                    debug!(
+                        "AudioController: SetMonitorMode command received: {}",
+                        enabled
+                    );
+                    if !enabled {
+                        self.stop_monitor();
+                    } else if self.is_monitoring() {
+                        debug!("AudioController: Already monitoring, ignoring SetMonitorMode");
+                    } else if let Err(e) = self.start_monitor() {
+                        error!("AudioController: Failed to start monitoring: {}", e);
+                    }
+                    self.broadcast_monitoring();
                 }
                 AudioCommand::ClearBuffer => {
                     debug!("AudioController: ClearBuffer command received");
                 }
                 AudioCommand::Shutdown => {
                     debug!("AudioController: Shutdown command received");
+                    self.shutdown();
                     break;
                 }
                 AudioCommand::BroadcastPosition => {
-                    let position = *self.position.lock().unwrap();
+                    let position = self.playback.position();
                     if let Err(e) = self
                         .track_manager_sender
                         .try_send(track::TrackManagerCommand::SetReadPosition(position))
@@ -327,7 +1107,871 @@ impl AudioController {
                         error!("AudioController: Failed to send playhead position: {}", e);
                     }
                 }
+                AudioCommand::BroadcastLevels => {
+                    let levels = self.playback.levels();
+                    if let Err(e) = self
+                        .track_manager_sender
+                        .try_send(track::TrackManagerCommand::SetOutputLevels(levels))
+                    {
+                        error!("AudioController: Failed to send output levels: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drains the commands already queued behind `command` while they are of the same kind and
+/// only the latest one matters (SetVolume, SetReadPosition), e.g. while a slider is dragged.
+/// Returns the latest of them. The first command of another kind is put in `pending`.
+fn coalesce_command(
+    mut command: AudioCommand,
+    receiver: &mut tokio::sync::mpsc::Receiver<AudioCommand>,
+    pending: &mut Option<AudioCommand>,
+) -> AudioCommand {
+    while matches!(
+        command,
+        AudioCommand::SetVolume(_) | AudioCommand::SetReadPosition(_)
+    ) {
+        let Ok(next) = receiver.try_recv() else {
+            break;
+        };
+        if std::mem::discriminant(&next) != std::mem::discriminant(&command) {
+            *pending = Some(next);
+            break;
+        }
+        command = next;
+    }
+    command
+}
+
+/// Buffer size to request for an output stream: `requested` frames unless the device
+/// reports a range that does not include it, in which case the device default.
+fn choose_buffer_size(supported: &cpal::SupportedBufferSize, requested: u32) -> cpal::BufferSize {
+    match *supported {
+        cpal::SupportedBufferSize::Range { min, max } if !(min..=max).contains(&requested) => {
+            debug!(requested, min, max, "Requested buffer size not supported");
+            cpal::BufferSize::Default
+        }
+        _ => cpal::BufferSize::Fixed(requested),
+    }
+}
+
+/// Applies a new track order from the TrackManager to the ids the controller knows about.
+/// Unknown ids are ignored and known ids missing from `order` keep their place at the end,
+/// so a stale order can never drop a track from the mix.
+fn reorder_track_ids(current: &[u32], order: &[u32]) -> Vec<u32> {
+    let mut reordered: Vec<u32> = order
+        .iter()
+        .copied()
+        .filter(|id| current.contains(id))
+        .collect();
+    reordered.dedup();
+    for &id in current {
+        if !reordered.contains(&id) {
+            reordered.push(id);
+        }
+    }
+    reordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::sine_wave;
+
+    /// Playback state and a ring buffer already filled from `audio`, ready for the callback
+    fn playing_source(
+        audio: Audio,
+    ) -> (Arc<PlaybackState>, playback::PlaybackFeeder, PlaybackSource) {
+        let playback = Arc::new(PlaybackState::default());
+        playback.set_playing(true);
+        let (mut feeder, source) = playback::playback_ring(
+            Arc::new(Mutex::new(audio)),
+            Arc::clone(&playback),
+            RING_BUFFER_FRAMES,
+        );
+        feeder.fill();
+        (playback, feeder, source)
+    }
+
+    /// A preview source with nothing queued
+    fn no_preview() -> PreviewSource {
+        playback::preview_ring(1).1
+    }
+
+    /// Mix settings that leave the tracks as they are
+    fn snapshot(tracks: &[(u32, Audio)]) -> MixSnapshot {
+        MixSnapshot {
+            tracks: tracks.to_vec(),
+            track_pans: HashMap::new(),
+            compare_original: HashSet::new(),
+            engine: ShiftEngine::default(),
+        }
+    }
+
+    /// A render target for `audio_buffer` whose latest requested generation is `latest`
+    fn mix_target(audio_buffer: Arc<Mutex<Audio>>, latest: u64) -> MixTarget {
+        MixTarget {
+            audio_buffer,
+            playback: Arc::new(PlaybackState::default()),
+            clip_indicators: ClipIndicators::default(),
+            latest_generation: Arc::new(AtomicU64::new(latest)),
+        }
+    }
+
+    /// A controller without an output stream, so its command loop runs on machines without
+    /// an audio device, along with its command sender and the TrackManager's receiving end
+    fn headless_controller() -> (
+        AudioController,
+        tokio::sync::mpsc::Sender<AudioCommand>,
+        tokio::sync::mpsc::Receiver<track::TrackManagerCommand>,
+    ) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+        let (track_sender, track_receiver) = tokio::sync::mpsc::channel(32);
+        let controller = AudioController {
+            receiver,
+            track_manager_sender: track_sender,
+            tracks: HashMap::new(),
+            track_order: Vec::new(),
+            track_pans: HashMap::new(),
+            shift_cache: Arc::new(Mutex::new(ShiftCache::default())),
+            shift_engine: ShiftEngine::default(),
+            mix_generation: Arc::new(AtomicU64::new(0)),
+            compare_original: HashSet::new(),
+            audio_buffer: Arc::new(Mutex::new(Audio::new(44100, Vec::new(), Vec::new()))),
+            playback: Arc::new(PlaybackState::default()),
+            clip_indicators: ClipIndicators::default(),
+            recorder: None,
+            input_stream: None,
+            monitor_stream: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            output_stream: OutputStream {
+                stream: None,
+                preview: playback::preview_ring(1).0,
+                monitor: None,
+                sample_rate: 44100,
+            },
+        };
+        (controller, sender, track_receiver)
+    }
+
+    #[test]
+    fn test_clipping_output_sets_master_indicator_until_cleared() {
+        let audio = Audio::new(44100, vec![0.5, 1.5, 0.2, 0.1], vec![0.5, 0.4, 0.2, 0.1]);
+        let (playback, mut feeder, mut source) = playing_source(audio);
+        let indicators = ClipIndicators::default();
+        let mut output = vec![0.0; 8];
+
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 1.0,
+            &indicators.master,
+            &mut output,
+        );
+        assert!(indicators.master_clipped());
+
+        indicators.clear();
+        assert!(!indicators.master_clipped());
+
+        // Turning the volume down keeps the output below full scale
+        playback.seek(0);
+        playback.set_volume(0.5);
+        source.read(&mut []);
+        feeder.fill();
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 0.5,
+            &indicators.master,
+            &mut output,
+        );
+        assert_eq!(output[2], 0.75);
+        assert!(!indicators.master_clipped());
+    }
+
+    #[test]
+    fn test_playback_rate_steps_through_mix_with_interpolation() {
+        let ramp: Vec<f32> = (0..4096).map(|i| i as f32 / 4096.0).collect();
+        let audio = Audio::new(44100, ramp.clone(), ramp.clone());
+        let (playback, _feeder, mut source) = playing_source(audio);
+        let master_clipped = AtomicBool::new(false);
+        let mut output = vec![0.0; 64 * 2];
+        let mut fill = |source: &mut PlaybackSource, output: &mut [f32]| {
+            AudioController::fill_output_buffer(
+                source,
+                &mut no_preview(),
+                &mut no_preview(),
+                &playback,
+                &mut 1.0,
+                &master_clipped,
+                output,
+            );
+        };
+
+        fill(&mut source, &mut output);
+        assert_eq!(playback.position(), 64);
+
+        // Twice as fast: every callback uses up twice as many frames of the mix
+        playback.set_playback_rate(2.0);
+        fill(&mut source, &mut output);
+        assert_eq!(playback.position(), 64 + 128);
+        fill(&mut source, &mut output);
+        assert_eq!(playback.position(), 64 + 256);
+        assert_eq!(output[..2], [ramp[192], ramp[192]]);
+        assert_eq!(output[2], ramp[194]);
+
+        // Half speed lands between frames
+        playback.set_playback_rate(0.5);
+        fill(&mut source, &mut output);
+        assert_eq!(playback.position(), 320 + 32);
+        for (i, frame) in output.chunks_exact(2).enumerate() {
+            let expected = (320.0 + i as f32 * 0.5) / 4096.0;
+            assert!((frame[0] - expected).abs() < 1e-6, "frame {}", i);
+            assert_eq!(frame[0], frame[1]);
+        }
+
+        // Out of range rates are clamped
+        playback.set_playback_rate(10.0);
+        assert_eq!(playback.playback_rate(), 4.0);
+        playback.set_playback_rate(0.0);
+        assert_eq!(playback.playback_rate(), 0.25);
+    }
+
+    #[test]
+    fn test_volume_change_ramps_instead_of_jumping() {
+        let audio = Audio::new(44100, vec![0.5; 8192], vec![0.5; 8192]);
+        let (playback, _feeder, mut source) = playing_source(audio);
+        playback.set_volume(0.0);
+        let master_clipped = AtomicBool::new(false);
+        let mut current_volume = 1.0f32;
+        let mut output = vec![0.0; 512 * 2];
+
+        let mut previous_last = 0.5;
+        for _ in 0..3 {
+            AudioController::fill_output_buffer(
+                &mut source,
+                &mut no_preview(),
+                &mut no_preview(),
+                &playback,
+                &mut current_volume,
+                &master_clipped,
+                &mut output,
+            );
+            // Gain only moves a small step from the previous buffer...
+            assert!((previous_last - output[0]).abs() < 0.01);
+            // ...and keeps falling within the buffer without reaching the target yet
+            assert!(output[output.len() - 1] < output[0]);
+            assert!(output[output.len() - 1] > 0.0);
+            previous_last = output[output.len() - 1];
+        }
+
+        for _ in 0..4 {
+            AudioController::fill_output_buffer(
+                &mut source,
+                &mut no_preview(),
+                &mut no_preview(),
+                &playback,
+                &mut current_volume,
+                &master_clipped,
+                &mut output,
+            );
+        }
+        assert_eq!(current_volume, 0.0);
+        assert!(output.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_stopped_output_is_silent_and_keeps_position() {
+        let audio = Audio::new(44100, vec![0.5; 64], vec![0.5; 64]);
+        let (playback, _feeder, mut source) = playing_source(audio);
+        playback.set_playing(false);
+        let mut output = vec![1.0; 16];
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 1.0,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        assert!(output.iter().all(|&s| s == 0.0));
+        assert_eq!(playback.position(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_seek_while_stopped_moves_playhead() {
+        let (mut controller, sender, mut track_receiver) = headless_controller();
+        let mix = Audio::new(44100, vec![0.5; 44100], vec![0.5; 44100]);
+        *controller.audio_buffer.lock().unwrap() = mix;
+        // Clicking the ruler while stopped, then ⏹ (Stop and back to the start)
+        for command in [
+            AudioCommand::SetReadPosition(22050),
+            AudioCommand::BroadcastPosition,
+            AudioCommand::Stop,
+            AudioCommand::SetReadPosition(0),
+            AudioCommand::BroadcastPosition,
+            AudioCommand::Shutdown,
+        ] {
+            sender.try_send(command).unwrap();
+        }
+        controller.run().await;
+
+        let mut positions = Vec::new();
+        while let Ok(command) = track_receiver.try_recv() {
+            if let track::TrackManagerCommand::SetReadPosition(position) = command {
+                positions.push(position);
+            }
+        }
+        assert_eq!(positions, [22050, 0]);
+        assert_eq!(controller.get_position(), 0);
+        assert!(!controller.is_playing());
+    }
+
+    #[tokio::test]
+    async fn test_monitor_mode_reports_whether_monitoring_started() {
+        let (mut controller, sender, mut track_receiver) = headless_controller();
+        sender.try_send(AudioCommand::SetMonitorMode(true)).unwrap();
+        drop(sender);
+        controller.run().await;
+
+        // Without an input device nothing is monitored, the toolbar must not show it as on
+        let mut reported = Vec::new();
+        while let Ok(command) = track_receiver.try_recv() {
+            if let track::TrackManagerCommand::SetMonitoring(monitoring) = command {
+                reported.push(monitoring);
+            }
+        }
+        assert_eq!(reported, [controller.is_monitoring()]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_recording_hands_the_take_to_the_track_manager() {
+        let (mut controller, sender, mut track_receiver) = headless_controller();
+        // Stands in for the recording StartRecording opens on the input device, the clone
+        // plays the part of its capture callback
+        let recorder = Recorder::start(44100, 2);
+        controller.recorder = Some(recorder.clone());
+        recorder.push_input(&[0.25, -0.5].repeat(512));
+        for command in [
+            // Already recording, so this keeps the running take
+            AudioCommand::StartRecording,
+            AudioCommand::StopRecording,
+            // Nothing left to hand over
+            AudioCommand::StopRecording,
+            AudioCommand::Shutdown,
+        ] {
+            sender.try_send(command).unwrap();
+        }
+        controller.run().await;
+        assert!(!controller.is_recording());
+
+        let mut takes = Vec::new();
+        while let Ok(command) = track_receiver.try_recv() {
+            if let track::TrackManagerCommand::AddRecording(audio) = command {
+                takes.push(audio);
             }
         }
+        assert_eq!(takes.len(), 1);
+        let expected = Audio::new(44100, vec![0.25; 512], vec![-0.5; 512]);
+        assert!(takes[0].approx_eq(&expected, 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_playback_and_silences_output() {
+        let (mut controller, sender, _track_receiver) = headless_controller();
+        let audio = Audio::new(44100, vec![0.5; 4096], vec![0.5; 4096]);
+        let (playback, _feeder, mut source) = playing_source(audio);
+        controller.playback = Arc::clone(&playback);
+        assert!(playback.is_playing());
+
+        // The loop ends at Shutdown, the Play queued behind it is never handled
+        sender.try_send(AudioCommand::Shutdown).unwrap();
+        sender.try_send(AudioCommand::Play).unwrap();
+        controller.run().await;
+        assert!(!playback.is_playing());
+        let mut output = vec![1.0; 256];
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 1.0,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        assert!(output.iter().all(|&s| s == 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_stereo_width_is_applied_on_playback_without_remixing() {
+        let (mut controller, sender, _track_receiver) = headless_controller();
+        sender.try_send(AudioCommand::SetStereoWidth(0.0)).unwrap();
+        sender.try_send(AudioCommand::Shutdown).unwrap();
+        controller.run().await;
+        assert_eq!(controller.playback.stereo_width(), 0.0);
+        assert_eq!(controller.mix_generation.load(Ordering::Acquire), 0);
+
+        let audio = Audio::new(44100, vec![0.5; 64], vec![0.1; 64]);
+        let (playback, _feeder, mut source) = playing_source(audio);
+        playback.set_stereo_width(0.0);
+        let mut output = vec![0.0; 2 * 32];
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 1.0,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        for frame in output.chunks_exact(2) {
+            assert!((frame[0] - 0.3).abs() < 1e-6 && (frame[1] - 0.3).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_preview_tone_plays_over_stopped_output() {
+        let audio = Audio::new(44100, vec![0.5; 64], vec![0.5; 64]);
+        let (playback, _feeder, mut source) = playing_source(audio);
+        playback.set_playing(false);
+        playback.set_volume(0.5);
+        let (mut sink, mut preview) = playback::preview_ring(PREVIEW_BUFFER_FRAMES);
+        let tone = playback::synthesize_tone(440.0, 10, 44100);
+        assert_eq!(sink.play(&tone), tone.len());
+
+        let mut output = vec![1.0; 2 * 256];
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut preview,
+            &mut no_preview(),
+            &playback,
+            &mut 1.0,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        // Only the tone is heard, at the master volume, and the mix does not move
+        for (frame, &s) in output.chunks_exact(2).zip(&tone) {
+            assert_eq!(frame, [s * 0.5, s * 0.5]);
+        }
+        assert_eq!(playback.position(), 0);
+    }
+
+    /// Levels of every SetOutputLevels the controller sent to the TrackManager
+    fn broadcast_levels(
+        track_receiver: &mut tokio::sync::mpsc::Receiver<track::TrackManagerCommand>,
+    ) -> Vec<OutputLevels> {
+        let mut levels = Vec::new();
+        while let Ok(command) = track_receiver.try_recv() {
+            if let track::TrackManagerCommand::SetOutputLevels(block) = command {
+                levels.push(block);
+            }
+        }
+        levels
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_levels_sends_levels_of_played_block() {
+        let (mut controller, sender, mut track_receiver) = headless_controller();
+        let sine: Vec<f32> = sine_wave(441.0, 44100, 4096)
+            .iter()
+            .map(|s| 0.8 * s)
+            .collect();
+        let (playback, _feeder, mut source) = playing_source(Audio::new(44100, sine.clone(), sine));
+        controller.playback = Arc::clone(&playback);
+        playback.set_volume(0.5);
+        let mut output = vec![0.0; 2 * 1000];
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 0.5,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        sender.try_send(AudioCommand::BroadcastLevels).unwrap();
+        sender.try_send(AudioCommand::Shutdown).unwrap();
+        controller.run().await;
+
+        // A 0.8 sine at half volume: 0.4 peak, 0.4 / sqrt(2) RMS
+        let levels = broadcast_levels(&mut track_receiver);
+        assert_eq!(levels.len(), 1);
+        assert!(
+            (levels[0].rms - 0.4 / 2.0f32.sqrt()).abs() < 0.005,
+            "{:?}",
+            levels
+        );
+        assert!((levels[0].peak - 0.4).abs() < 0.005, "{:?}", levels);
+
+        // Silence once stopped
+        AudioController::fill_output_buffer(
+            &mut source,
+            &mut no_preview(),
+            &mut no_preview(),
+            &playback,
+            &mut 0.5,
+            &AtomicBool::new(false),
+            &mut output,
+        );
+        sender.try_send(AudioCommand::BroadcastLevels).unwrap();
+        drop(sender);
+        controller.run().await;
+        assert_eq!(
+            broadcast_levels(&mut track_receiver),
+            [OutputLevels::default()]
+        );
+    }
+
+    #[test]
+    fn test_track_clip_indicator() {
+        let indicators = ClipIndicators::default();
+        indicators.record_track(1, &Audio::new(44100, vec![0.9], vec![-0.9]));
+        indicators.record_track(2, &Audio::new(44100, vec![0.9], vec![-1.2]));
+        assert!(!indicators.track_clipped(1));
+        assert!(indicators.track_clipped(2));
+
+        indicators.clear();
+        assert!(!indicators.track_clipped(2));
+    }
+
+    #[test]
+    fn test_shift_cache_reuses_output_for_identical_inputs() {
+        let mut audio = Audio::new(16000, vec![0.1; 4096], vec![0.1; 4096]);
+        audio.pyin_handle().write().unwrap().replace(PYINData::new(
+            vec![220.0; 8],
+            vec![true; 8],
+            vec![1.0; 8],
+        ));
+        audio.desired_f0 = Some(vec![230.0; 8]);
+        let mut cache = ShiftCache::default();
+
+        let first = cache.get_or_compute(0, &audio).unwrap();
+        let second = cache.get_or_compute(0, &audio).unwrap();
+        assert_eq!(cache.computations, 1);
+        assert_eq!(first.left(), second.left());
+
+        // An edit to the desired F0 invalidates it
+        audio.desired_f0.as_mut().unwrap()[3] = 240.0;
+        cache.get_or_compute(0, &audio).unwrap();
+        assert_eq!(cache.computations, 2);
+
+        // So does excluding frames from tuning
+        audio.tune_mask = Some(vec![true, false]);
+        cache.get_or_compute(0, &audio).unwrap();
+        assert_eq!(cache.computations, 3);
+
+        // And switching engines
+        cache.engine = ShiftEngine::PhaseVocoder;
+        let vocoded = cache.get_or_compute(0, &audio).unwrap();
+        assert_eq!(cache.computations, 4);
+        assert_eq!(vocoded.length(), audio.length());
+
+        // And editing the samples without changing the length
+        audio.noise_gate(-20.0, 5.0, 50.0);
+        assert_eq!(audio.length(), 4096);
+        cache.get_or_compute(0, &audio).unwrap();
+        assert_eq!(cache.computations, 5);
+    }
+
+    #[test]
+    fn test_compare_original_renders_unprocessed_audio() {
+        let samples = crate::audio::sine_wave(220.0, 16000, 4096);
+        let mut audio = Audio::new(16000, samples.clone(), samples.clone());
+        audio.pyin_handle().write().unwrap().replace(PYINData::new(
+            vec![220.0; 8],
+            vec![true; 8],
+            vec![1.0; 8],
+        ));
+        audio.desired_f0 = Some(vec![260.0; 8]);
+        let mut cache = ShiftCache::default();
+
+        let tuned = render_track(&mut cache, 0, &audio, false);
+        assert_ne!(tuned.left(), &samples[..]);
+
+        let original = render_track(&mut cache, 0, &audio, true);
+        assert_eq!(original.left(), &samples[..]);
+        // The stored desired F0 is untouched
+        assert_eq!(audio.desired_f0, Some(vec![260.0; 8]));
+
+        let tuned_again = render_track(&mut cache, 0, &audio, false);
+        assert_eq!(tuned_again.left(), tuned.left());
+    }
+
+    #[test]
+    fn test_remix_only_reshifts_the_updated_track() {
+        let mut tracks: Vec<(u32, Audio)> = (0..3)
+            .map(|id| {
+                let mut audio = Audio::new(44100, vec![0.1; 4096], vec![0.1; 4096]);
+                audio.pyin_handle().write().unwrap().replace(PYINData::new(
+                    vec![220.0; 8],
+                    vec![true; 8],
+                    vec![1.0; 8],
+                ));
+                audio.desired_f0 = Some(vec![230.0 + id as f32 * 10.0; 8]);
+                (id, audio)
+            })
+            .collect();
+        let mut cache = ShiftCache::default();
+        let mix = |tracks: &[(u32, Audio)], cache: &mut ShiftCache| {
+            render_mix(
+                tracks.iter().map(|(id, track)| (*id, track)),
+                &HashMap::new(),
+                &HashSet::new(),
+                cache,
+                &ClipIndicators::default(),
+            )
+        };
+
+        let first = mix(&tracks, &mut cache);
+        assert_eq!(cache.computations, 3);
+
+        // As after a SendTrack for track 1 with an edited desired F0
+        tracks[1].1.desired_f0 = Some(vec![250.0; 8]);
+        let remixed = mix(&tracks, &mut cache);
+        assert_eq!(cache.computations, 4);
+        assert_ne!(remixed.left(), first.left());
+
+        // Resending an unchanged track recomputes nothing
+        mix(&tracks, &mut cache);
+        assert_eq!(cache.computations, 4);
+    }
+
+    #[test]
+    fn test_reorder_track_ids_keeps_every_known_track() {
+        assert_eq!(reorder_track_ids(&[0, 1, 2], &[2, 0, 1]), vec![2, 0, 1]);
+        // Unknown ids are dropped, missing ones appended
+        assert_eq!(reorder_track_ids(&[0, 1, 2], &[7, 1]), vec![1, 0, 2]);
+        assert_eq!(reorder_track_ids(&[], &[1, 2]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_coalesce_command_keeps_latest_volume() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+        // Fills the channel up to its capacity
+        let volumes = COMMAND_CHANNEL_CAPACITY - 3;
+        for i in 0..volumes {
+            sender
+                .try_send(AudioCommand::SetVolume(i as f32 / 100.0))
+                .unwrap();
+        }
+        sender.try_send(AudioCommand::SetReadPosition(10)).unwrap();
+        sender.try_send(AudioCommand::SetReadPosition(20)).unwrap();
+        sender.try_send(AudioCommand::Play).unwrap();
+        assert_eq!(sender.capacity(), 0);
+
+        let mut pending = None;
+        let first = receiver.try_recv().unwrap();
+        let command = coalesce_command(first, &mut receiver, &mut pending);
+        let last_volume = (volumes - 1) as f32 / 100.0;
+        assert!(matches!(command, AudioCommand::SetVolume(v) if v == last_volume));
+        // The next kind of command is kept, not dropped
+        let command = coalesce_command(pending.take().unwrap(), &mut receiver, &mut pending);
+        assert!(matches!(command, AudioCommand::SetReadPosition(20)));
+        assert!(matches!(pending.take(), Some(AudioCommand::Play)));
+        // Nothing left queued behind
+        assert!(receiver.try_recv().is_err());
+
+        // Other commands are never merged
+        sender.try_send(AudioCommand::Play).unwrap();
+        let command = coalesce_command(AudioCommand::Play, &mut receiver, &mut pending);
+        assert!(matches!(command, AudioCommand::Play));
+        assert!(pending.is_none());
+        assert!(matches!(receiver.try_recv(), Ok(AudioCommand::Play)));
+    }
+
+    #[tokio::test]
+    async fn test_burst_over_channel_capacity_applies_latest_volume_and_position() {
+        let (mut controller, sender, _track_receiver) = headless_controller();
+        let playback = Arc::clone(&controller.playback);
+        let handle = tokio::spawn(async move {
+            controller.run().await;
+        });
+
+        // A slider drag and a scrub queue more commands than the channel holds, the sender
+        // waits for the controller to drain them instead of failing
+        let burst = 3 * COMMAND_CHANNEL_CAPACITY;
+        for i in 1..=burst {
+            let volume = i as f32 / burst as f32;
+            assert!(sender.send(AudioCommand::SetVolume(volume)).await.is_ok());
+        }
+        for position in 1..=burst {
+            assert!(
+                sender
+                    .send(AudioCommand::SetReadPosition(position))
+                    .await
+                    .is_ok()
+            );
+        }
+        assert!(sender.send(AudioCommand::Shutdown).await.is_ok());
+        handle.await.unwrap();
+
+        assert_eq!(playback.volume(), 1.0);
+        assert_eq!(playback.position(), burst);
+    }
+
+    #[test]
+    fn test_render_stems_writes_one_wav_per_track() {
+        let dir = std::env::temp_dir().join(format!("autotune_stems_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let tone: Vec<f32> = sine_wave(220.0, 44100, 4096)
+            .iter()
+            .map(|s| 0.3 * s)
+            .collect();
+        let tracks = [
+            (3, Audio::new(44100, tone.clone(), tone.clone())),
+            (7, Audio::new(44100, vec![0.25; 2048], vec![0.25; 2048])),
+        ];
+        // Track 7 hard right
+        let track_pans = HashMap::from([(7, 1.0)]);
+
+        let paths = render_stems(
+            tracks.iter().map(|(id, track)| (*id, track)),
+            &track_pans,
+            &mut ShiftCache::default(),
+            &dir,
+        )
+        .unwrap();
+        assert_eq!(paths, [dir.join("track_3.wav"), dir.join("track_7.wav")]);
+        let stems: Vec<Audio> = paths
+            .iter()
+            .map(|path| AudioFileData::load(path).unwrap().to_audio())
+            .collect();
+        assert_eq!(stems[0].length(), 4096);
+        assert!(stems[0].approx_eq(&tracks[0].1, 1e-3));
+        // Each stem only holds its own track, with its pan applied
+        assert_eq!(stems[1].length(), 2048);
+        assert!(stems[1].left().iter().all(|&s| s.abs() < 1e-3));
+        assert!(stems[1].right().iter().all(|&s| s > 0.2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prerender_all_swaps_in_autotuned_mix() {
+        // The mix is built at 44.1kHz, tracks at other rates are skipped
+        let tone = |freq: f32| -> Vec<f32> {
+            sine_wave(freq, 44100, 8192)
+                .iter()
+                .map(|s| 0.3 * s)
+                .collect()
+        };
+        let mut tracks = Vec::new();
+        for (id, (freq, target)) in [(220.0, 260.0), (330.0, 300.0)].into_iter().enumerate() {
+            let mut audio = Audio::new(44100, tone(freq), tone(freq));
+            audio.pyin_handle().write().unwrap().replace(PYINData::new(
+                vec![freq; 32],
+                vec![true; 32],
+                vec![1.0; 32],
+            ));
+            audio.desired_f0 = Some(vec![target; 32]);
+            tracks.push((id as u32, audio));
+        }
+
+        let old_mix = Audio::new(44100, vec![0.25; 16], vec![0.25; 16]);
+        let audio_buffer = Arc::new(Mutex::new(old_mix.clone()));
+        let shift_cache = Arc::new(Mutex::new(ShiftCache::default()));
+        let target = mix_target(Arc::clone(&audio_buffer), 1);
+        let handle = snapshot(&tracks).spawn_render(1, Arc::clone(&shift_cache), target);
+        // Until the render lands the buffer holds either the old or the new mix, never a mix
+        // in progress
+        let during = audio_buffer.lock().unwrap().left().to_vec();
+        assert!(during == old_mix.left() || during.len() >= 8192);
+        handle.await.unwrap();
+
+        // Every track went through PSOLA and the buffer matches a mix of the tuned tracks
+        assert_eq!(shift_cache.lock().unwrap().computations, 2);
+        let expected = render_mix(
+            tracks.iter().map(|(id, track)| (*id, track)),
+            &HashMap::new(),
+            &HashSet::new(),
+            &mut ShiftCache::default(),
+            &ClipIndicators::default(),
+        );
+        let untuned = render_mix(
+            tracks.iter().map(|(id, track)| (*id, track)),
+            &HashMap::new(),
+            &tracks.iter().map(|(id, _)| *id).collect(),
+            &mut ShiftCache::default(),
+            &ClipIndicators::default(),
+        );
+        let buffer = audio_buffer.lock().unwrap();
+        assert!(!buffer.left().is_empty());
+        assert_eq!(buffer.left(), expected.left());
+        assert_ne!(buffer.left(), untuned.left());
+    }
+
+    #[test]
+    fn test_render_mix_skips_tracks_at_another_sample_rate() {
+        let tracks = [
+            (0, Audio::new(44100, vec![0.25; 64], vec![0.25; 64])),
+            (1, Audio::new(22050, vec![0.5; 32], vec![0.5; 32])),
+            (2, Audio::new(44100, vec![0.125; 64], vec![0.125; 64])),
+        ];
+        let mix = render_mix(
+            tracks.iter().map(|(id, track)| (*id, track)),
+            &HashMap::new(),
+            &HashSet::new(),
+            &mut ShiftCache::default(),
+            &ClipIndicators::default(),
+        );
+        assert_eq!(mix.sample_rate(), 44100);
+        assert_eq!(mix.left(), &[0.375; 64]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_mix_render_is_not_swapped_in() {
+        let tracks = vec![(0, Audio::new(44100, vec![0.5; 64], vec![0.5; 64]))];
+        let old_mix = Audio::new(44100, vec![0.25; 16], vec![0.25; 16]);
+        let audio_buffer = Arc::new(Mutex::new(old_mix.clone()));
+        let shift_cache = Arc::new(Mutex::new(ShiftCache::default()));
+
+        // Generation 2 was requested after this render of generation 1
+        let target = mix_target(Arc::clone(&audio_buffer), 2);
+        snapshot(&tracks)
+            .spawn_render(1, Arc::clone(&shift_cache), target.clone())
+            .await
+            .unwrap();
+        assert_eq!(audio_buffer.lock().unwrap().left(), old_mix.left());
+
+        snapshot(&tracks)
+            .spawn_render(2, Arc::clone(&shift_cache), target)
+            .await
+            .unwrap();
+        assert_eq!(audio_buffer.lock().unwrap().left(), &[0.5; 64]);
+    }
+
+    #[test]
+    fn test_choose_buffer_size_falls_back_outside_supported_range() {
+        let range = cpal::SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert_eq!(
+            choose_buffer_size(&range, 256),
+            cpal::BufferSize::Fixed(256)
+        );
+        assert_eq!(choose_buffer_size(&range, 16), cpal::BufferSize::Default);
+        assert_eq!(choose_buffer_size(&range, 8192), cpal::BufferSize::Default);
+        // Devices that don't report a range get the requested size, with a fallback if
+        // opening the stream then fails
+        assert_eq!(
+            choose_buffer_size(&cpal::SupportedBufferSize::Unknown, 128),
+            cpal::BufferSize::Fixed(128)
+        );
+    }
+
+    #[test]
+    fn test_with_buffer_size_opens_stream_or_reports_missing_device() {
+        let (_sender, receiver) = tokio::sync::mpsc::channel(1);
+        let (track_sender, _track_receiver) = tokio::sync::mpsc::channel(1);
+        // Machines without an audio device (e.g. CI) can't open a stream at all, but an odd
+        // buffer size must never be the reason construction fails
+        match AudioController::with_buffer_size(receiver, track_sender, 300) {
+            Ok(controller) => assert_eq!(controller.buffer_size(), 300),
+            Err(e) => assert!(!e.to_string().contains("buffer"), "{}", e),
+        }
     }
 }
@@ -0,0 +1,230 @@
+use crate::audio::autotune::pyin::{self, PYINData, PyinConfig};
+use crate::audio::autotune::{
+    AUTOCORRELATION_VOICING_THRESHOLD, FRAME_LENGTH, HOP_LENGTH, MAX_F0, MIN_F0, PYIN_SILENCE_RATIO,
+};
+use tracing::debug;
+
+/// A pitch-tracking algorithm that turns a mono signal into a per-frame f0 track.
+/// Implementations must be shareable across threads since stereo audio is analyzed
+/// one channel per thread.
+pub trait PitchDetector: Send + Sync {
+    fn detect(&self, signal: &[f32], sample_rate: u32) -> PYINData;
+
+    /// Length of one analysis frame. Signals shorter than this yield no frames, so callers
+    /// may skip running the detector on them.
+    fn frame_length(&self) -> usize {
+        0
+    }
+}
+
+/// The probabilistic YIN detector from `pyin`.
+#[derive(Debug, Clone, Default)]
+pub struct PyinDetector {
+    pub config: PyinConfig,
+}
+
+impl PyinDetector {
+    pub fn new(config: PyinConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl PitchDetector for PyinDetector {
+    fn detect(&self, signal: &[f32], sample_rate: u32) -> PYINData {
+        pyin::pyin_with_config(signal, sample_rate, &self.config)
+    }
+
+    fn frame_length(&self) -> usize {
+        self.config.frame_length
+    }
+}
+
+/// Picks the lag with the strongest normalized autocorrelation in each frame.
+/// Faster and less robust than PYIN (no probabilistic tracking, more octave errors), meant
+/// for quick previews. The voiced probability is the height of the chosen peak.
+#[derive(Debug, Clone)]
+pub struct AutocorrelationDetector {
+    pub frame_length: usize,
+    pub hop_length: usize,
+    pub fmin: f32,
+    pub fmax: f32,
+    /// Peaks at or below this normalized correlation are reported as unvoiced
+    pub voicing_threshold: f32,
+    /// Frames quieter than this fraction of the whole signal's RMS are treated as silence
+    pub silence_ratio: f32,
+}
+
+impl Default for AutocorrelationDetector {
+    fn default() -> Self {
+        Self {
+            frame_length: FRAME_LENGTH,
+            hop_length: HOP_LENGTH,
+            fmin: MIN_F0,
+            fmax: MAX_F0,
+            voicing_threshold: AUTOCORRELATION_VOICING_THRESHOLD,
+            silence_ratio: PYIN_SILENCE_RATIO,
+        }
+    }
+}
+
+/// Autocorrelation of `frame` at `lag`, normalized by the energy of both overlapping
+/// parts so a perfectly periodic frame scores 1.0 regardless of the lag.
+fn normalized_autocorrelation(frame: &[f32], lag: usize) -> f32 {
+    let (head, tail) = (&frame[..frame.len() - lag], &frame[lag..]);
+    let mut cross = 0.0;
+    let mut head_energy = 0.0;
+    let mut tail_energy = 0.0;
+    for (&a, &b) in head.iter().zip(tail) {
+        cross += a * b;
+        head_energy += a * a;
+        tail_energy += b * b;
+    }
+    let norm = (head_energy * tail_energy).sqrt();
+    if norm > 0.0 { cross / norm } else { 0.0 }
+}
+
+impl AutocorrelationDetector {
+    /// Estimates the period of one frame, returning (f0, peak height).
+    fn detect_frame(
+        &self,
+        frame: &[f32],
+        min_lag: usize,
+        max_lag: usize,
+        sample_rate: u32,
+    ) -> (f32, f32) {
+        let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+        let frame: Vec<f32> = frame.iter().map(|x| x - mean).collect();
+        let r: Vec<f32> = (min_lag - 1..=max_lag + 1)
+            .map(|lag| normalized_autocorrelation(&frame, lag))
+            .collect();
+
+        // Take the first local maximum close to the global one, so multiples of the period
+        // (which correlate just as well) don't win
+        let global_max = r[1..r.len() - 1].iter().copied().fold(0.0, f32::max);
+        let Some(peak) = (1..r.len() - 1)
+            .find(|&i| r[i] >= 0.9 * global_max && r[i] >= r[i - 1] && r[i] >= r[i + 1])
+        else {
+            return (0.0, 0.0);
+        };
+
+        // Parabolic interpolation around the peak for sub-sample precision
+        let (y0, y1, y2) = (r[peak - 1], r[peak], r[peak + 1]);
+        let denom = y0 - 2.0 * y1 + y2;
+        let offset = if denom.abs() < 1e-9 {
+            0.0
+        } else {
+            0.5 * (y0 - y2) / denom
+        };
+        let lag = (min_lag - 1 + peak) as f32 + offset;
+        (sample_rate as f32 / lag, y1.clamp(0.0, 1.0))
+    }
+}
+
+impl PitchDetector for AutocorrelationDetector {
+    fn detect(&self, signal: &[f32], sample_rate: u32) -> PYINData {
+        let min_lag = ((sample_rate as f32 / self.fmax).floor() as usize).max(1);
+        let max_lag = (sample_rate as f32 / self.fmin).ceil() as usize;
+        if signal.len() < self.frame_length
+            || max_lag + 1 >= self.frame_length
+            || max_lag <= min_lag
+        {
+            return PYINData::new(Vec::new(), Vec::new(), Vec::new());
+        }
+
+        let n_frames = (signal.len() - self.frame_length) / self.hop_length + 1;
+        debug!(
+            n_frames,
+            min_lag, max_lag, "Running autocorrelation pitch detection"
+        );
+        let global_rms = (signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32).sqrt();
+        let silence_threshold = global_rms * self.silence_ratio + 1e-6;
+
+        let mut f0 = vec![0.0; n_frames];
+        let mut voiced_flag = vec![false; n_frames];
+        let mut voiced_prob = vec![0.0; n_frames];
+        for i in 0..n_frames {
+            let start = i * self.hop_length;
+            let frame = &signal[start..start + self.frame_length];
+            let rms = (frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32).sqrt();
+            if rms < silence_threshold {
+                continue;
+            }
+            let (frequency, peak) = self.detect_frame(frame, min_lag, max_lag, sample_rate);
+            if peak > self.voicing_threshold {
+                f0[i] = frequency;
+                voiced_flag[i] = true;
+            }
+            voiced_prob[i] = peak;
+        }
+        PYINData::new(f0, voiced_flag, voiced_prob)
+    }
+
+    fn frame_length(&self) -> usize {
+        self.frame_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::sine_wave;
+
+    #[test]
+    fn test_pyin_detector_matches_pyin_with_config() {
+        let sr = 16000;
+        let signal = sine_wave(220.0, sr, sr as usize / 2);
+        let config = PyinConfig {
+            fmax: 500.0,
+            ..Default::default()
+        };
+
+        let detector: &dyn PitchDetector = &PyinDetector::new(config.clone());
+        assert_eq!(
+            detector.detect(&signal, sr),
+            pyin::pyin_with_config(&signal, sr, &config)
+        );
+    }
+
+    #[test]
+    fn test_autocorrelation_detects_clean_sine_pitch() {
+        let sr = 16000;
+        let f0_hz = 220.0;
+        let signal = sine_wave(f0_hz, sr, sr as usize / 2);
+        let detector = AutocorrelationDetector {
+            fmax: 500.0,
+            ..Default::default()
+        };
+
+        let result = detector.detect(&signal, sr);
+        assert!(!result.f0().is_empty());
+        assert_eq!(result.f0().len(), result.voiced_flag().len());
+        assert_eq!(result.f0().len(), result.voiced_prob().len());
+        assert!(result.voiced_flag().iter().all(|&v| v));
+        for (&f0_est, &prob) in result.f0().iter().zip(result.voiced_prob()) {
+            assert!((f0_est - f0_hz).abs() < 10.0, "f0 = {}", f0_est);
+            assert!(prob > 0.9);
+        }
+    }
+
+    #[test]
+    fn test_autocorrelation_treats_silence_and_noise_as_unvoiced() {
+        let sr = 16000;
+        let detector = AutocorrelationDetector::default();
+        let silence = detector.detect(&vec![0.0; sr as usize / 2], sr);
+        assert!(!silence.f0().is_empty());
+        assert!(silence.voiced_flag().iter().all(|&v| !v));
+        assert!(silence.f0().iter().all(|&f| f == 0.0));
+
+        // Deterministic white-ish noise from a linear congruential generator
+        let mut state = 12345u32;
+        let noise: Vec<f32> = (0..sr as usize / 2)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as f32 / 32768.0 - 1.0
+            })
+            .collect();
+        let result = detector.detect(&noise, sr);
+        let voiced = result.voiced_flag().iter().filter(|&&v| v).count();
+        assert!(voiced * 10 < result.f0().len(), "{} voiced frames", voiced);
+    }
+}
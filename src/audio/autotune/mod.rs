@@ -1,7 +1,18 @@
 use crate::audio::Audio;
+use crate::audio::autotune::pyin::PYINData;
+use crate::audio::scales::{
+    Key, Note, Scale, cents_to_hz, frequency_to_midi_note, hz_to_cents, midi_note_name,
+    midi_note_to_frequency_with_reference,
+};
+use std::fmt;
+use tracing::debug;
 
+pub mod detector;
+pub mod phase_vocoder;
 pub mod psola;
 pub mod pyin;
+pub mod pyin_cache;
+pub mod spectral;
 
 // Constants for PYIN and PSOLA
 pub const FRAME_LENGTH: usize = 2048;
@@ -12,48 +23,1212 @@ pub const PYIN_THRESHOLD: f32 = 0.1;
 pub const PYIN_SIGMA: f32 = 0.2;
 pub const MIN_F0: f32 = 50.0;
 pub const MAX_F0: f32 = 2000.0;
+pub const PYIN_SILENCE_RATIO: f32 = 0.02;
+pub const PYIN_HIGHPASS_CUTOFF: f32 = 40.0;
+/// Default bounds on the ratio of a candidate's f0 to the previous frame's
+pub const PYIN_OCTAVE_GUARD: (f32, f32) = (0.7, 1.5);
+
+// Constants for the autocorrelation detector
+pub const AUTOCORRELATION_VOICING_THRESHOLD: f32 = 0.5;
+
+// Constants for PYIN octave correction
+pub const OCTAVE_CORRECTION_MAX_RUN: usize = 8;
+pub const OCTAVE_CORRECTION_CONTEXT: usize = 16;
+pub const OCTAVE_CORRECTION_TOLERANCE: f32 = 0.1;
+
+/// Algorithm used to shift a track to its desired F0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShiftEngine {
+    /// Pitch-synchronous overlap-add. Cleanest on monophonic voices
+    #[default]
+    Psola,
+    /// Phase vocoder. Copes with chords and noisy consonants, softens transients
+    PhaseVocoder,
+}
+
+/// Parses the names printed by `Display`: "psola" or "phase_vocoder".
+impl std::str::FromStr for ShiftEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "psola" => Ok(ShiftEngine::Psola),
+            "phase_vocoder" => Ok(ShiftEngine::PhaseVocoder),
+            _ => Err(format!(
+                "Unknown engine \"{}\", expected psola or phase_vocoder",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ShiftEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShiftEngine::Psola => write!(f, "psola"),
+            ShiftEngine::PhaseVocoder => write!(f, "phase_vocoder"),
+        }
+    }
+}
+
+/// How far above the lead a harmony voice sings, in steps of the key's scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HarmonyInterval {
+    /// Two scale steps up, e.g. C to E in C major
+    Third,
+    /// Four scale steps up, e.g. C to G in C major
+    Fifth,
+}
+
+impl HarmonyInterval {
+    fn scale_steps(self) -> usize {
+        match self {
+            HarmonyInterval::Third => 2,
+            HarmonyInterval::Fifth => 4,
+        }
+    }
+}
+
+/// Errors returned by the autotune pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutotuneError {
+    /// PYIN analysis has not been run, or has not finished yet
+    MissingPyin,
+    /// No desired F0 track has been set on the audio
+    MissingDesiredF0,
+    /// The desired F0 track cannot be aligned to the PYIN frames (e.g. it is empty)
+    LengthMismatch { expected: usize, actual: usize },
+    /// Frames of `frame_length` samples can't be taken every `hop_length` samples
+    InvalidFrameHop {
+        frame_length: usize,
+        hop_length: usize,
+    },
+}
+
+impl fmt::Display for AutotuneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutotuneError::MissingPyin => write!(f, "No PYIN data available for audio"),
+            AutotuneError::MissingDesiredF0 => {
+                write!(f, "No desired F0 data available for audio")
+            }
+            AutotuneError::LengthMismatch { expected, actual } => write!(
+                f,
+                "Desired F0 has {} frames but PYIN data has {}",
+                actual, expected
+            ),
+            AutotuneError::InvalidFrameHop {
+                frame_length,
+                hop_length,
+            } => write!(
+                f,
+                "Invalid frame length {} with hop length {}, need 0 < hop <= frame",
+                frame_length, hop_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AutotuneError {}
+
+/// Checks that frames of `frame_length` samples taken every `hop_length` samples cover the
+/// signal without gaps: both must be non-zero and the hop no longer than the frame.
+pub fn validate_frame_hop(frame_length: usize, hop_length: usize) -> Result<(), AutotuneError> {
+    if frame_length == 0 || hop_length == 0 || hop_length > frame_length {
+        return Err(AutotuneError::InvalidFrameHop {
+            frame_length,
+            hop_length,
+        });
+    }
+    Ok(())
+}
+
+/// Snaps every voiced frequency in `f0` to the closest frequency of `key`.
+/// Unvoiced frames (f0 <= 0) stay at 0.0.
+pub fn snap_to_scale(f0: &[f32], key: Key) -> Vec<f32> {
+    let voiced: Vec<bool> = f0.iter().map(|&freq| freq > 0.0).collect();
+    snap_voiced_to_scale(f0, &voiced, key, false)
+}
+
+/// Like `snap_to_scale`, with the voicing taken from `voiced_flag` (e.g. PYIN's) instead
+/// of the sign of `f0`. Frames that aren't voiced become 0.0, or with
+/// `passthrough_unvoiced` keep their original f0 so they are played unshifted. Frames
+/// past the end of `voiced_flag` count as unvoiced.
+pub fn snap_voiced_to_scale(
+    f0: &[f32],
+    voiced_flag: &[bool],
+    key: Key,
+    passthrough_unvoiced: bool,
+) -> Vec<f32> {
+    let is_voiced = |i: usize| f0[i] > 0.0 && voiced_flag.get(i).copied().unwrap_or(false);
+    // Cover the detector's range, widened to any pitch outside it so nothing gets
+    // clamped to the nearest boundary note
+    let (low, high) = (0..f0.len())
+        .filter(|&i| is_voiced(i))
+        .fold((MIN_F0, MAX_F0), |(low, high), i| {
+            (low.min(f0[i]), high.max(f0[i]))
+        });
+    let scale_frequencies = key.scale_frequencies_in_range(low, high);
+    f0.iter()
+        .enumerate()
+        .map(|(i, &freq)| {
+            if !is_voiced(i) {
+                return if passthrough_unvoiced {
+                    freq.max(0.0)
+                } else {
+                    0.0
+                };
+            }
+            let mut closest_freq = scale_frequencies[0];
+            let mut min_diff = (freq - closest_freq).abs();
+            for &scale_freq in &scale_frequencies[1..] {
+                let diff = (freq - scale_freq).abs();
+                if diff < min_diff {
+                    min_diff = diff;
+                    closest_freq = scale_freq;
+                }
+            }
+            closest_freq
+        })
+        .collect()
+}
+
+/// Glides the detected pitch `f0` towards `target` instead of snapping to it, like the
+/// "retune speed" knob of a hardware autotune. Each frame the correction (in cents) moves
+/// `speed` of the way towards the full correction: 1.0 snaps instantly, 0.0 never corrects.
+/// Unvoiced frames stay at 0.0 and restart the glide; frames without a target keep their
+/// detected pitch.
+pub fn retune(f0: &[f32], target: &[f32], speed: f32) -> Vec<f32> {
+    let speed = speed.clamp(0.0, 1.0);
+    let mut correction = 0.0;
+    f0.iter()
+        .zip(target)
+        .map(|(&detected, &target)| {
+            if detected <= 0.0 {
+                correction = 0.0;
+                return 0.0;
+            }
+            if target <= 0.0 {
+                correction = 0.0;
+                return detected;
+            }
+            correction += speed * (hz_to_cents(target, detected) - correction);
+            cents_to_hz(correction, detected)
+        })
+        .collect()
+}
+
+/// Stretches or squashes an F0 track to `n_frames` frames with linear interpolation.
+/// Frames next to an unvoiced (<= 0) frame take the nearest value instead, so voiced and
+/// unvoiced regions are never blended into a glide from 0 Hz.
+pub fn resample_f0_track(f0: &[f32], n_frames: usize) -> Vec<f32> {
+    if f0.is_empty() {
+        return vec![0.0; n_frames];
+    }
+    if f0.len() == n_frames {
+        return f0.to_vec();
+    }
+    if f0.len() == 1 || n_frames == 1 {
+        return vec![f0[0]; n_frames];
+    }
+    let scale = (f0.len() - 1) as f32 / (n_frames - 1) as f32;
+    (0..n_frames)
+        .map(|i| {
+            let pos = i as f32 * scale;
+            let lower = (pos.floor() as usize).min(f0.len() - 1);
+            let upper = (lower + 1).min(f0.len() - 1);
+            let frac = pos - lower as f32;
+            let (a, b) = (f0[lower], f0[upper]);
+            if a <= 0.0 || b <= 0.0 {
+                if frac < 0.5 { a } else { b }
+            } else {
+                a + (b - a) * frac
+            }
+        })
+        .collect()
+}
+
+/// Whether going from `previous` to `current` starts a new note: a voicing change, or a
+/// jump of at least half a semitone.
+fn is_note_change(previous: f32, current: f32) -> bool {
+    match (previous > 0.0, current > 0.0) {
+        (true, true) => hz_to_cents(current, previous).abs() >= 50.0,
+        (was_voiced, is_voiced) => was_voiced != is_voiced,
+    }
+}
+
+/// Desired F0 that leaves the pitch as detected: the detected f0 on voiced frames,
+/// 0.0 (unvoiced) elsewhere.
+pub fn passthrough_f0(pyin: &PYINData) -> Vec<f32> {
+    pyin.f0()
+        .iter()
+        .zip(pyin.voiced_flag())
+        .map(|(&f0, &voiced)| if voiced { f0.max(0.0) } else { 0.0 })
+        .collect()
+}
+
+/// Desired F0 with every frame unvoiced (0.0).
+pub fn cleared_f0(pyin: &PYINData) -> Vec<f32> {
+    vec![0.0; pyin.f0().len()]
+}
+
+/// Desired F0 holding every voiced frame at `frequency`, unvoiced frames stay at 0.0.
+pub fn flat_f0(pyin: &PYINData, frequency: f32) -> Vec<f32> {
+    pyin.voiced_flag()
+        .iter()
+        .map(|&voiced| if voiced { frequency } else { 0.0 })
+        .collect()
+}
+
+/// Number of pitch frames (one every `hop_length` samples) in one beat at `bpm`.
+pub fn frames_per_beat(bpm: f32, sample_rate: u32, hop_length: usize) -> f32 {
+    60.0 / bpm * sample_rate as f32 / hop_length as f32
+}
+
+/// Moves every note change in `desired_f0` to the nearest grid line, with the grid dividing
+/// each beat of `frames_per_beat` frames into `subdivisions` steps (e.g. 2 for 1/8 notes in
+/// 4/4). Frames between the old and new change point take the value of the note that now
+/// covers them. A note is never shortened below one frame, so neighbouring changes keep
+/// their order.
+pub fn quantize_f0_onsets(desired_f0: &mut [f32], frames_per_beat: f32, subdivisions: u32) {
+    let step = frames_per_beat / subdivisions.max(1) as f32;
+    if step <= 0.0 || !step.is_finite() || desired_f0.len() < 2 {
+        return;
+    }
+    let original = desired_f0.to_vec();
+    let changes: Vec<usize> = (1..original.len())
+        .filter(|&i| is_note_change(original[i - 1], original[i]))
+        .collect();
+    for (n, &change) in changes.iter().enumerate() {
+        let lower = n.checked_sub(1).map_or(1, |previous| changes[previous] + 1);
+        let upper = changes
+            .get(n + 1)
+            .map_or(original.len() - 1, |&next| next - 1);
+        let target = ((change as f32 / step).round() * step).round() as usize;
+        let target = target.clamp(lower, upper.max(lower));
+        if target < change {
+            desired_f0[target..change].fill(original[change]);
+        } else if target > change {
+            desired_f0[change..target].fill(original[change - 1]);
+        }
+    }
+    debug!(changes = changes.len(), step, "Quantized desired F0 onsets");
+}
+
+/// Turns the instant jumps between notes of `desired_f0` into glides: at every change from
+/// one voiced note to another, the first `glide_frames` frames of the new note move from
+/// the previous note's pitch to their own along a geometric (log-frequency) ramp. A note
+/// shorter than the glide is cut short by the next change. Transitions to or from unvoiced
+/// frames (<= 0) are left alone.
+pub fn apply_portamento(desired_f0: &mut [f32], glide_frames: usize) {
+    if glide_frames == 0 || desired_f0.len() < 2 {
+        return;
+    }
+    let original = desired_f0.to_vec();
+    let changes: Vec<usize> = (1..original.len())
+        .filter(|&i| {
+            original[i - 1] > 0.0
+                && original[i] > 0.0
+                && is_note_change(original[i - 1], original[i])
+        })
+        .collect();
+    for (n, &change) in changes.iter().enumerate() {
+        let from = original[change - 1];
+        let end = changes
+            .get(n + 1)
+            .map_or(original.len(), |&next| next)
+            .min(change + glide_frames);
+        for frame in change..end {
+            let to = original[frame];
+            if to <= 0.0 {
+                break;
+            }
+            let t = (frame - change + 1) as f32 / (glide_frames + 1) as f32;
+            desired_f0[frame] = from * (to / from).powf(t);
+        }
+    }
+    debug!(
+        changes = changes.len(),
+        glide_frames, "Applied portamento to desired F0"
+    );
+}
+
+/// How far one note was moved by tuning, as reported by `correction_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteCorrection {
+    /// First PYIN frame of the note
+    pub start_frame: usize,
+    /// Number of frames the note lasts
+    pub frames: usize,
+    /// Name of the in-key note closest to the tuned pitch, e.g. "A4"
+    pub note: String,
+    /// Average shift from the original to the tuned pitch, in cents (positive is up)
+    pub cents: f32,
+}
+
+impl NoteCorrection {
+    /// Start of the note in seconds, for pitch data computed every `HOP_LENGTH` samples
+    pub fn start_time(&self, sample_rate: u32) -> f32 {
+        (self.start_frame * HOP_LENGTH) as f32 / sample_rate as f32
+    }
+}
+
+/// Summarizes how much tuning moved each note. Notes are runs of frames voiced in both
+/// tracks, split wherever the tuned pitch jumps by half a semitone or more.
+pub fn correction_report(original_f0: &[f32], tuned_f0: &[f32], key: &Key) -> Vec<NoteCorrection> {
+    let n_frames = original_f0.len().min(tuned_f0.len());
+    let voiced = |i: usize| original_f0[i] > 0.0 && tuned_f0[i] > 0.0;
+
+    let mut notes = Vec::new();
+    let mut start = 0;
+    while start < n_frames {
+        if !voiced(start) {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < n_frames && voiced(end) && !is_note_change(tuned_f0[end - 1], tuned_f0[end]) {
+            end += 1;
+        }
+
+        let frames = end - start;
+        let cents = (start..end)
+            .map(|i| hz_to_cents(tuned_f0[i], original_f0[i]))
+            .sum::<f32>()
+            / frames as f32;
+        // Average the tuned pitch in cents, so an octave above counts as much as one below
+        let tuned_cents = (start..end)
+            .map(|i| hz_to_cents(tuned_f0[i], 440.0))
+            .sum::<f32>()
+            / frames as f32;
+        let tuned = cents_to_hz(tuned_cents, 440.0);
+        let nearest = key
+            .scale_frequencies_in_range(tuned, tuned)
+            .into_iter()
+            .min_by(|a, b| {
+                hz_to_cents(tuned, *a)
+                    .abs()
+                    .total_cmp(&hz_to_cents(tuned, *b).abs())
+            });
+        if let Some(frequency) = nearest {
+            let midi = frequency_to_midi_note(frequency).round() as u8;
+            notes.push(NoteCorrection {
+                start_frame: start,
+                frames,
+                note: midi_note_name(midi),
+                cents,
+            });
+        }
+        start = end;
+    }
+    debug!(notes = notes.len(), %key, "Built correction report");
+    notes
+}
+
+/// A detected note, as returned by `note_segments`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteSegment {
+    /// Start of the note's first frame, in seconds
+    pub start_sec: f32,
+    /// Start of the first frame after the note, in seconds
+    pub end_sec: f32,
+    /// Median detected pitch over the note, in Hz
+    pub median_f0: f32,
+}
+
+/// Splits the voiced frames of `pyin` into notes: runs of contiguous voiced frames whose
+/// pitch stays within a semitone of the note's first frame. Frames are `hop_length`
+/// samples apart at `sample_rate`.
+pub fn note_segments(pyin: &PYINData, hop_length: usize, sample_rate: u32) -> Vec<NoteSegment> {
+    let f0 = pyin.f0();
+    let n_frames = f0.len().min(pyin.voiced_flag().len());
+    let voiced = |i: usize| pyin.voiced_flag()[i] && f0[i] > 0.0;
+    let frame_time = |frame: usize| (frame * hop_length) as f32 / sample_rate as f32;
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < n_frames {
+        if !voiced(start) {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < n_frames && voiced(end) && hz_to_cents(f0[end], f0[start]).abs() < 100.0 {
+            end += 1;
+        }
+
+        let mut pitches = f0[start..end].to_vec();
+        pitches.sort_by(f32::total_cmp);
+        let middle = pitches.len() / 2;
+        let median_f0 = if pitches.len().is_multiple_of(2) {
+            (pitches[middle - 1] + pitches[middle]) / 2.0
+        } else {
+            pitches[middle]
+        };
+        segments.push(NoteSegment {
+            start_sec: frame_time(start),
+            end_sec: frame_time(end),
+            median_f0,
+        });
+        start = end;
+    }
+    debug!(segments = segments.len(), "Found note segments");
+    segments
+}
+
+/// Shifts all of `audio` by `semitones` (negative for down) with PSOLA: the target is the
+/// detected pitch of every voiced frame times `2^(semitones / 12)`. Needs the PYIN data of
+/// `audio`, its desired F0 and tune mask are ignored.
+pub fn pitch_shift_semitones(audio: &Audio, semitones: f32) -> anyhow::Result<Audio> {
+    pitch_shift_semitones_with_engine(audio, semitones, ShiftEngine::Psola)
+}
+
+/// Same as `pitch_shift_semitones`, shifting with `engine`.
+pub fn pitch_shift_semitones_with_engine(
+    audio: &Audio,
+    semitones: f32,
+    engine: ShiftEngine,
+) -> anyhow::Result<Audio> {
+    let pyin = audio.get_pyin().ok_or(AutotuneError::MissingPyin)?;
+    let ratio = 2f32.powf(semitones / 12.0);
+    let mut shifted = audio.clone();
+    shifted.desired_f0 = Some(
+        passthrough_f0(&pyin)
+            .into_iter()
+            .map(|freq| freq * ratio)
+            .collect(),
+    );
+    shifted.tune_mask = None;
+    debug!(semitones, ratio, %engine, "Pitch shifting audio");
+    Ok(compute_shifted_audio_with_engine(&shifted, engine)?)
+}
+
+/// Krumhansl-Kessler key profiles: how well each pitch class above the root fits a key
+const MAJOR_KEY_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_KEY_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// Template a pitch-class histogram is correlated with for `scale` rooted on C: the
+/// Krumhansl-Kessler profile for major and minor, scale membership for the others.
+/// None for the chromatic scale, which fits every track equally.
+fn key_profile(scale: Scale) -> Option<[f32; 12]> {
+    match scale {
+        Scale::Major => Some(MAJOR_KEY_PROFILE),
+        Scale::Minor => Some(MINOR_KEY_PROFILE),
+        Scale::Chromatic => None,
+        _ => {
+            let key = Key::new(Note::C, scale);
+            Some(std::array::from_fn(|pc| {
+                if key.contains(pc as u8) { 1.0 } else { 0.0 }
+            }))
+        }
+    }
+}
+
+/// Pearson correlation of two pitch-class profiles, 0.0 if either is flat
+fn profile_correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = b.iter().sum::<f32>() / 12.0;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+/// Most likely key of a track: builds a pitch-class histogram of the voiced frames and
+/// picks the root and scale whose profile (see `key_profile`) correlates best with it.
+/// Falls back to C major when no frame is voiced.
+pub fn detect_key(pyin: &PYINData) -> Key {
+    let mut histogram = [0.0f32; 12];
+    for (&freq, &voiced) in pyin.f0().iter().zip(pyin.voiced_flag()) {
+        if voiced && freq > 0.0 {
+            let pitch_class = (frequency_to_midi_note(freq).round() as i32).rem_euclid(12);
+            histogram[pitch_class as usize] += 1.0;
+        }
+    }
+
+    let mut best = (Key::new(Note::C, Scale::Major), f32::NEG_INFINITY);
+    for scale in Scale::ALL {
+        let Some(profile) = key_profile(scale) else {
+            continue;
+        };
+        for (root_index, root) in Note::ALL.into_iter().enumerate() {
+            // Profile of this root: pitch class pc is (pc - root) semitones above the root
+            let rotated = std::array::from_fn(|pc| profile[(pc + 12 - root_index) % 12]);
+            let score = profile_correlation(&histogram, &rotated);
+            if score > best.1 {
+                best = (Key::new(root, scale), score);
+            }
+        }
+    }
+    debug!(key = %best.0, score = best.1, "Detected key");
+    best.0
+}
+
+/// Target f0 of a harmony voice `interval` above the lead `f0`: each voiced frame moves from
+/// its nearest note of `key` up the scale, keeping its offset from that note so vibrato and
+/// bends carry over. Unvoiced frames (f0 <= 0) stay at 0.0.
+pub fn harmony_f0(f0: &[f32], interval: HarmonyInterval, key: &Key) -> Vec<f32> {
+    let scale: Vec<u8> = (0..=127u8).filter(|&m| key.contains(m)).collect();
+    let frequency =
+        |midi: u8| midi_note_to_frequency_with_reference(midi as f32, key.reference_a4());
+    f0.iter()
+        .map(|&freq| {
+            if freq <= 0.0 || scale.is_empty() {
+                return 0.0;
+            }
+            let Some((index, cents)) = scale
+                .iter()
+                .map(|&m| hz_to_cents(freq, frequency(m)))
+                .enumerate()
+                .min_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            else {
+                return 0.0;
+            };
+            let target = scale[(index + interval.scale_steps()).min(scale.len() - 1)];
+            cents_to_hz(cents, frequency(target))
+        })
+        .collect()
+}
+
+/// Renders a harmony voice for `audio`: the lead's detected pitch is moved `interval` up the
+/// scale of `key` with `harmony_f0` and PSOLA-shifted there. Needs the PYIN data of `audio`.
+/// The lead's desired F0 and tune mask are ignored.
+pub fn generate_harmony(
+    audio: &Audio,
+    interval: HarmonyInterval,
+    key: &Key,
+) -> anyhow::Result<Audio> {
+    let pyin = audio.get_pyin().ok_or(AutotuneError::MissingPyin)?;
+    let mut voice = audio.clone();
+    voice.desired_f0 = Some(harmony_f0(&passthrough_f0(&pyin), interval, key));
+    voice.tune_mask = None;
+    debug!(?interval, %key, "Generating harmony voice");
+    Ok(compute_shifted_audio(&voice)?)
+}
+
+/// Returns the desired F0 resampled to one value per PYIN frame.
+fn align_desired_f0(pyin: &PYINData, desired_f0: &[f32]) -> Result<Vec<f32>, AutotuneError> {
+    let n_frames = pyin.f0().len();
+    if desired_f0.len() == n_frames {
+        return Ok(desired_f0.to_vec());
+    }
+    if desired_f0.is_empty() {
+        return Err(AutotuneError::LengthMismatch {
+            expected: n_frames,
+            actual: 0,
+        });
+    }
+    debug!(
+        from = desired_f0.len(),
+        to = n_frames,
+        "Resampling desired F0 to the PYIN frame count"
+    );
+    Ok(resample_f0_track(desired_f0, n_frames))
+}
+
+/// Replaces the frames of `shifted` that `tune_mask` excludes with the `original` samples.
+/// The mask value of frame i applies at sample i * hop_length, in between the two signals
+/// are crossfaded so switching between tuned and untuned doesn't click.
+fn apply_tune_mask(original: &[f32], shifted: &mut [f32], tune_mask: &[bool], hop_length: usize) {
+    let Some(last) = tune_mask.len().checked_sub(1) else {
+        return;
+    };
+    let weight = |frame: usize| if tune_mask[frame.min(last)] { 1.0 } else { 0.0 };
+    for (n, (s, &o)) in shifted.iter_mut().zip(original).enumerate() {
+        let frame = n / hop_length;
+        let frac = (n % hop_length) as f32 / hop_length as f32;
+        let w = weight(frame) * (1.0 - frac) + weight(frame + 1) * frac;
+        *s = w * *s + (1.0 - w) * o;
+    }
+}
 
 /**
  * Computes a shifted audio signal using the Audio struct's desired f0 and PYIN data.
+ * A desired f0 of a different length is resampled to the PYIN frame count first.
+ * Frames excluded by the tune mask keep their original samples.
  * Returns the signal as a new audio struct.
 **/
-pub fn compute_shifted_audio(audio: &Audio) -> anyhow::Result<Audio> {
+pub fn compute_shifted_audio(audio: &Audio) -> Result<Audio, AutotuneError> {
+    compute_shifted_audio_with_engine(audio, ShiftEngine::default())
+}
+
+/// Like `compute_shifted_audio`, shifting with the given engine.
+pub fn compute_shifted_audio_with_engine(
+    audio: &Audio,
+    engine: ShiftEngine,
+) -> Result<Audio, AutotuneError> {
+    let shift = |signal: &[f32], pyin: &PYINData, desired_f0: &[f32]| match engine {
+        ShiftEngine::Psola => psola::psola_with_config(
+            signal,
+            audio.sample_rate(),
+            pyin,
+            desired_f0,
+            &psola::PsolaConfig::default(),
+        ),
+        ShiftEngine::PhaseVocoder => phase_vocoder::phase_vocoder_shift_to_f0(
+            signal,
+            audio.sample_rate(),
+            pyin,
+            desired_f0,
+            FRAME_LENGTH,
+            HOP_LENGTH,
+        ),
+    };
     let pyin_data = audio.get_pyin();
     match pyin_data {
         Some(pyin) => {
             let desired_f0: Vec<f32>;
             match &audio.desired_f0 {
                 Some(f0) => {
-                    desired_f0 = f0.clone();
+                    desired_f0 = align_desired_f0(&pyin, f0)?;
                 }
                 None => {
-                    return Err(anyhow::anyhow!("No desired F0 data available for audio"));
+                    return Err(AutotuneError::MissingDesiredF0);
                 }
             }
-            let (shifted_left, shifted_right) = rayon::join(
-                || {
-                    psola::psola(
-                        &audio.left().to_vec(),
-                        audio.sample_rate(),
-                        &pyin,
-                        &desired_f0,
-                        None,
-                        None,
-                    )
-                },
-                || {
-                    psola::psola(
-                        &audio.right().to_vec(),
-                        audio.sample_rate(),
-                        &pyin,
-                        &desired_f0,
-                        None,
-                        None,
-                    )
-                },
+            if let Some(mask) = &audio.tune_mask
+                && !mask.is_empty()
+                && mask.iter().all(|&tune| !tune)
+            {
+                debug!("Tune mask excludes every frame, keeping the original audio");
+                if audio.channels() == 1 {
+                    return Ok(Audio::new_mono(audio.sample_rate(), audio.left().to_vec()));
+                }
+                return Ok(Audio::new(
+                    audio.sample_rate(),
+                    audio.left().to_vec(),
+                    audio.right().to_vec(),
+                ));
+            }
+            if audio.channels() == 1 {
+                let mut shifted = shift(audio.left(), &pyin, &desired_f0);
+                if let Some(mask) = &audio.tune_mask {
+                    apply_tune_mask(audio.left(), &mut shifted, mask, HOP_LENGTH);
+                }
+                return Ok(Audio::new_mono(audio.sample_rate(), shifted));
+            }
+            let (mut shifted_left, mut shifted_right) = rayon::join(
+                || shift(audio.left(), &pyin, &desired_f0),
+                || shift(audio.right(), &pyin, &desired_f0),
             );
+            if let Some(mask) = &audio.tune_mask {
+                apply_tune_mask(audio.left(), &mut shifted_left, mask, HOP_LENGTH);
+                apply_tune_mask(audio.right(), &mut shifted_right, mask, HOP_LENGTH);
+            }
             Ok(Audio::new(audio.sample_rate(), shifted_left, shifted_right))
         }
-        None => Err(anyhow::anyhow!("No PYIN data available for audio")),
+        None => Err(AutotuneError::MissingPyin),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::scales::{Note, Scale, midi_note_to_frequency};
+    use crate::audio::sine_wave;
+
+    #[test]
+    fn test_shift_engine_round_trips_through_strings() {
+        for engine in [ShiftEngine::Psola, ShiftEngine::PhaseVocoder] {
+            assert_eq!(engine.to_string().parse::<ShiftEngine>(), Ok(engine));
+        }
+        assert_eq!("PSOLA".parse::<ShiftEngine>(), Ok(ShiftEngine::Psola));
+        assert!("vocoder".parse::<ShiftEngine>().is_err());
+    }
+
+    #[test]
+    fn test_correction_report_summarizes_each_note() {
+        let key = Key::new(Note::C, Scale::Major);
+        // A slightly flat A4, a break, then a sharp C5, each snapped to the key
+        let mut original = vec![430.0; 20];
+        original.extend([0.0; 5]);
+        original.extend(vec![530.0; 30]);
+        let tuned = snap_to_scale(&original, key);
+
+        let report = correction_report(&original, &tuned, &key);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].note, "A4");
+        assert_eq!((report[0].start_frame, report[0].frames), (0, 20));
+        assert!((report[0].cents - hz_to_cents(440.0, 430.0)).abs() < 0.1);
+        assert!(report[0].cents > 0.0);
+        assert_eq!(report[1].note, "C5");
+        assert_eq!((report[1].start_frame, report[1].frames), (25, 30));
+        assert!(report[1].cents < 0.0);
+        assert!((report[1].start_time(44100) - 25.0 * 256.0 / 44100.0).abs() < 1e-6);
+
+        // Back to back notes without a break are told apart by the jump in tuned pitch
+        let legato: Vec<f32> = [430.0; 10].into_iter().chain([530.0; 10]).collect();
+        let report = correction_report(&legato, &snap_to_scale(&legato, key), &key);
+        let notes: Vec<&str> = report.iter().map(|n| n.note.as_str()).collect();
+        assert_eq!(notes, ["A4", "C5"]);
+    }
+
+    fn mixed_voicing_pyin() -> PYINData {
+        PYINData::new(
+            vec![220.0, 0.0, 231.5, 180.0],
+            vec![true, false, true, false],
+            vec![0.9, 0.1, 0.8, 0.3],
+        )
+    }
+
+    #[test]
+    fn test_passthrough_f0_copies_voiced_frames() {
+        assert_eq!(
+            passthrough_f0(&mixed_voicing_pyin()),
+            [220.0, 0.0, 231.5, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_cleared_f0_is_all_unvoiced() {
+        assert_eq!(cleared_f0(&mixed_voicing_pyin()), [0.0; 4]);
+        assert!(cleared_f0(&PYINData::new(Vec::new(), Vec::new(), Vec::new())).is_empty());
+    }
+
+    #[test]
+    fn test_flat_f0_holds_voiced_frames_on_one_note() {
+        assert_eq!(
+            flat_f0(&mixed_voicing_pyin(), 261.63),
+            [261.63, 0.0, 261.63, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_frames_per_beat() {
+        // Half a second per beat, 44100 / 256 frames per second
+        assert!((frames_per_beat(120.0, 44100, 256) - 86.132_81).abs() < 1e-3);
+        assert!((frames_per_beat(60.0, 44100, 256) - 2.0 * 86.132_81).abs() < 1e-3);
+        assert_eq!(frames_per_beat(120.0, 16000, 250), 32.0);
+    }
+
+    #[test]
+    fn test_validate_frame_hop() {
+        for (frame_length, hop_length) in [(2048, 0), (256, 2048), (0, 256), (0, 0)] {
+            assert_eq!(
+                validate_frame_hop(frame_length, hop_length),
+                Err(AutotuneError::InvalidFrameHop {
+                    frame_length,
+                    hop_length
+                })
+            );
+        }
+        assert_eq!(validate_frame_hop(FRAME_LENGTH, HOP_LENGTH), Ok(()));
+        // Back to back frames without overlap are still fine
+        assert_eq!(validate_frame_hop(512, 512), Ok(()));
+    }
+
+    #[test]
+    fn test_snap_to_scale_picks_closest_in_key_frequency() {
+        let key = Key::new(Note::C, Scale::Major);
+        let snapped = snap_to_scale(&[0.0, 445.0, 270.0, -1.0], key);
+        assert_eq!(snapped[0], 0.0);
+        assert!((snapped[1] - 440.0).abs() < 0.01);
+        assert!((snapped[2] - 261.63).abs() < 0.01);
+        assert_eq!(snapped[3], 0.0);
+    }
+
+    #[test]
+    fn test_snap_voiced_to_scale_zeroes_or_passes_through_unvoiced() {
+        let key = Key::new(Note::C, Scale::Major);
+        // Frame 1 carries a pitch but is flagged unvoiced
+        let f0 = [445.0, 270.0, 0.0, 330.0];
+        let voiced = [true, false, false, true];
+
+        let zeroed = snap_voiced_to_scale(&f0, &voiced, key, false);
+        assert!((zeroed[0] - 440.0).abs() < 0.01);
+        assert_eq!(zeroed[1..3], [0.0, 0.0]);
+        assert!((zeroed[3] - 329.63).abs() < 0.01);
+
+        let passed = snap_voiced_to_scale(&f0, &voiced, key, true);
+        assert_eq!(passed[0], zeroed[0]);
+        assert_eq!(passed[1..3], [270.0, 0.0]);
+        assert_eq!(passed[3], zeroed[3]);
+    }
+
+    #[test]
+    fn test_snap_to_scale_handles_pitches_above_octave_6() {
+        let key = Key::new(Note::C, Scale::Major);
+        let snapped = snap_to_scale(&[2500.0], key)[0];
+        assert!(snapped > 2000.0, "snapped = {}", snapped);
+        assert!(hz_to_cents(2500.0, snapped).abs() <= 100.0);
+        assert!(key.contains(frequency_to_midi_note(snapped).round() as u8));
+    }
+
+    #[test]
+    fn test_compute_shifted_audio_reports_missing_pyin() {
+        let mut audio = Audio::new(16000, vec![0.0; 4096], vec![0.0; 4096]);
+        audio.desired_f0 = Some(vec![220.0; 8]);
+        assert_eq!(
+            compute_shifted_audio(&audio).unwrap_err(),
+            AutotuneError::MissingPyin
+        );
+    }
+
+    #[test]
+    fn test_compute_shifted_audio_reports_missing_desired_f0() {
+        let audio = Audio::new(16000, vec![0.0; 4096], vec![0.0; 4096]);
+        audio.pyin_handle().write().unwrap().replace(PYINData::new(
+            vec![0.0; 8],
+            vec![false; 8],
+            vec![0.0; 8],
+        ));
+        assert_eq!(
+            compute_shifted_audio(&audio).unwrap_err(),
+            AutotuneError::MissingDesiredF0
+        );
+    }
+
+    #[test]
+    fn test_compute_shifted_audio_reports_empty_desired_f0() {
+        let mut audio = Audio::new(16000, vec![0.0; 4096], vec![0.0; 4096]);
+        audio.pyin_handle().write().unwrap().replace(PYINData::new(
+            vec![0.0; 8],
+            vec![false; 8],
+            vec![0.0; 8],
+        ));
+        audio.desired_f0 = Some(Vec::new());
+        assert_eq!(
+            compute_shifted_audio(&audio).unwrap_err(),
+            AutotuneError::LengthMismatch {
+                expected: 8,
+                actual: 0
+            }
+        );
+    }
+
+    /// A 220 Hz tone with matching PYIN data, to be tuned to `target` Hz
+    fn tone_to_tune(target: f32) -> Audio {
+        let samples: Vec<f32> = sine_wave(220.0, 16000, 8192)
+            .iter()
+            .map(|s| 0.5 * s)
+            .collect();
+        analyzed_at_220(samples, target)
+    }
+
+    /// Like `tone_to_tune`, with the first eight harmonics of 220 Hz. PSOLA keeps the
+    /// spectral envelope, so a bare sine says little about how far a voice shifts.
+    fn voice_to_tune(target: f32) -> Audio {
+        let mut samples = vec![0.0; 8192];
+        for k in 1..=8 {
+            let harmonic = sine_wave(220.0 * k as f32, 16000, samples.len());
+            for (s, h) in samples.iter_mut().zip(harmonic) {
+                *s += 0.25 * h / k as f32;
+            }
+        }
+        analyzed_at_220(samples, target)
+    }
+
+    /// Stereo audio of `samples` at 16 kHz with PYIN data of a steady 220 Hz
+    fn analyzed_at_220(samples: Vec<f32>, target: f32) -> Audio {
+        let mut audio = Audio::new(16000, samples.clone(), samples);
+        let n_frames = (audio.length() - FRAME_LENGTH) / HOP_LENGTH + 1;
+        audio.pyin_handle().write().unwrap().replace(PYINData::new(
+            vec![220.0; n_frames],
+            vec![true; n_frames],
+            vec![1.0; n_frames],
+        ));
+        audio.desired_f0 = Some(vec![target; n_frames]);
+        audio
+    }
+
+    /// Median f0 PYIN detects in the voiced frames of the left channel, refined to a
+    /// fraction of a lag so it resolves a few cents
+    fn median_f0(audio: &Audio) -> f32 {
+        let config = pyin::PyinConfig {
+            sub_lag_refinement: true,
+            ..Default::default()
+        };
+        let pitch = pyin::pyin_with_config(audio.left(), audio.sample_rate(), &config);
+        let mut voiced: Vec<f32> = pitch
+            .f0()
+            .iter()
+            .zip(pitch.voiced_flag())
+            .filter(|&(_, &voiced)| voiced)
+            .map(|(&f0, _)| f0)
+            .collect();
+        assert!(!voiced.is_empty());
+        voiced.sort_by(f32::total_cmp);
+        voiced[voiced.len() / 2]
+    }
+
+    /// Distance from `f0` to `expected`, in cents
+    fn cents_off(f0: f32, expected: f32) -> f32 {
+        1200.0 * (f0 / expected).log2().abs()
+    }
+
+    #[test]
+    fn test_detect_key_finds_c_major() {
+        // A C major melody, resting on the tonic and the fifth
+        let melody = [60, 62, 64, 65, 67, 69, 71, 72, 67, 64, 60, 60, 67, 60];
+        let f0: Vec<f32> = melody
+            .iter()
+            .flat_map(|&m| vec![midi_note_to_frequency(m as f32); 10])
+            .collect();
+        let n = f0.len();
+        let pyin = PYINData::new(f0, vec![true; n], vec![1.0; n]);
+        assert_eq!(detect_key(&pyin), Key::new(Note::C, Scale::Major));
+
+        // The same notes centered on A read as A minor
+        let melody = [57, 59, 60, 62, 64, 65, 67, 69, 64, 60, 57, 57, 64, 57];
+        let f0: Vec<f32> = melody
+            .iter()
+            .flat_map(|&m| vec![midi_note_to_frequency(m as f32); 10])
+            .collect();
+        let pyin = PYINData::new(f0, vec![true; n], vec![1.0; n]);
+        assert_eq!(detect_key(&pyin), Key::new(Note::A, Scale::Minor));
+
+        // Unvoiced frames are ignored
+        let pyin = PYINData::new(vec![0.0; 10], vec![false; 10], vec![0.0; 10]);
+        assert_eq!(detect_key(&pyin), Key::new(Note::C, Scale::Major));
+    }
+
+    #[test]
+    fn test_harmony_f0_moves_up_the_scale() {
+        let key = Key::new(Note::C, Scale::Major);
+        let c4 = midi_note_to_frequency(60.0);
+        let third = harmony_f0(&[c4, 0.0], HarmonyInterval::Third, &key);
+        assert!(
+            (third[0] - midi_note_to_frequency(64.0)).abs() < 0.01,
+            "{}",
+            third[0]
+        ); // E4
+        assert_eq!(third[1], 0.0);
+
+        // Scale steps, not semitones: a third above E4 is G4, a fifth above B4 is F5
+        let e4 = midi_note_to_frequency(64.0);
+        let b4 = midi_note_to_frequency(71.0);
+        let g4 = harmony_f0(&[e4], HarmonyInterval::Third, &key)[0];
+        let f5 = harmony_f0(&[b4], HarmonyInterval::Fifth, &key)[0];
+        assert!((g4 - midi_note_to_frequency(67.0)).abs() < 0.01, "{}", g4);
+        assert!((f5 - midi_note_to_frequency(77.0)).abs() < 0.01, "{}", f5);
+
+        // A sharp lead gives an equally sharp harmony
+        let sharp = harmony_f0(&[cents_to_hz(20.0, c4)], HarmonyInterval::Third, &key)[0];
+        assert!((hz_to_cents(sharp, midi_note_to_frequency(64.0)) - 20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_pitch_shift_semitones_up_an_octave_doubles_frequency() {
+        let audio = voice_to_tune(0.0);
+        let n_frames = audio.get_pyin().unwrap().f0().len();
+
+        // The target is the detected pitch times the semitone ratio
+        let mut expected = audio.clone();
+        expected.desired_f0 = Some(vec![440.0; n_frames]);
+        let octave_up = pitch_shift_semitones(&audio, 12.0).unwrap();
+        let expected = compute_shifted_audio_with_engine(&expected, ShiftEngine::Psola).unwrap();
+        assert!(octave_up.approx_eq(&expected, 1e-3));
+
+        assert!(cents_off(median_f0(&audio), 220.0) < 5.0);
+        let f0 = median_f0(&octave_up);
+        assert!(cents_off(f0, 440.0) < 5.0, "{}", f0);
+
+        let unanalyzed = Audio::new(16000, vec![0.0; 4096], vec![0.0; 4096]);
+        assert!(pitch_shift_semitones(&unanalyzed, 5.0).is_err());
+    }
+
+    #[test]
+    fn test_generate_harmony_shifts_to_harmony_f0() {
+        let key = Key::new(Note::C, Scale::Major);
+        // The lead is an A3, so the third above is C4 (261.6 Hz)
+        let audio = voice_to_tune(0.0);
+        let harmony = generate_harmony(&audio, HarmonyInterval::Third, &key).unwrap();
+        let f0 = median_f0(&harmony);
+        assert!(cents_off(f0, midi_note_to_frequency(60.0)) < 5.0, "{}", f0);
+
+        let unanalyzed = Audio::new(16000, vec![0.0; 4096], vec![0.0; 4096]);
+        assert!(generate_harmony(&unanalyzed, HarmonyInterval::Fifth, &key).is_err());
+    }
+
+    #[test]
+    fn test_fully_false_tune_mask_keeps_original_audio() {
+        for target in [110.0, 260.0, 440.0] {
+            let mut audio = tone_to_tune(target);
+            assert!(
+                !compute_shifted_audio(&audio)
+                    .unwrap()
+                    .approx_eq(&audio, 1e-3)
+            );
+
+            audio.tune_mask = Some(vec![false; 25]);
+            let shifted = compute_shifted_audio(&audio).unwrap();
+            assert!(shifted.approx_eq(&audio, 0.0));
+            let vocoded =
+                compute_shifted_audio_with_engine(&audio, ShiftEngine::PhaseVocoder).unwrap();
+            assert!(vocoded.approx_eq(&audio, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_tune_mask_passes_excluded_frames_through() {
+        let mut audio = tone_to_tune(260.0);
+        let tuned = compute_shifted_audio(&audio).unwrap();
+        // Leave the first 10 frames alone, tune the rest
+        let mut mask = vec![true; 25];
+        mask[..10].fill(false);
+        audio.tune_mask = Some(mask);
+        let masked = compute_shifted_audio(&audio).unwrap();
+
+        // Up to the start of frame 9 only the original is heard, from frame 10 on the
+        // tuned audio, with a crossfade in between
+        let fade_start = 9 * HOP_LENGTH;
+        let fade_end = 10 * HOP_LENGTH;
+        assert_eq!(masked.left()[..fade_start], audio.left()[..fade_start]);
+        assert_eq!(masked.left()[fade_end..], tuned.left()[fade_end..]);
+        assert_eq!(masked.right()[fade_end..], tuned.right()[fade_end..]);
+    }
+
+    #[test]
+    fn test_short_desired_f0_is_aligned_to_pyin_frames() {
+        let mut audio = Audio::synth_sine(230.0, 16000, 0.5);
+        audio.perform_pyin();
+        let pyin = audio.get_pyin().unwrap();
+        let n_frames = pyin.f0().len();
+
+        let short = vec![220.0; n_frames / 3];
+        let aligned = align_desired_f0(&pyin, &short).unwrap();
+        assert_eq!(aligned.len(), n_frames);
+
+        audio.desired_f0 = Some(short);
+        let shifted = compute_shifted_audio(&audio).expect("short desired F0 should be aligned");
+        assert!(shifted.length() > 0);
+    }
+
+    #[test]
+    fn test_resample_f0_track_interpolates_voiced_and_keeps_unvoiced() {
+        let resampled = resample_f0_track(&[100.0, 200.0], 3);
+        assert_eq!(resampled, vec![100.0, 150.0, 200.0]);
+
+        // Never glide in from an unvoiced frame
+        let resampled = resample_f0_track(&[0.0, 200.0], 5);
+        assert_eq!(resampled, vec![0.0, 0.0, 200.0, 200.0, 200.0]);
+
+        assert_eq!(
+            resample_f0_track(&[100.0, 0.0, 300.0], 3),
+            vec![100.0, 0.0, 300.0]
+        );
+        assert_eq!(resample_f0_track(&[], 4), vec![0.0; 4]);
+    }
+
+    #[test]
+    fn test_retune_speed_extremes() {
+        let detected = [0.0, 225.0, 230.0, 228.0, 0.0, 450.0, 452.0];
+        let target = [0.0, 220.0, 220.0, 220.0, 0.0, 440.0, 440.0];
+
+        let snapped = retune(&detected, &target, 1.0);
+        for (out, want) in snapped.iter().zip(&target) {
+            assert!((out - want).abs() < 1e-3, "{} != {}", out, want);
+        }
+
+        let untouched = retune(&detected, &target, 0.0);
+        for (out, want) in untouched.iter().zip(&detected) {
+            assert!((out - want).abs() < 1e-3, "{} != {}", out, want);
+        }
+    }
+
+    #[test]
+    fn test_retune_glides_towards_target() {
+        let detected = vec![230.0; 32];
+        let target = vec![220.0; 32];
+        let out = retune(&detected, &target, 0.25);
+        // Moves monotonically from the detected pitch towards the target
+        assert!(out[0] < 230.0 && out[0] > 220.0);
+        assert!(out.windows(2).all(|w| w[1] <= w[0]));
+        assert!((out[31] - 220.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_quantize_f0_onsets_moves_transition_to_grid() {
+        // Two notes changing at frame 37, on a grid line every 16 frames
+        let mut desired = vec![220.0; 37];
+        desired.extend(vec![330.0; 27]);
+        quantize_f0_onsets(&mut desired, 32.0, 2);
+        assert!(desired[..32].iter().all(|&f| f == 220.0));
+        assert!(desired[32..].iter().all(|&f| f == 330.0));
+
+        // A change past the midpoint moves later instead
+        let mut desired = vec![220.0; 44];
+        desired.extend(vec![330.0; 20]);
+        quantize_f0_onsets(&mut desired, 32.0, 2);
+        assert!(desired[..48].iter().all(|&f| f == 220.0));
+        assert!(desired[48..].iter().all(|&f| f == 330.0));
+    }
+
+    #[test]
+    fn test_quantize_f0_onsets_ignores_small_bends_and_keeps_unvoiced() {
+        // Vibrato within a note is not an onset
+        let mut desired: Vec<f32> = (0..20).map(|i| 220.0 + (i % 3) as f32).collect();
+        let original = desired.clone();
+        quantize_f0_onsets(&mut desired, 8.0, 1);
+        assert_eq!(desired, original);
+
+        // A note entering after silence snaps too, here to the later grid line
+        let mut desired = vec![0.0; 5];
+        desired.extend(vec![440.0; 11]);
+        quantize_f0_onsets(&mut desired, 8.0, 1);
+        assert_eq!(&desired[..], &[&[0.0; 8][..], &[440.0; 8][..]].concat()[..]);
+    }
+
+    #[test]
+    fn test_note_segments_splits_two_notes() {
+        // 4 unvoiced frames, 10 frames around A3, 2 unvoiced, 8 frames around E4
+        let mut f0 = vec![0.0; 4];
+        f0.extend([
+            220.0, 221.0, 219.0, 222.0, 220.0, 218.0, 220.0, 223.0, 220.0, 221.0,
+        ]);
+        f0.extend([0.0, 0.0]);
+        f0.extend([330.0, 331.0, 329.0, 330.0, 332.0, 330.0, 328.0, 330.0]);
+        let voiced: Vec<bool> = f0.iter().map(|&f| f > 0.0).collect();
+        let pyin = PYINData::new(f0.clone(), voiced, vec![1.0; f0.len()]);
+
+        let segments = note_segments(&pyin, 256, 16000);
+        assert_eq!(segments.len(), 2);
+        let frame = 256.0 / 16000.0;
+        assert!((segments[0].start_sec - 4.0 * frame).abs() < 1e-6);
+        assert!((segments[0].end_sec - 14.0 * frame).abs() < 1e-6);
+        assert_eq!(segments[0].median_f0, 220.0);
+        assert!((segments[1].start_sec - 16.0 * frame).abs() < 1e-6);
+        assert!((segments[1].end_sec - 24.0 * frame).abs() < 1e-6);
+        assert_eq!(segments[1].median_f0, 330.0);
+
+        // A jump of a semitone or more splits a note even without a gap
+        let legato = PYINData::new(
+            vec![220.0, 220.0, 233.1, 233.1],
+            vec![true; 4],
+            vec![1.0; 4],
+        );
+        assert_eq!(note_segments(&legato, 256, 16000).len(), 2);
+    }
+
+    #[test]
+    fn test_apply_portamento_glides_geometrically() {
+        let mut desired = [vec![220.0; 6], vec![440.0; 10]].concat();
+        apply_portamento(&mut desired, 4);
+
+        assert_eq!(desired[..6], [220.0; 6]);
+        assert_eq!(desired[10..], [440.0; 6]);
+        // Every step of the ramp (220 -> glide -> 440) multiplies by the same ratio
+        let ramp = &desired[5..11];
+        let ratio = 2f32.powf(1.0 / 5.0);
+        for pair in ramp.windows(2) {
+            assert!(pair[1] > pair[0]);
+            assert!((pair[1] / pair[0] - ratio).abs() < 1e-4, "{:?}", ramp);
+        }
+
+        // Glides never start from or run into silence
+        let mut desired = [vec![0.0; 3], vec![440.0; 2], vec![0.0; 3], vec![220.0; 3]].concat();
+        let original = desired.clone();
+        apply_portamento(&mut desired, 4);
+        assert_eq!(desired, original);
     }
 }
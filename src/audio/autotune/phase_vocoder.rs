@@ -0,0 +1,246 @@
+use crate::audio::autotune::{pyin::PYINData, spectral::WindowKind, validate_frame_hop};
+use rustfft::{FftPlanner, num_complex::Complex};
+use std::f32::consts::PI;
+use tracing::{debug, error};
+
+/// Wraps a phase to [-PI, PI).
+fn wrap_phase(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Shifts the pitch of `signal` by `shift_ratio` (2.0 is an octave up) without changing
+/// its length, using a phase vocoder.
+/// Every bin is moved to `shift_ratio` times its frequency, so chords and noisy sounds are
+/// shifted as a whole instead of relying on a single pitch period like PSOLA. Transients
+/// get smeared over roughly `frame_length` samples in exchange.
+pub fn phase_vocoder_shift(
+    signal: &[f32],
+    sample_rate: u32,
+    shift_ratio: f32,
+    frame_length: usize,
+    hop_length: usize,
+) -> Vec<f32> {
+    shift_with(signal, sample_rate, frame_length, hop_length, |_| {
+        shift_ratio
+    })
+}
+
+/// Like `phase_vocoder_shift`, with the ratio following the pitch data: each frame is
+/// shifted by `target_f0 / f0` of the PYIN frame it lines up with, unvoiced frames are
+/// left as they are. `hop_length` must be the hop the pitch data was computed with.
+pub fn phase_vocoder_shift_to_f0(
+    signal: &[f32],
+    sample_rate: u32,
+    pyin_result: &PYINData,
+    target_f0: &[f32],
+    frame_length: usize,
+    hop_length: usize,
+) -> Vec<f32> {
+    let n_frames = pyin_result.f0().len().min(target_f0.len());
+    if n_frames == 0 {
+        return signal.to_vec();
+    }
+    shift_with(signal, sample_rate, frame_length, hop_length, |centre| {
+        // PYIN frame i covers [i * hop, i * hop + frame_length)
+        let frame = (centre.saturating_sub(frame_length / 2) / hop_length).min(n_frames - 1);
+        let (f0, target) = (pyin_result.f0()[frame], target_f0[frame]);
+        if pyin_result.voiced_flag()[frame] && f0 > 0.0 && target > 0.0 {
+            target / f0
+        } else {
+            1.0
+        }
+    })
+}
+
+/// Phase vocoder pitch shift with a ratio per frame, given by `ratio_at` from the sample
+/// (of `signal`) at the centre of the frame.
+fn shift_with(
+    signal: &[f32],
+    sample_rate: u32,
+    frame_length: usize,
+    hop_length: usize,
+    ratio_at: impl Fn(usize) -> f32,
+) -> Vec<f32> {
+    if let Err(e) = validate_frame_hop(frame_length, hop_length) {
+        error!("Skipping phase vocoder: {}", e);
+        return signal.to_vec();
+    }
+    if signal.is_empty() || frame_length < 2 {
+        return signal.to_vec();
+    }
+    debug!(
+        frame_length,
+        hop_length,
+        n_samples = signal.len(),
+        "Starting phase vocoder pitch shifting"
+    );
+
+    // Pad a whole frame on both sides so every sample is covered by the same number of frames
+    let mut padded = vec![0.0; frame_length];
+    padded.extend_from_slice(signal);
+    padded.resize(signal.len() + 2 * frame_length, 0.0);
+    let n_frames = (padded.len() - frame_length) / hop_length + 1;
+
+    let n_bins = frame_length / 2 + 1;
+    let bin_hz = sample_rate as f32 / frame_length as f32;
+    // Phase advance of bin k over one hop is k * expected_advance
+    let expected_advance = 2.0 * PI * hop_length as f32 / frame_length as f32;
+
+    let window = WindowKind::Hann.window(frame_length);
+    let mut planner = FftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(frame_length);
+    let ifft = planner.plan_fft_inverse(frame_length);
+    let mut buffer = vec![Complex::new(0.0, 0.0); frame_length];
+
+    let mut last_phase = vec![0.0; n_bins];
+    let mut synth_phase = vec![0.0; n_bins];
+    let mut magnitude = vec![0.0; n_bins];
+    let mut frequency = vec![0.0; n_bins];
+    let mut output = vec![0.0; padded.len()];
+    let mut window_sum = vec![0.0; padded.len()];
+
+    for i in 0..n_frames {
+        let start = i * hop_length;
+        let ratio = ratio_at((start + frame_length / 2).saturating_sub(frame_length));
+        for ((b, &s), &w) in buffer
+            .iter_mut()
+            .zip(&padded[start..start + frame_length])
+            .zip(&window)
+        {
+            *b = Complex::new(s * w, 0.0);
+        }
+        fft.process(&mut buffer);
+
+        // Analysis: estimate the true frequency of each bin from its phase advance, then
+        // move its energy to the bin that frequency lands on after shifting
+        magnitude.fill(0.0);
+        frequency.fill(0.0);
+        for k in 0..n_bins {
+            let (mag, phase) = buffer[k].to_polar();
+            let deviation = wrap_phase(phase - last_phase[k] - k as f32 * expected_advance);
+            last_phase[k] = phase;
+            let true_hz = (k as f32 + deviation / expected_advance) * bin_hz;
+
+            let target = (k as f32 * ratio).round() as usize;
+            if target < n_bins {
+                magnitude[target] += mag;
+                frequency[target] = true_hz * ratio;
+            }
+        }
+
+        // Synthesis: accumulate each bin's phase at its new frequency
+        for k in 0..n_bins {
+            synth_phase[k] = wrap_phase(synth_phase[k] + frequency[k] / bin_hz * expected_advance);
+            buffer[k] = Complex::from_polar(magnitude[k], synth_phase[k]);
+        }
+        for k in n_bins..frame_length {
+            buffer[k] = buffer[frame_length - k].conj();
+        }
+        ifft.process(&mut buffer);
+
+        for (j, (b, &w)) in buffer.iter().zip(&window).enumerate() {
+            output[start + j] += b.re / frame_length as f32 * w;
+            window_sum[start + j] += w * w;
+        }
+    }
+
+    let shifted: Vec<f32> = output[frame_length..frame_length + signal.len()]
+        .iter()
+        .zip(&window_sum[frame_length..])
+        .map(|(&s, &w)| if w > 1e-6 { s / w } else { 0.0 })
+        .collect();
+    debug!(
+        n_samples = shifted.len(),
+        "Completed phase vocoder pitch shifting"
+    );
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::autotune::spectral;
+    use crate::audio::sine_wave;
+
+    /// Frequencies of the `count` strongest spectral peaks of `signal`, lowest first
+    fn strongest_partials(signal: &[f32], sample_rate: u32, count: usize) -> Vec<f32> {
+        let frame_length = 4096;
+        let frame = &spectral::stft(signal, frame_length, frame_length, WindowKind::Hann)[1];
+        let mut peaks: Vec<usize> = (1..frame.len() - 1)
+            .filter(|&k| frame[k] > frame[k - 1] && frame[k] >= frame[k + 1])
+            .collect();
+        peaks.sort_by(|&a, &b| frame[b].total_cmp(&frame[a]));
+        let mut partials: Vec<f32> = peaks[..count]
+            .iter()
+            .map(|&k| k as f32 * sample_rate as f32 / frame_length as f32)
+            .collect();
+        partials.sort_by(f32::total_cmp);
+        partials
+    }
+
+    fn two_tone(sample_rate: u32, length: usize) -> Vec<f32> {
+        sine_wave(220.0, sample_rate, length)
+            .iter()
+            .zip(sine_wave(330.0, sample_rate, length))
+            .map(|(low, high)| 0.4 * low + 0.4 * high)
+            .collect()
+    }
+
+    #[test]
+    fn test_octave_shift_doubles_both_partials() {
+        let sr = 16000;
+        let signal = two_tone(sr, sr as usize);
+        assert_eq!(strongest_partials(&signal, sr, 2), [218.75, 328.125]);
+
+        let shifted = phase_vocoder_shift(&signal, sr, 2.0, 2048, 256);
+        assert_eq!(shifted.len(), signal.len());
+        let bin_hz = sr as f32 / 4096.0;
+        let partials = strongest_partials(&shifted, sr, 2);
+        for (partial, expected) in partials.iter().zip([440.0, 660.0]) {
+            assert!(
+                (partial - expected).abs() <= 2.0 * bin_hz,
+                "partials = {:?}",
+                partials
+            );
+        }
+        // Level stays within 6 dB (spreading a peak over every other bin loses some energy)
+        let rms = |s: &[f32]| (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt();
+        let ratio = rms(&shifted[4096..12000]) / rms(&signal[4096..12000]);
+        assert!((0.5..2.0).contains(&ratio), "rms ratio = {}", ratio);
+    }
+
+    #[test]
+    fn test_unit_ratio_reconstructs_signal() {
+        let sr = 16000;
+        let signal = two_tone(sr, 8000);
+        let shifted = phase_vocoder_shift(&signal, sr, 1.0, 1024, 256);
+        for (a, b) in shifted.iter().zip(&signal) {
+            assert!((a - b).abs() < 1e-3, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_shift_to_f0_follows_pitch_data() {
+        let sr = 16000;
+        let signal = two_tone(sr, sr as usize);
+        let n_frames = (signal.len() - 2048) / 256 + 1;
+        let pyin = PYINData::new(
+            vec![220.0; n_frames],
+            vec![true; n_frames],
+            vec![1.0; n_frames],
+        );
+        let shifted =
+            phase_vocoder_shift_to_f0(&signal, sr, &pyin, &vec![440.0; n_frames], 2048, 256);
+        assert_eq!(shifted, phase_vocoder_shift(&signal, sr, 2.0, 2048, 256));
+
+        // Unvoiced frames are left alone
+        let unvoiced = PYINData::new(
+            vec![0.0; n_frames],
+            vec![false; n_frames],
+            vec![0.0; n_frames],
+        );
+        let kept =
+            phase_vocoder_shift_to_f0(&signal, sr, &unvoiced, &vec![440.0; n_frames], 2048, 256);
+        assert_eq!(kept, phase_vocoder_shift(&signal, sr, 1.0, 2048, 256));
+    }
+}
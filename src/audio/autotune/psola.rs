@@ -1,7 +1,50 @@
-use crate::audio::autotune::{FRAME_LENGTH, HOP_LENGTH, pyin::PYINData};
-use tracing::debug;
+use crate::audio::autotune::{
+    FRAME_LENGTH, HOP_LENGTH, pyin::PYINData, spectral::WindowKind, validate_frame_hop,
+};
+use tracing::{debug, error, warn};
+
+/// Parameters for a PSOLA pitch-shifting run.
+/// Use `PsolaConfig::default()` and override the fields you need.
+#[derive(Debug, Clone)]
+pub struct PsolaConfig {
+    /// Length of the grains taken around each pitch mark, in samples
+    pub frame_size: usize,
+    /// Size each grain to this many local pitch periods instead of `frame_size` (which
+    /// then only caps it), so high notes aren't smeared over many periods. Two periods (the
+    /// default) is the classic PSOLA choice; grains much longer than that carry the original
+    /// pitch with them and barely shift.
+    pub grain_periods: Option<f32>,
+    /// Hop between the PYIN frames the pitch data was computed with, in samples
+    pub hop_size: usize,
+    /// Window applied to each grain before it is overlap-added
+    pub window: WindowKind,
+    /// Move each pitch mark to the nearest positive-going zero crossing within a quarter
+    /// period, which keeps the grains in phase and reduces buzz
+    pub snap_to_zero_crossings: bool,
+    /// Nudge each grain by up to half a period from its shifted mark, to where it
+    /// correlates best with the output already written, which reduces phasiness
+    pub refine_grain_placement: bool,
+}
 
-fn find_pitch_marks(pyin: &PYINData, sample_rate: u32) -> Vec<usize> {
+impl Default for PsolaConfig {
+    fn default() -> Self {
+        Self {
+            frame_size: FRAME_LENGTH,
+            grain_periods: Some(2.0),
+            hop_size: HOP_LENGTH,
+            window: WindowKind::Hann,
+            snap_to_zero_crossings: false,
+            refine_grain_placement: false,
+        }
+    }
+}
+
+fn find_pitch_marks(
+    pyin: &PYINData,
+    sample_rate: u32,
+    frame_size: usize,
+    hop_size: usize,
+) -> Vec<usize> {
     let mut pitch_marks = Vec::new();
     let mut pos = 0.0_f32;
 
@@ -10,13 +53,13 @@ fn find_pitch_marks(pyin: &PYINData, sample_rate: u32) -> Vec<usize> {
             continue;
         }
         let period = sample_rate as f32 / pyin.f0()[i];
-        let frame_start = i * HOP_LENGTH;
+        let frame_start = i * hop_size;
 
         if pos < frame_start as f32 {
             pos = frame_start as f32;
         }
 
-        while pos < (frame_start + FRAME_LENGTH) as f32 {
+        while pos < (frame_start + frame_size) as f32 {
             pitch_marks.push(pos.round() as usize);
             pos += period;
         }
@@ -25,44 +68,193 @@ fn find_pitch_marks(pyin: &PYINData, sample_rate: u32) -> Vec<usize> {
     pitch_marks
 }
 
+/// Moves each pitch mark to the closest positive-going zero crossing of `audio` within
+/// a quarter of the local period. Marks without a crossing nearby are left in place.
+fn snap_marks_to_zero_crossings(
+    audio: &[f32],
+    pitch_marks: &[usize],
+    pyin: &PYINData,
+    sample_rate: u32,
+    hop_size: usize,
+) -> Vec<usize> {
+    let mut snapped: Vec<usize> = pitch_marks
+        .iter()
+        .map(|&mark| {
+            let frame_index = (mark / hop_size).min(pyin.f0().len().saturating_sub(1));
+            let f0 = pyin.f0().get(frame_index).copied().unwrap_or(0.0);
+            if f0 <= 0.0 || mark >= audio.len() {
+                return mark;
+            }
+            let radius = (sample_rate as f32 / f0 / 4.0) as usize;
+            let start = mark.saturating_sub(radius).max(1);
+            let end = (mark + radius).min(audio.len() - 1);
+            (start..=end)
+                .filter(|&j| audio[j - 1] < 0.0 && audio[j] >= 0.0)
+                // Of the two samples around the crossing, take the one closer to zero
+                .map(|j| {
+                    if audio[j - 1].abs() < audio[j].abs() {
+                        j - 1
+                    } else {
+                        j
+                    }
+                })
+                .min_by_key(|&j| j.abs_diff(mark))
+                .unwrap_or(mark)
+        })
+        .collect();
+    // Neighbouring marks can snap onto the same crossing
+    snapped.sort_unstable();
+    snapped.dedup();
+    snapped
+}
+
+/// Marks spaced for the pitch `target_f0`, one per analysis mark: each spacing between
+/// `pitch_marks` is divided by the shift ratio at its frame. Intervals that span an
+/// unvoiced frame keep their spacing. The marks are packed from the first one on, so they
+/// end early when the pitch goes up; `synthesis_marks` lays the spacings back out over the
+/// original time span.
 fn compute_target_pitch_spacing(
     pyin_result: &PYINData,
-    target_f0: &Vec<f32>,
-    pitch_marks: &Vec<usize>,
+    target_f0: &[f32],
+    pitch_marks: &[usize],
+    hop_size: usize,
 ) -> Vec<usize> {
     let mut shifted_marks = Vec::new();
     if pitch_marks.is_empty() {
         return shifted_marks;
     }
-
     shifted_marks.push(pitch_marks[0]);
+    let voiced = |frame: usize| pyin_result.voiced_flag()[frame] && pyin_result.f0()[frame] > 0.0;
+    let last_frame = pyin_result.f0().len().saturating_sub(1);
+    // Kept fractional so rounding doesn't add up over many marks
+    let mut pos = pitch_marks[0] as f32;
 
     for i in 1..pitch_marks.len() {
-        let frame_index =
-            (pitch_marks[i] / HOP_LENGTH).min(pyin_result.f0().len().saturating_sub(1));
-        if frame_index >= pyin_result.f0().len() {
-            break;
+        let frame_index = (pitch_marks[i] / hop_size).min(last_frame);
+        let previous_frame = (pitch_marks[i - 1] / hop_size).min(frame_index);
+        let old_spacing = pitch_marks[i] - pitch_marks[i - 1];
+        if !(previous_frame..=frame_index).all(voiced) {
+            pos += old_spacing as f32;
+            shifted_marks.push(pos.round() as usize);
+            continue;
         }
 
-        if !pyin_result.voiced_flag()[frame_index] || pyin_result.f0()[frame_index] <= 0.0 {
-            shifted_marks.push(shifted_marks[i - 1] + (pitch_marks[i] - pitch_marks[i - 1]));
+        let ratio = target_f0[frame_index] / pyin_result.f0()[frame_index];
+        // A NaN target or a denormal f0 would place the mark nowhere, keep the spacing then
+        if !ratio.is_finite() || ratio <= 0.0 {
+            pos += old_spacing as f32;
+            shifted_marks.push(pos.round() as usize);
             continue;
         }
+        pos += (old_spacing as f32 / ratio).max(1.0); // avoid zero spacing
+        shifted_marks.push(pos.round() as usize);
+    }
+
+    shifted_marks
+}
 
+/// Output marks of a PSOLA shift: the spacings of `shifted_marks` (see
+/// `compute_target_pitch_spacing`) repeated over the span of `pitch_marks`, so the pitch
+/// changes but the timing doesn't. Between two analysis marks the output gets one mark per
+/// target period; intervals that aren't shifted end on their analysis mark exactly.
+fn synthesis_marks(pitch_marks: &[usize], shifted_marks: &[usize]) -> Vec<usize> {
+    let Some(&first) = pitch_marks.first() else {
+        return Vec::new();
+    };
+    let mut marks = vec![first];
+    let mut pos = first as f32;
+    for i in 1..pitch_marks.len().min(shifted_marks.len()) {
+        let end = pitch_marks[i] as f32;
         let old_spacing = pitch_marks[i] - pitch_marks[i - 1];
-        let ratio = target_f0[frame_index] / pyin_result.f0()[frame_index];
-        let new_spacing = (old_spacing as f32 * ratio).max(1.0); // avoid zero spacing
-        shifted_marks.push(shifted_marks[i - 1] + new_spacing as usize);
+        let new_spacing = shifted_marks[i].saturating_sub(shifted_marks[i - 1]);
+        if new_spacing == old_spacing {
+            pos = end;
+            marks.push(pitch_marks[i]);
+            continue;
+        }
+        let new_spacing = new_spacing.max(1) as f32;
+        // Marks up to half a target period past the analysis mark belong to this interval
+        while pos + new_spacing <= end + new_spacing * 0.5 {
+            pos += new_spacing;
+            marks.push(pos.round() as usize);
+        }
     }
+    marks
+}
 
-    shifted_marks
+/// Index of the mark in `pitch_marks` (sorted) closest to sample `position`
+fn nearest_mark(pitch_marks: &[usize], position: usize) -> usize {
+    let after = pitch_marks.partition_point(|&mark| mark < position);
+    match (after.checked_sub(1), pitch_marks.get(after)) {
+        (Some(before), Some(&next)) if position - pitch_marks[before] > next - position => after,
+        (Some(before), _) => before,
+        (None, _) => 0,
+    }
+}
+
+/// Start within `radius` samples of `start` at which `grain` correlates best with what
+/// `output` already holds there. Ties, e.g. nothing written yet, keep the start closest
+/// to `start`.
+fn best_grain_start(output: &[f32], grain: &[f32], start: usize, radius: usize) -> usize {
+    let correlation = |candidate: usize| -> f32 {
+        output
+            .get(candidate..)
+            .unwrap_or_default()
+            .iter()
+            .zip(grain)
+            .map(|(o, g)| o * g)
+            .sum()
+    };
+    let mut best = (start, correlation(start));
+    for distance in 1..=radius {
+        let candidates = [start.checked_sub(distance), Some(start + distance)];
+        for candidate in candidates.into_iter().flatten() {
+            let score = correlation(candidate);
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+    }
+    best.0
+}
+
+/// Length of the grain around mark `i`: `grain_periods` times the local pitch period
+/// (the spacing to the closer neighbouring mark), capped at `frame_size`. Without
+/// `grain_periods`, or for a lone mark, it's `frame_size`.
+fn grain_size(
+    pitch_marks: &[usize],
+    i: usize,
+    frame_size: usize,
+    grain_periods: Option<f32>,
+) -> usize {
+    let Some(periods) = grain_periods else {
+        return frame_size;
+    };
+    let previous = i
+        .checked_sub(1)
+        .map(|p| pitch_marks[i].saturating_sub(pitch_marks[p]));
+    let next = pitch_marks
+        .get(i + 1)
+        .map(|&n| n.saturating_sub(pitch_marks[i]));
+    let period = match (previous, next) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) | (None, Some(a)) => a,
+        (None, None) => return frame_size,
+    };
+    ((period as f32 * periods).round() as usize).clamp(1, frame_size)
 }
 
+/// Overlap-adds a windowed grain at every mark of `shifted_marks`, each cut from `audio`
+/// around the analysis mark of `pitch_marks` closest to it in time. Raising the pitch
+/// repeats grains, lowering it skips some.
 fn overlap_add(
-    audio: &Vec<f32>,
-    pitch_marks: &Vec<usize>,
-    shifted_marks: &Vec<usize>,
+    audio: &[f32],
+    pitch_marks: &[usize],
+    shifted_marks: &[usize],
     frame_size: usize,
+    grain_periods: Option<f32>,
+    window: WindowKind,
+    refine_placement: bool,
 ) -> Vec<f32> {
     if pitch_marks.is_empty() || shifted_marks.is_empty() {
         return Vec::new();
@@ -70,37 +262,43 @@ fn overlap_add(
 
     let output_length = (*shifted_marks.last().unwrap() + frame_size).min(audio.len() * 2);
     let mut output = vec![0.0; output_length];
-    let half_frame = frame_size / 2;
-
-    // Hann window
-    let window: Vec<f32> = (0..frame_size)
-        .map(|n| {
-            let x = std::f32::consts::PI * 2.0 * n as f32 / (frame_size as f32 - 1.0);
-            0.5 * (1.0 - x.cos())
-        })
-        .collect();
-
-    for i in 0..pitch_marks.len().min(shifted_marks.len()) {
-        let orig_pos = pitch_marks[i];
-        let new_pos = shifted_marks[i];
+    // Grains of the same length share a window, so it's only rebuilt when the length changes
+    let mut grain_window = Vec::new();
+
+    for (i, &new_pos) in shifted_marks.iter().enumerate() {
+        let source = nearest_mark(pitch_marks, new_pos);
+        let orig_pos = pitch_marks[source];
+        let grain_len = grain_size(pitch_marks, source, frame_size, grain_periods);
+        if grain_window.len() != grain_len {
+            grain_window = window.window(grain_len);
+        }
+        let half_frame = grain_len / 2;
 
         let start_orig = orig_pos.saturating_sub(half_frame);
         let end_orig = (orig_pos + half_frame).min(audio.len());
-        let start_new = new_pos.saturating_sub(half_frame);
+        let mut start_new = new_pos.saturating_sub(half_frame);
         let end_new = (new_pos + half_frame).min(output.len());
 
         let max_len_orig = end_orig.saturating_sub(start_orig);
         let max_len_new = end_new.saturating_sub(start_new);
-        let len = max_len_orig.min(max_len_new);
+        let mut len = max_len_orig.min(max_len_new);
 
         if len == 0 {
             continue;
         }
 
         let win_start = half_frame.saturating_sub(orig_pos.saturating_sub(start_orig));
-        for j in 0..len {
-            let w = window[win_start + j];
-            output[start_new + j] += audio[start_orig + j] * w;
+        let grain: Vec<f32> = (0..len)
+            .map(|j| audio[start_orig + j] * grain_window[win_start + j])
+            .collect();
+        if refine_placement && i > 0 {
+            let period = grain_size(pitch_marks, source, frame_size, Some(1.0));
+            let radius = (period / 2).min(half_frame);
+            start_new = best_grain_start(&output, &grain, start_new, radius);
+            len = len.min(output.len().saturating_sub(start_new));
+        }
+        for (out, g) in output[start_new..start_new + len].iter_mut().zip(&grain) {
+            *out += g;
         }
     }
 
@@ -115,8 +313,24 @@ pub fn psola(
     frame_size: Option<usize>,
     hop_size: Option<usize>,
 ) -> Vec<f32> {
-    let frame_size = frame_size.unwrap_or(FRAME_LENGTH);
-    let hop_size = hop_size.unwrap_or(HOP_LENGTH);
+    let defaults = PsolaConfig::default();
+    let config = PsolaConfig {
+        frame_size: frame_size.unwrap_or(defaults.frame_size),
+        hop_size: hop_size.unwrap_or(defaults.hop_size),
+        ..defaults
+    };
+    psola_with_config(audio, sample_rate, pyin_result, target_f0, &config)
+}
+
+pub fn psola_with_config(
+    audio: &[f32],
+    sample_rate: u32,
+    pyin_result: &PYINData,
+    target_f0: &[f32],
+    config: &PsolaConfig,
+) -> Vec<f32> {
+    let frame_size = config.frame_size;
+    let hop_size = config.hop_size;
     debug!(
         frame_size,
         hop_size,
@@ -124,13 +338,44 @@ pub fn psola(
         "Starting PSOLA pitch shifting"
     );
 
+    if let Err(e) = validate_frame_hop(frame_size, hop_size) {
+        error!("Skipping PSOLA: {}", e);
+        return Vec::new();
+    }
     if audio.is_empty() || pyin_result.f0().is_empty() || target_f0.is_empty() {
         return Vec::new();
     }
 
-    let pitch_marks = find_pitch_marks(pyin_result, sample_rate);
-    let shifted_marks = compute_target_pitch_spacing(pyin_result, target_f0, &pitch_marks);
-    let output = overlap_add(audio, &pitch_marks, &shifted_marks, frame_size);
+    let mut pitch_marks = find_pitch_marks(pyin_result, sample_rate, frame_size, hop_size);
+    if config.snap_to_zero_crossings {
+        pitch_marks =
+            snap_marks_to_zero_crossings(audio, &pitch_marks, pyin_result, sample_rate, hop_size);
+    }
+    let shifted_marks =
+        compute_target_pitch_spacing(pyin_result, target_f0, &pitch_marks, hop_size);
+    let output_marks = synthesis_marks(&pitch_marks, &shifted_marks);
+    let mut output = overlap_add(
+        audio,
+        &pitch_marks,
+        &output_marks,
+        frame_size,
+        config.grain_periods,
+        config.window,
+        config.refine_grain_placement,
+    );
+
+    // Non-finite samples would reach the output device as a burst of full-scale noise
+    let mut n_non_finite = 0;
+    for s in output.iter_mut().filter(|s| !s.is_finite()) {
+        *s = 0.0;
+        n_non_finite += 1;
+    }
+    if n_non_finite > 0 {
+        warn!(
+            n_non_finite,
+            "PSOLA produced non-finite samples, replaced with silence"
+        );
+    }
 
     debug!(n_samples = output.len(), "Completed PSOLA pitch shifting");
     output
@@ -139,6 +384,7 @@ pub fn psola(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audio::sine_wave;
 
     struct DummyPYIN {
         f0: Vec<f32>,
@@ -177,7 +423,7 @@ mod tests {
         let voiced_flag = vec![true; 5];
         let pyin = DummyPYIN::new(f0, voiced_flag).as_pyin_data();
 
-        let marks = find_pitch_marks(&pyin, sample_rate);
+        let marks = find_pitch_marks(&pyin, sample_rate, FRAME_LENGTH, HOP_LENGTH);
         assert!(!marks.is_empty());
 
         let period = (sample_rate as f32 / 100.0).round() as usize;
@@ -186,6 +432,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_psola_config_overrides_frame_size_in_pitch_marks() {
+        let sample_rate = 1000;
+        let pyin = DummyPYIN::new(vec![100.0], vec![true]).as_pyin_data();
+
+        let default_config = PsolaConfig::default();
+        let default_marks = find_pitch_marks(
+            &pyin,
+            sample_rate,
+            default_config.frame_size,
+            default_config.hop_size,
+        );
+
+        let config = PsolaConfig {
+            frame_size: 100,
+            ..Default::default()
+        };
+        let marks = find_pitch_marks(&pyin, sample_rate, config.frame_size, config.hop_size);
+
+        // A single voiced frame only gets marks within its frame_size samples
+        assert_eq!(marks.len(), 10);
+        assert!(marks.iter().all(|&m| m < config.frame_size));
+        assert!(default_marks.len() > marks.len());
+        assert!(*default_marks.last().unwrap() >= config.frame_size);
+    }
+
+    #[test]
+    fn test_psola_with_config_matches_psola_wrapper() {
+        let audio: Vec<f32> = (0..(FRAME_LENGTH * 4)).map(|x| (x as f32).sin()).collect();
+        let f0 = vec![100.0; 10];
+        let pyin = DummyPYIN::new(f0.clone(), vec![true; 10]).as_pyin_data();
+
+        let config = PsolaConfig {
+            frame_size: 512,
+            hop_size: 128,
+            ..Default::default()
+        };
+        let from_config = psola_with_config(&audio, 44100, &pyin, &f0, &config);
+        let from_wrapper = psola(&audio, 44100, &pyin, &f0, Some(512), Some(128));
+        assert_eq!(from_config, from_wrapper);
+    }
+
     #[test]
     fn test_compute_target_pitch_spacing_identity_when_same_f0() {
         let f0 = vec![100.0; 4];
@@ -195,7 +483,7 @@ mod tests {
         let pitch_marks = vec![0, 100, 200, 300];
         let target_f0 = f0;
 
-        let shifted = compute_target_pitch_spacing(&pyin, &target_f0, &pitch_marks);
+        let shifted = compute_target_pitch_spacing(&pyin, &target_f0, &pitch_marks, HOP_LENGTH);
         assert_eq!(shifted, pitch_marks);
     }
 
@@ -209,12 +497,28 @@ mod tests {
         // Double the pitch
         let target_f0 = vec![200.0; 4];
 
-        let shifted = compute_target_pitch_spacing(&pyin, &target_f0, &pitch_marks);
+        let shifted = compute_target_pitch_spacing(&pyin, &target_f0, &pitch_marks, HOP_LENGTH);
         assert_eq!(shifted.len(), pitch_marks.len());
         // Spacing should be roughly halved between marks
         assert!(shifted[1] - shifted[0] < pitch_marks[1] - pitch_marks[0]);
     }
 
+    #[test]
+    fn test_synthesis_marks_repeat_target_spacing_over_the_same_span() {
+        let f0 = vec![100.0; 4];
+        let pyin = DummyPYIN::new(f0, vec![true; 4]).as_pyin_data();
+        let pitch_marks = vec![0, 100, 200, 300];
+
+        // An octave up: twice the marks, half as far apart, ending where the input does
+        let shifted = compute_target_pitch_spacing(&pyin, &[200.0; 4], &pitch_marks, HOP_LENGTH);
+        let marks = synthesis_marks(&pitch_marks, &shifted);
+        assert_eq!(marks, (0..=6).map(|k| k * 50).collect::<Vec<_>>());
+
+        // Unshifted marks come back as they were
+        let shifted = compute_target_pitch_spacing(&pyin, &[100.0; 4], &pitch_marks, HOP_LENGTH);
+        assert_eq!(synthesis_marks(&pitch_marks, &shifted), pitch_marks);
+    }
+
     #[test]
     fn test_overlap_add_no_panics_and_nonzero_output() {
         let audio: Vec<f32> = (0..200).map(|x| x as f32).collect();
@@ -222,7 +526,15 @@ mod tests {
         let shifted_marks = pitch_marks.clone();
         let frame_size = 32;
 
-        let out = overlap_add(&audio, &pitch_marks, &shifted_marks, frame_size);
+        let out = overlap_add(
+            &audio,
+            &pitch_marks,
+            &shifted_marks,
+            frame_size,
+            None,
+            WindowKind::Hann,
+            false,
+        );
         assert!(!out.is_empty());
         // Hann windowing should produce non-zero energy near marks
         for &pm in &pitch_marks {
@@ -231,6 +543,146 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_overlap_add_with_each_window() {
+        let audio: Vec<f32> = (0..400).map(|x| (x as f32 * 0.1).sin()).collect();
+        let pitch_marks = vec![50, 100, 150, 200, 250, 300];
+        let shifted_marks = pitch_marks.clone();
+        for window in [
+            WindowKind::Hann,
+            WindowKind::Hamming,
+            WindowKind::Blackman,
+            WindowKind::Rectangular,
+        ] {
+            let out = overlap_add(
+                &audio,
+                &pitch_marks,
+                &shifted_marks,
+                100,
+                None,
+                window,
+                false,
+            );
+            assert!(!out.is_empty());
+            assert!(out.iter().all(|s| s.is_finite()));
+            // Grains overlap by half, so the middle of the signal is reconstructed
+            assert!(out[150..250].iter().any(|&s| s != 0.0), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn test_grain_periods_shorten_grains_for_high_pitch() {
+        let sample_rate = 16000;
+        let grain_sizes = |f0: f32, grain_periods: Option<f32>| -> Vec<usize> {
+            let pyin = DummyPYIN::new(vec![f0; 4], vec![true; 4]).as_pyin_data();
+            let marks = find_pitch_marks(&pyin, sample_rate, FRAME_LENGTH, HOP_LENGTH);
+            (0..marks.len())
+                .map(|i| grain_size(&marks, i, FRAME_LENGTH, grain_periods))
+                .collect()
+        };
+
+        // Without grain_periods every grain is frame_size long, whatever the pitch
+        assert!(grain_sizes(800.0, None).iter().all(|&g| g == FRAME_LENGTH));
+
+        let high = grain_sizes(800.0, Some(2.0));
+        let low = grain_sizes(100.0, Some(2.0));
+        assert!(high.iter().all(|&g| g.abs_diff(40) <= 2), "{:?}", high);
+        assert!(low.iter().all(|&g| g.abs_diff(320) <= 2), "{:?}", low);
+        assert!(high.iter().max() < low.iter().min());
+    }
+
+    #[test]
+    fn test_refined_grain_placement_smooths_steady_sine() {
+        // 200 Hz at 16 kHz: marks every period, re-spaced by one and a half periods so
+        // neighbouring naive grains land in antiphase
+        let period = 80;
+        let audio: Vec<f32> = (0..4000)
+            .map(|n| (2.0 * std::f32::consts::PI * n as f32 / period as f32).sin())
+            .collect();
+        let pitch_marks: Vec<usize> = (1..30).map(|k| k * period).collect();
+        let shifted_marks: Vec<usize> = (1..30).map(|k| k * 120).collect();
+        // Mean phase jump of the 200 Hz component from one period-long frame to the next:
+        // grains adding up in phase keep it near zero, out of phase it flips
+        let discontinuity = |out: &[f32]| {
+            let phases: Vec<f32> = out[400..3600]
+                .chunks_exact(period)
+                .map(|frame| {
+                    let (mut sin, mut cos) = (0.0f32, 0.0f32);
+                    for (n, &s) in frame.iter().enumerate() {
+                        let angle = 2.0 * std::f32::consts::PI * n as f32 / period as f32;
+                        sin += s * angle.sin();
+                        cos += s * angle.cos();
+                    }
+                    cos.atan2(sin)
+                })
+                .collect();
+            let jumps: f32 = phases
+                .windows(2)
+                .map(|w| {
+                    let jump = (w[1] - w[0]).rem_euclid(2.0 * std::f32::consts::PI);
+                    jump.min(2.0 * std::f32::consts::PI - jump)
+                })
+                .sum();
+            jumps / (phases.len() - 1) as f32
+        };
+
+        let naive = overlap_add(
+            &audio,
+            &pitch_marks,
+            &shifted_marks,
+            160,
+            None,
+            WindowKind::Hann,
+            false,
+        );
+        let refined = overlap_add(
+            &audio,
+            &pitch_marks,
+            &shifted_marks,
+            160,
+            None,
+            WindowKind::Hann,
+            true,
+        );
+        assert!(refined.iter().all(|s| s.is_finite()));
+        assert!(
+            discontinuity(&refined) < 0.25 * discontinuity(&naive),
+            "{} vs {}",
+            discontinuity(&refined),
+            discontinuity(&naive)
+        );
+    }
+
+    #[test]
+    fn test_snapped_pitch_marks_land_on_zero_crossings() {
+        let sample_rate = 16000;
+        let freq = 110.0;
+        // Start 12 samples (about half a radian) into the cycle, so the unsnapped marks all
+        // sit ~12 samples after a crossing, well within the quarter period (~36 samples)
+        // snapping searches
+        let audio = sine_wave(freq, sample_rate, 8012)[12..].to_vec();
+        let n_frames = (audio.len() - FRAME_LENGTH) / HOP_LENGTH + 1;
+        let pyin = DummyPYIN::new(vec![freq; n_frames], vec![true; n_frames]).as_pyin_data();
+
+        let marks = find_pitch_marks(&pyin, sample_rate, FRAME_LENGTH, HOP_LENGTH);
+        assert!(marks.iter().all(|&m| audio[m].abs() > 0.3));
+
+        let snapped = snap_marks_to_zero_crossings(&audio, &marks, &pyin, sample_rate, HOP_LENGTH);
+        assert!(!snapped.is_empty());
+        // One sample step of a 110 Hz sine at 16 kHz is ~0.043, so a crossing is within half that
+        let epsilon = 0.025;
+        // The first mark's crossing lies before the start of the signal
+        for &m in snapped.iter().filter(|&&m| m > 36) {
+            assert!(
+                audio[m].abs() < epsilon,
+                "mark {} has sample {}",
+                m,
+                audio[m]
+            );
+        }
+        assert!(snapped.windows(2).all(|w| w[0] < w[1]));
+    }
+
     #[test]
     fn test_psola_handles_empty_inputs() {
         let audio = Vec::new();
@@ -241,6 +693,16 @@ mod tests {
         assert!(out.is_empty());
     }
 
+    #[test]
+    fn test_psola_rejects_invalid_frame_hop() {
+        let audio: Vec<f32> = (0..(FRAME_LENGTH * 4)).map(|x| (x as f32).sin()).collect();
+        let pyin = DummyPYIN::new(vec![100.0; 10], vec![true; 10]).as_pyin_data();
+        let target_f0 = vec![120.0; 10];
+
+        assert!(psola(&audio, 44100, &pyin, &target_f0, None, Some(0)).is_empty());
+        assert!(psola(&audio, 44100, &pyin, &target_f0, Some(128), Some(256)).is_empty());
+    }
+
     #[test]
     fn test_psola_runs_with_simple_constant_pitch() {
         let audio: Vec<f32> = (0..(FRAME_LENGTH * 4)).map(|x| (x as f32).sin()).collect();
@@ -252,4 +714,19 @@ mod tests {
         let out = psola(&audio, 44100, &pyin, &target_f0, None, None);
         assert!(!out.is_empty());
     }
+
+    #[test]
+    fn test_psola_output_stays_finite_for_tiny_f0() {
+        let audio: Vec<f32> = (0..(FRAME_LENGTH * 4)).map(|x| (x as f32).sin()).collect();
+        let mut f0 = vec![100.0; 10];
+        f0[3] = f32::EPSILON;
+        f0[6] = f32::MIN_POSITIVE / 4.0; // denormal, 220 / f0 overflows to Inf
+        let pyin = DummyPYIN::new(f0, vec![true; 10]).as_pyin_data();
+        let mut target_f0 = vec![220.0; 10];
+        target_f0[8] = f32::NAN;
+
+        let out = psola(&audio, 44100, &pyin, &target_f0, None, None);
+        assert!(!out.is_empty());
+        assert!(out.iter().all(|s| s.is_finite()));
+    }
 }
@@ -1,13 +1,22 @@
 use crate::audio::autotune::{
-    FRAME_LENGTH, HOP_LENGTH, MAX_F0, MIN_F0, PYIN_SIGMA, PYIN_THRESHOLD,
+    FRAME_LENGTH, HOP_LENGTH, MAX_F0, MIN_F0, OCTAVE_CORRECTION_CONTEXT, OCTAVE_CORRECTION_MAX_RUN,
+    OCTAVE_CORRECTION_TOLERANCE, PYIN_OCTAVE_GUARD, PYIN_SIGMA, PYIN_SILENCE_RATIO, PYIN_THRESHOLD,
+    validate_frame_hop,
 };
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use tracing::{debug, error};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PYINData {
     f0: Vec<f32>,
     voiced_flag: Vec<bool>,
     voiced_prob: Vec<f32>,
+    /// `Audio::analysis_generation` of the samples this was computed from. Only meaningful
+    /// while attached to that Audio, so it isn't cached to disk.
+    #[serde(skip)]
+    generation: u64,
 }
 
 impl PYINData {
@@ -16,8 +25,18 @@ impl PYINData {
             f0,
             voiced_flag,
             voiced_prob,
+            generation: 0,
         }
     }
+
+    /// Generation of the samples this data was computed from, see `Audio::pyin_is_current`.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
     pub fn f0(&self) -> &Vec<f32> {
         &self.f0
     }
@@ -29,6 +48,52 @@ impl PYINData {
     pub fn voiced_prob(&self) -> &Vec<f32> {
         &self.voiced_prob
     }
+
+    /// Mutable access for in-place edits. Keep the three tracks the same length.
+    pub fn f0_mut(&mut self) -> &mut Vec<f32> {
+        &mut self.f0
+    }
+
+    pub fn voiced_flag_mut(&mut self) -> &mut Vec<bool> {
+        &mut self.voiced_flag
+    }
+
+    pub fn voiced_prob_mut(&mut self) -> &mut Vec<f32> {
+        &mut self.voiced_prob
+    }
+
+    /// Replaces the f0 of every voiced frame with `f(frame_index, f0)`.
+    /// Unvoiced frames are left untouched.
+    pub fn map_voiced(&mut self, f: impl Fn(usize, f32) -> f32) {
+        for (i, (value, &voiced)) in self.f0.iter_mut().zip(&self.voiced_flag).enumerate() {
+            if voiced {
+                *value = f(i, *value);
+            }
+        }
+    }
+
+    /// Overwrites the frames starting at `first_frame` with the frames of `region`.
+    /// The tracks grow with unvoiced frames if `region` reaches past their end.
+    pub fn splice_frames(&mut self, first_frame: usize, region: &PYINData) {
+        let end = first_frame + region.f0.len();
+        if self.f0.len() < end {
+            self.f0.resize(end, 0.0);
+            self.voiced_flag.resize(end, false);
+            self.voiced_prob.resize(end, 0.0);
+        }
+        self.f0[first_frame..end].copy_from_slice(&region.f0);
+        self.voiced_flag[first_frame..end].copy_from_slice(&region.voiced_flag);
+        self.voiced_prob[first_frame..end].copy_from_slice(&region.voiced_prob);
+    }
+
+    /// Removes `frames` (clamped to the length of the tracks), the frames after them move up.
+    pub fn remove_frames(&mut self, frames: std::ops::Range<usize>) {
+        let end = frames.end.min(self.f0.len());
+        let start = frames.start.min(end);
+        self.f0.drain(start..end);
+        self.voiced_flag.drain(start..end);
+        self.voiced_prob.drain(start..end);
+    }
 }
 
 /// Simple RMS energy of a frame, used for voicing / silence detection.
@@ -83,6 +148,54 @@ fn parabolic_interp(cmnd: &[f32], tau: usize) -> f32 {
     }
 }
 
+/// Fractional lags per sample evaluated by `refine_lag`
+const SUB_LAG_STEPS: usize = 16;
+
+/// Difference function at the fractional lag `tau`, reading the delayed frame by linear
+/// interpolation between samples. Only the first `n_terms` squared differences are
+/// summed, so neighbouring lags are compared over the same samples.
+fn fractional_difference(frame: &[f32], tau: f32, n_terms: usize) -> f32 {
+    let whole = tau.floor() as usize;
+    let frac = tau - whole as f32;
+    (0..n_terms)
+        .map(|i| {
+            let delayed = frame[i + whole] * (1.0 - frac) + frame[i + whole + 1] * frac;
+            let diff = frame[i] - delayed;
+            diff * diff
+        })
+        .sum()
+}
+
+/// Refines the integer lag `tau` of a period estimate: the difference function is
+/// evaluated every 1/`SUB_LAG_STEPS` of a sample within one lag of `tau`, and the best
+/// of those is interpolated parabolically. Much finer than `parabolic_interp` on the
+/// integer lags, which matters most for long periods (low notes).
+fn refine_lag(frame: &[f32], tau: usize) -> f32 {
+    if tau < 2 {
+        return tau as f32;
+    }
+    let n_terms = frame.len().saturating_sub(tau + 2);
+    let step = 1.0 / SUB_LAG_STEPS as f32;
+    let lags: Vec<f32> = (0..=2 * SUB_LAG_STEPS)
+        .map(|k| (tau - 1) as f32 + k as f32 * step)
+        .collect();
+    let d: Vec<f32> = lags
+        .iter()
+        .map(|&lag| fractional_difference(frame, lag, n_terms))
+        .collect();
+    let best = (1..d.len() - 1)
+        .min_by(|&a, &b| d[a].total_cmp(&d[b]))
+        .unwrap_or(SUB_LAG_STEPS);
+    let (y0, y1, y2) = (d[best - 1], d[best], d[best + 1]);
+    let denom = y0 - 2.0 * y1 + y2;
+    let offset = if denom.abs() < 1e-12 {
+        0.0
+    } else {
+        0.5 * (y0 - y2) / denom
+    };
+    lags[best] + offset * step
+}
+
 fn find_pitch_candidates(
     cmnd: &[f32],
     threshold: f32,
@@ -119,11 +232,15 @@ fn find_pitch_candidates(
     }
 }
 
+/// Picks the candidate with the best probability weighted by its continuity with
+/// `previous_f0`. With `octave_guard` set to (min, max), candidates whose ratio to
+/// `previous_f0` falls outside it are skipped outright.
 fn probabilistic_f0_selection(
     f0_candidates: &[f32],
     candidate_probs: &[f32],
     sigma: f32,
     previous_f0: Option<f32>,
+    octave_guard: Option<(f32, f32)>,
 ) -> (f32, bool, f32) {
     if f0_candidates.is_empty() {
         return (0.0, false, 0.0);
@@ -136,10 +253,10 @@ fn probabilistic_f0_selection(
         let candidate = f0_candidates[i];
 
         // Hard octave / subharmonic guard
-        if let Some(pf0) = previous_f0 {
+        if let (Some(pf0), Some((min_ratio, max_ratio))) = (previous_f0, octave_guard) {
             if pf0 > 0.0 {
                 let ratio = candidate / pf0;
-                if ratio < 0.7 || ratio > 1.5 {
+                if ratio < min_ratio || ratio > max_ratio {
                     continue;
                 }
             }
@@ -167,6 +284,203 @@ fn probabilistic_f0_selection(
     (f0_candidates[best_f0_i], voiced_flag, best_score)
 }
 
+/// How the signal is extended past its ends when `PyinConfig::center` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PadMode {
+    /// Silence
+    #[default]
+    Zero,
+    /// The signal mirrored around its first and last sample
+    Reflect,
+    /// The first and last sample repeated
+    Edge,
+}
+
+/// Extends `signal` by `pad` samples on both ends according to `mode`.
+fn pad_signal(signal: &[f32], pad: usize, mode: PadMode) -> Vec<f32> {
+    let n = signal.len();
+    if n == 0 {
+        return vec![0.0; 2 * pad];
+    }
+    // Sample used for position `i` of the padded signal, `i - pad` in the original
+    let source = |i: isize| -> f32 {
+        if (0..n as isize).contains(&i) {
+            return signal[i as usize];
+        }
+        match mode {
+            PadMode::Zero => 0.0,
+            PadMode::Edge => signal[i.clamp(0, n as isize - 1) as usize],
+            PadMode::Reflect if n == 1 => signal[0],
+            PadMode::Reflect => {
+                // Reflection without repeating the edge sample has period 2 * (n - 1)
+                let period = 2 * (n as isize - 1);
+                let i = i.rem_euclid(period);
+                signal[if i < n as isize { i } else { period - i } as usize]
+            }
+        }
+    };
+    (0..n + 2 * pad)
+        .map(|i| source(i as isize - pad as isize))
+        .collect()
+}
+
+/// Parameters for a PYIN analysis run.
+/// Use `PyinConfig::default()` and override the fields you need.
+#[derive(Debug, Clone)]
+pub struct PyinConfig {
+    pub frame_length: usize,
+    pub hop_length: usize,
+    pub fmin: f32,
+    pub fmax: f32,
+    pub threshold: f32,
+    pub sigma: f32,
+    /// Frames quieter than this fraction of the whole signal's RMS are treated as silence
+    pub silence_ratio: f32,
+    /// Absolute RMS below which frames are treated as silence, overrides `silence_ratio`
+    pub silence_floor: Option<f32>,
+    /// Bounds (min, max) on the ratio of a candidate's f0 to the previous frame's; others
+    /// are never picked. Widen them (or use None) to follow large leaps such as octaves.
+    pub octave_guard: Option<(f32, f32)>,
+    /// Run `octave_correct` on the f0 track after detection
+    pub octave_correction: bool,
+    /// Cutoff in Hz of a one-pole high-pass applied before analysis to remove DC offset
+    /// and rumble, None (the default) to analyze the raw signal. `PYIN_HIGHPASS_CUTOFF`
+    /// suits voice recordings.
+    pub highpass_cutoff: Option<f32>,
+    /// Pad the signal by half a frame on both ends so frame i is centered on sample
+    /// `i * hop_length` (librosa's `center=True`) instead of starting there
+    pub center: bool,
+    /// How the signal is padded when `center` is set
+    pub pad_mode: PadMode,
+    /// Refine each candidate's period with a fractional lag search (`refine_lag`) instead
+    /// of only interpolating the integer lags. Slower, more precise for low notes.
+    pub sub_lag_refinement: bool,
+    /// Receives the analysis progress as a percentage (0-100) while frames are processed
+    pub progress: Option<Arc<AtomicU32>>,
+    /// Checked before every frame; once set, analysis stops and the frames done so far
+    /// are returned
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for PyinConfig {
+    fn default() -> Self {
+        Self {
+            frame_length: FRAME_LENGTH,
+            hop_length: HOP_LENGTH,
+            fmin: MIN_F0,
+            fmax: MAX_F0,
+            threshold: PYIN_THRESHOLD,
+            sigma: PYIN_SIGMA,
+            silence_ratio: PYIN_SILENCE_RATIO,
+            silence_floor: None,
+            octave_guard: Some(PYIN_OCTAVE_GUARD),
+            octave_correction: false,
+            highpass_cutoff: None,
+            center: false,
+            pad_mode: PadMode::Zero,
+            sub_lag_refinement: false,
+            progress: None,
+            cancel: None,
+        }
+    }
+}
+
+/// One-pole (RC) high-pass filter. The filter starts from the first sample, so a
+/// constant offset is removed from the very start instead of decaying away.
+fn highpass(signal: &[f32], sample_rate: u32, cutoff: f32) -> Vec<f32> {
+    let Some(&first) = signal.first() else {
+        return Vec::new();
+    };
+    let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+    let dt = 1.0 / sample_rate as f32;
+    let alpha = rc / (rc + dt);
+
+    let mut output = Vec::with_capacity(signal.len());
+    let mut previous_in = first;
+    let mut previous_out = 0.0;
+    for &x in signal {
+        previous_out = alpha * (previous_out + x - previous_in);
+        previous_in = x;
+        output.push(previous_out);
+    }
+    output
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Fixes short runs of voiced frames that jumped by whole octaves (usually to a
+/// subharmonic) relative to the frames around them.
+/// A run is a group of consecutive voiced frames without a jump of more than half an
+/// octave. Runs no longer than OCTAVE_CORRECTION_MAX_RUN frames whose median is within
+/// OCTAVE_CORRECTION_TOLERANCE octaves of a whole number of octaves away from the
+/// median of the surrounding voiced frames are shifted back into line.
+pub fn octave_correct(f0: &mut [f32], voiced: &[bool]) {
+    let n = f0.len().min(voiced.len());
+    let is_voiced: Vec<bool> = (0..n).map(|i| voiced[i] && f0[i] > 0.0).collect();
+
+    // Split voiced frames into runs of stable pitch
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for i in 0..n {
+        if !is_voiced[i] {
+            if let Some(start) = run_start.take() {
+                runs.push((start, i));
+            }
+            continue;
+        }
+        match run_start {
+            Some(start) if (f0[i] / f0[i - 1]).log2().abs() > 0.5 => {
+                runs.push((start, i));
+                run_start = Some(i);
+            }
+            Some(_) => {}
+            None => run_start = Some(i),
+        }
+    }
+    if let Some(start) = run_start {
+        runs.push((start, n));
+    }
+
+    for (start, end) in runs {
+        if end - start > OCTAVE_CORRECTION_MAX_RUN {
+            continue;
+        }
+        let context_start = start.saturating_sub(OCTAVE_CORRECTION_CONTEXT);
+        let context_end = (end + OCTAVE_CORRECTION_CONTEXT).min(n);
+        let mut context: Vec<f32> = (context_start..start)
+            .chain(end..context_end)
+            .filter(|&i| is_voiced[i])
+            .map(|i| f0[i])
+            .collect();
+        // Only correct runs that are shorter than the context they are compared to
+        if context.len() <= end - start {
+            continue;
+        }
+        let mut run: Vec<f32> = f0[start..end].to_vec();
+        let octaves = (median(&mut run) / median(&mut context)).log2();
+        let whole_octaves = octaves.round();
+        if whole_octaves == 0.0 || (octaves - whole_octaves).abs() > OCTAVE_CORRECTION_TOLERANCE {
+            continue;
+        }
+        debug!(start, end, whole_octaves, "Correcting octave jump in f0");
+        let factor = 2f32.powf(-whole_octaves);
+        for value in &mut f0[start..end] {
+            *value *= factor;
+        }
+    }
+}
+
 pub fn pyin(
     signal: &[f32],
     sample_rate: u32,
@@ -177,25 +491,63 @@ pub fn pyin(
     threshold: Option<f32>,
     sigma: Option<f32>,
 ) -> PYINData {
-    let frame_length = frame_length.unwrap_or(FRAME_LENGTH);
-    let hop_length = hop_length.unwrap_or(HOP_LENGTH);
-    let fmin = fmin.unwrap_or(MIN_F0);
-    let fmax = fmax.unwrap_or(MAX_F0);
+    let defaults = PyinConfig::default();
+    let config = PyinConfig {
+        frame_length: frame_length.unwrap_or(defaults.frame_length),
+        hop_length: hop_length.unwrap_or(defaults.hop_length),
+        fmin: fmin.unwrap_or(defaults.fmin),
+        fmax: fmax.unwrap_or(defaults.fmax),
+        threshold: threshold.unwrap_or(defaults.threshold),
+        sigma: sigma.unwrap_or(defaults.sigma),
+        ..defaults
+    };
+    pyin_with_config(signal, sample_rate, &config)
+}
+
+pub fn pyin_with_config(signal: &[f32], sample_rate: u32, config: &PyinConfig) -> PYINData {
+    let frame_length = config.frame_length;
+    let hop_length = config.hop_length;
+    let fmin = config.fmin;
+    let fmax = config.fmax;
     let min_lag = (sample_rate as f32 / fmax).floor() as usize;
     let max_lag = (sample_rate as f32 / fmin).ceil() as usize;
-    let threshold = threshold.unwrap_or(PYIN_THRESHOLD);
-    let sigma = sigma.unwrap_or(PYIN_SIGMA);
+    let threshold = config.threshold;
+    let sigma = config.sigma;
     debug!(
         frame_length,
         hop_length, fmin, fmax, min_lag, max_lag, threshold, sigma, "PYIN parameters"
     );
 
-    if signal.len() < frame_length {
-        return PYINData {
-            f0: Vec::new(),
-            voiced_flag: Vec::new(),
-            voiced_prob: Vec::new(),
-        };
+    if let Some(progress) = &config.progress {
+        progress.store(0, Ordering::Relaxed);
+    }
+
+    let filtered;
+    let signal = match config.highpass_cutoff {
+        Some(cutoff) if cutoff > 0.0 => {
+            filtered = highpass(signal, sample_rate, cutoff);
+            &filtered[..]
+        }
+        _ => signal,
+    };
+    // The silence threshold is taken from the signal itself, not the padding
+    let unpadded = signal;
+    let padded;
+    let signal = if config.center {
+        padded = pad_signal(signal, frame_length / 2, config.pad_mode);
+        &padded[..]
+    } else {
+        signal
+    };
+
+    let invalid = validate_frame_hop(frame_length, hop_length)
+        .inspect_err(|e| error!("Skipping PYIN: {}", e))
+        .is_err();
+    if invalid || signal.len() < frame_length {
+        if let Some(progress) = &config.progress {
+            progress.store(100, Ordering::Relaxed);
+        }
+        return PYINData::new(Vec::new(), Vec::new(), Vec::new());
     }
 
     let n_frames = (signal.len() - frame_length) / hop_length + 1;
@@ -205,10 +557,25 @@ pub fn pyin(
     let mut voiced_prob = vec![0.0; n_frames];
     let mut previous_f0: Option<f32> = None;
 
-    // Simple global RMS to derive a silence threshold.
-    let global_rms = frame_rms(signal);
-    let silence_rms_threshold = global_rms * 0.02 + 1e-6;
+    // Simple global RMS to derive a silence threshold, unless an absolute floor is given.
+    let silence_rms_threshold = match config.silence_floor {
+        Some(floor) => floor,
+        None => frame_rms(unpadded) * config.silence_ratio + 1e-6,
+    };
     for i in 0..n_frames {
+        if let Some(cancel) = &config.cancel
+            && cancel.load(Ordering::Relaxed)
+        {
+            debug!(frame = i, n_frames, "PYIN analysis cancelled");
+            f0.truncate(i);
+            voiced_flag.truncate(i);
+            voiced_prob.truncate(i);
+            return PYINData::new(f0, voiced_flag, voiced_prob);
+        }
+        if let Some(progress) = &config.progress {
+            // fetch_max so parallel channel analyses sharing one counter never go backwards
+            progress.fetch_max((i * 100 / n_frames) as u32, Ordering::Relaxed);
+        }
         let start = i * hop_length;
         let end = start + frame_length;
         let frame = &signal[start..end];
@@ -233,10 +600,21 @@ pub fn pyin(
 
         let d = difference_function(frame, max_lag);
         let cmnd = cumulative_mean_normalized_difference(&d, max_lag);
-        let (f0_candidates, candidate_probs) =
+        let (mut f0_candidates, candidate_probs) =
             find_pitch_candidates(&cmnd, threshold, min_lag, max_lag, sample_rate);
-        let (best_f0, is_voiced, best_prob) =
-            probabilistic_f0_selection(&f0_candidates, &candidate_probs, sigma, previous_f0);
+        if config.sub_lag_refinement {
+            for candidate in f0_candidates.iter_mut().filter(|f0| **f0 > 0.0) {
+                let tau = (sample_rate as f32 / *candidate).round() as usize;
+                *candidate = sample_rate as f32 / refine_lag(frame, tau);
+            }
+        }
+        let (best_f0, is_voiced, best_prob) = probabilistic_f0_selection(
+            &f0_candidates,
+            &candidate_probs,
+            sigma,
+            previous_f0,
+            config.octave_guard,
+        );
 
         // Additional guard: reject obviously out-of-range or unstable f0 as unvoiced.
         let mut final_f0 = best_f0;
@@ -257,24 +635,56 @@ pub fn pyin(
         voiced_prob[i] = final_prob;
     }
 
-    PYINData {
-        f0,
-        voiced_flag,
-        voiced_prob,
+    if config.octave_correction {
+        octave_correct(&mut f0, &voiced_flag);
+    }
+    if let Some(progress) = &config.progress {
+        progress.store(100, Ordering::Relaxed);
     }
+
+    PYINData::new(f0, voiced_flag, voiced_prob)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audio::autotune::PYIN_HIGHPASS_CUTOFF;
+    use crate::audio::sine_wave;
 
-    fn sine_wave(freq: f32, sr: u32, len: usize) -> Vec<f32> {
-        (0..len)
-            .map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / sr as f32).sin())
-            .collect()
+    // -------- Low-level helpers --------
+
+    #[test]
+    fn test_map_voiced_only_touches_voiced_frames() {
+        let mut data = PYINData::new(
+            vec![0.0, 220.0, 230.0, 0.0, 110.0],
+            vec![false, true, true, false, true],
+            vec![0.0, 0.9, 0.8, 0.1, 0.7],
+        );
+        data.map_voiced(|_, f0| f0 * 2.0);
+        assert_eq!(data.f0(), &vec![0.0, 440.0, 460.0, 0.0, 220.0]);
+
+        // Manual fix: mark the last frame unvoiced, map_voiced then leaves it alone
+        data.f0_mut()[4] = 0.0;
+        data.voiced_flag_mut()[4] = false;
+        data.voiced_prob_mut()[4] = 0.0;
+        data.map_voiced(|i, f0| f0 + i as f32);
+        assert_eq!(data.f0(), &vec![0.0, 441.0, 462.0, 0.0, 0.0]);
     }
 
-    // -------- Low-level helpers --------
+    #[test]
+    fn test_splice_frames_overwrites_and_grows() {
+        let mut data = PYINData::new(vec![1.0; 4], vec![true; 4], vec![0.5; 4]);
+        let region = PYINData::new(vec![2.0, 0.0], vec![true, false], vec![0.9, 0.1]);
+        data.splice_frames(1, &region);
+        assert_eq!(data.f0(), &vec![1.0, 2.0, 0.0, 1.0]);
+        assert_eq!(data.voiced_flag(), &vec![true, true, false, true]);
+        assert_eq!(data.voiced_prob(), &vec![0.5, 0.9, 0.1, 0.5]);
+
+        data.splice_frames(5, &region);
+        assert_eq!(data.f0(), &vec![1.0, 2.0, 0.0, 1.0, 0.0, 2.0, 0.0]);
+        assert_eq!(data.voiced_flag().len(), 7);
+        assert_eq!(data.voiced_prob().len(), 7);
+    }
 
     #[test]
     fn test_frame_rms_basic_and_empty() {
@@ -349,7 +759,7 @@ mod tests {
 
     #[test]
     fn test_probabilistic_f0_selection_empty_input() {
-        let (f0, voiced, prob) = probabilistic_f0_selection(&[], &[], PYIN_SIGMA, None);
+        let (f0, voiced, prob) = probabilistic_f0_selection(&[], &[], PYIN_SIGMA, None, None);
         assert_eq!(f0, 0.0);
         assert!(!voiced);
         assert_eq!(prob, 0.0);
@@ -361,7 +771,7 @@ mod tests {
         let candidate_probs = vec![0.1, 0.8, 0.3];
 
         let (f0, voiced, prob) =
-            probabilistic_f0_selection(&f0_candidates, &candidate_probs, PYIN_SIGMA, None);
+            probabilistic_f0_selection(&f0_candidates, &candidate_probs, PYIN_SIGMA, None, None);
 
         assert_eq!(f0, 200.0);
         assert!(voiced);
@@ -375,13 +785,48 @@ mod tests {
         let candidate_probs = vec![0.6, 0.9];
         let previous_f0 = Some(100.0);
 
-        let (f0, _voiced, _prob) =
-            probabilistic_f0_selection(&f0_candidates, &candidate_probs, 0.1, previous_f0);
+        let (f0, _voiced, _prob) = probabilistic_f0_selection(
+            &f0_candidates,
+            &candidate_probs,
+            0.1,
+            previous_f0,
+            Some(PYIN_OCTAVE_GUARD),
+        );
 
         // With strong continuity penalty, should prefer 100 Hz (closer to previous_f0)
         assert_eq!(f0, 100.0);
     }
 
+    #[test]
+    fn test_octave_guard_rejects_leap_unless_widened() {
+        // A melody leaping up an octave: the only candidate is twice the previous pitch.
+        // A wide sigma keeps the continuity weight from rejecting the leap on its own.
+        let (f0_candidates, candidate_probs) = (vec![440.0], vec![0.9]);
+        let previous_f0 = Some(220.0);
+
+        let (_, voiced, prob) = probabilistic_f0_selection(
+            &f0_candidates,
+            &candidate_probs,
+            2.0,
+            previous_f0,
+            PyinConfig::default().octave_guard,
+        );
+        assert!(!voiced);
+        assert_eq!(prob, 0.0);
+
+        for guard in [Some((0.4, 2.5)), None] {
+            let (f0, voiced, _) = probabilistic_f0_selection(
+                &f0_candidates,
+                &candidate_probs,
+                2.0,
+                previous_f0,
+                guard,
+            );
+            assert_eq!(f0, 440.0);
+            assert!(voiced, "guard {:?}", guard);
+        }
+    }
+
     // -------- High-level pyin behavior --------
 
     #[test]
@@ -481,6 +926,269 @@ mod tests {
         assert!(voiced_count * 4 < total); // < 25% voiced
     }
 
+    #[test]
+    fn test_octave_correct_fixes_short_octave_blip() {
+        let mut f0 = vec![220.0; 20];
+        f0[8..11].fill(440.0);
+        let voiced = vec![true; 20];
+
+        octave_correct(&mut f0, &voiced);
+
+        for &f in &f0 {
+            assert!((f - 220.0).abs() < 1.0, "expected ~220 Hz, got {}", f);
+        }
+    }
+
+    #[test]
+    fn test_octave_correct_leaves_real_note_changes_and_unvoiced() {
+        // A sustained octave leap is a real note change, not a detection error
+        let mut f0 = vec![220.0; 40];
+        f0[20..].fill(440.0);
+        f0[5] = 0.0;
+        let mut voiced = vec![true; 40];
+        voiced[5] = false;
+        let expected = f0.clone();
+
+        octave_correct(&mut f0, &voiced);
+        assert_eq!(f0, expected);
+    }
+
+    #[test]
+    fn test_pyin_silence_ratio_controls_quiet_passages() {
+        let sr = 16000;
+        let len = sr as usize / 2;
+        // Loud sine followed by a much quieter, but still clean, sine
+        let mut signal = sine_wave(220.0, sr, len);
+        signal.extend(sine_wave(220.0, sr, len).iter().map(|x| x * 0.01));
+
+        let quiet_frames = |result: &PYINData| {
+            let first_quiet = len / HOP_LENGTH + 1;
+            result.voiced_flag()[first_quiet..].to_vec()
+        };
+
+        let config = PyinConfig {
+            fmax: 500.0,
+            ..Default::default()
+        };
+        let default_result = pyin_with_config(&signal, sr, &config);
+        assert!(quiet_frames(&default_result).iter().all(|&v| !v));
+
+        let lowered = PyinConfig {
+            silence_ratio: 0.001,
+            ..config.clone()
+        };
+        let lowered_result = pyin_with_config(&signal, sr, &lowered);
+        let voiced = quiet_frames(&lowered_result).iter().filter(|&&v| v).count();
+        assert!(voiced * 2 > quiet_frames(&lowered_result).len());
+
+        let floor = PyinConfig {
+            silence_floor: Some(1e-4),
+            ..config
+        };
+        let floor_result = pyin_with_config(&signal, sr, &floor);
+        assert_eq!(floor_result.voiced_flag(), lowered_result.voiced_flag());
+    }
+
+    #[test]
+    fn test_highpass_removes_dc_offset() {
+        let sr = 16000;
+        let signal: Vec<f32> = sine_wave(220.0, sr, sr as usize)
+            .iter()
+            .map(|x| x + 0.5)
+            .collect();
+
+        let filtered = highpass(&signal, sr, PYIN_HIGHPASS_CUTOFF);
+        assert_eq!(filtered.len(), signal.len());
+        let tail = &filtered[sr as usize / 2..];
+        let mean = tail.iter().sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.01, "mean = {mean}");
+        assert!(highpass(&[], sr, PYIN_HIGHPASS_CUTOFF).is_empty());
+    }
+
+    #[test]
+    fn test_pyin_highpass_ignores_dc_offset_and_rumble() {
+        let sr = 16000;
+        let clean = sine_wave(220.0, sr, sr as usize);
+        // DC offset plus 20 Hz rumble as loud as the sine itself
+        let rumble = sine_wave(20.0, sr, sr as usize);
+        let signal: Vec<f32> = clean
+            .iter()
+            .zip(&rumble)
+            .map(|(x, r)| 0.3 * x + 0.3 * r + 0.5)
+            .collect();
+
+        let highpass = PyinConfig {
+            highpass_cutoff: Some(PYIN_HIGHPASS_CUTOFF),
+            ..Default::default()
+        };
+        let filtered = pyin_with_config(&signal, sr, &highpass);
+        let reference = pyin_with_config(&clean, sr, &highpass);
+        assert_eq!(filtered.voiced_flag(), reference.voiced_flag());
+        for (f0, expected) in filtered.f0().iter().zip(reference.f0()) {
+            assert!(
+                (f0 - expected).abs() < 2.0,
+                "f0 = {f0}, expected {expected}"
+            );
+        }
+
+        let unfiltered = pyin_with_config(&signal, sr, &PyinConfig::default());
+        assert_ne!(unfiltered.voiced_flag(), reference.voiced_flag());
+    }
+
+    #[test]
+    fn test_pyin_progress_reaches_100() {
+        let sr = 16000;
+        let signal = sine_wave(220.0, sr, sr as usize);
+        let progress = Arc::new(AtomicU32::new(0));
+        let config = PyinConfig {
+            progress: Some(Arc::clone(&progress)),
+            ..Default::default()
+        };
+
+        let result = pyin_with_config(&signal, sr, &config);
+        assert!(result.f0().len() > 1);
+        assert_eq!(progress.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn test_pyin_returns_early_when_cancelled() {
+        let sr = 16000;
+        let signal = sine_wave(220.0, sr, sr as usize);
+        let cancel = Arc::new(AtomicBool::new(true));
+        let config = PyinConfig {
+            cancel: Some(Arc::clone(&cancel)),
+            ..Default::default()
+        };
+
+        let result = pyin_with_config(&signal, sr, &config);
+        assert!(result.f0().is_empty());
+        assert!(result.voiced_flag().is_empty());
+        assert!(result.voiced_prob().is_empty());
+
+        cancel.store(false, Ordering::Relaxed);
+        let result = pyin_with_config(&signal, sr, &config);
+        assert!(!result.f0().is_empty());
+    }
+
+    #[test]
+    fn test_pyin_config_default_matches_constants() {
+        let config = PyinConfig::default();
+        assert_eq!(config.frame_length, FRAME_LENGTH);
+        assert_eq!(config.hop_length, HOP_LENGTH);
+        assert_eq!(config.fmin, MIN_F0);
+        assert_eq!(config.fmax, MAX_F0);
+        assert!(!config.octave_correction);
+        assert_eq!(config.highpass_cutoff, None);
+        assert_eq!(config.octave_guard, Some((0.7, 1.5)));
+        assert!(!config.sub_lag_refinement);
+    }
+
+    #[test]
+    fn test_sub_lag_refinement_tightens_low_pitch_error() {
+        let sr = 16000;
+        let f0_hz = 55.0;
+        let signal = sine_wave(f0_hz, sr, sr as usize);
+        let mean_error = |sub_lag_refinement: bool| -> f32 {
+            let config = PyinConfig {
+                sub_lag_refinement,
+                ..Default::default()
+            };
+            let result = pyin_with_config(&signal, sr, &config);
+            let errors: Vec<f32> = result
+                .f0()
+                .iter()
+                .zip(result.voiced_flag())
+                .filter(|&(_, &voiced)| voiced)
+                .map(|(&f0, _)| (f0 - f0_hz).abs())
+                .collect();
+            assert!(errors.len() > result.f0().len() / 2);
+            errors.iter().sum::<f32>() / errors.len() as f32
+        };
+
+        let (plain, refined) = (mean_error(false), mean_error(true));
+        assert!(refined < plain / 2.0, "{} vs {}", refined, plain);
+        assert!(refined < 0.001, "{}", refined);
+    }
+
+    #[test]
+    fn test_pad_signal_modes() {
+        let signal = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(
+            pad_signal(&signal, 2, PadMode::Zero),
+            [0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            pad_signal(&signal, 2, PadMode::Edge),
+            [1.0, 1.0, 1.0, 2.0, 3.0, 4.0, 4.0, 4.0]
+        );
+        assert_eq!(
+            pad_signal(&signal, 2, PadMode::Reflect),
+            [3.0, 2.0, 1.0, 2.0, 3.0, 4.0, 3.0, 2.0]
+        );
+        // Padding longer than the signal keeps reflecting
+        assert_eq!(
+            pad_signal(&[1.0, 2.0], 3, PadMode::Reflect),
+            [2.0, 1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_pyin_center_frames_are_centered_on_hop_multiples() {
+        let sr = 16000;
+        let hop = HOP_LENGTH;
+        // Silence, then a tone from sample 32 * hop
+        let onset = 32 * hop;
+        let mut signal = vec![0.0; onset];
+        signal.extend(sine_wave(220.0, sr, 16000 - onset));
+
+        for pad_mode in [PadMode::Zero, PadMode::Reflect, PadMode::Edge] {
+            let config = PyinConfig {
+                center: true,
+                pad_mode,
+                ..PyinConfig::default()
+            };
+            let result = pyin_with_config(&signal, sr, &config);
+            assert_eq!(result.f0().len(), signal.len() / hop + 1, "{:?}", pad_mode);
+
+            // Frame i spans i * hop +- FRAME_LENGTH / 2
+            let half = FRAME_LENGTH / 2 / hop;
+            let voiced = result.voiced_flag();
+            assert!(voiced[..=32 - half].iter().all(|&v| !v), "{:?}", pad_mode);
+            let last_voiced = signal.len() / hop - half;
+            assert!(
+                voiced[32 + half..last_voiced].iter().all(|&v| v),
+                "{:?}",
+                pad_mode
+            );
+            assert!((result.f0()[32 + half] - 220.0).abs() < 10.0);
+        }
+
+        // Without centering, frame i starts at i * hop instead
+        let uncentered = pyin_with_config(&signal, sr, &PyinConfig::default());
+        assert_eq!(
+            uncentered.f0().len(),
+            (signal.len() - FRAME_LENGTH) / hop + 1
+        );
+    }
+
+    #[test]
+    fn test_pyin_rejects_invalid_frame_hop() {
+        let signal = vec![0.1; FRAME_LENGTH * 2];
+        for (frame_length, hop_length) in [(FRAME_LENGTH, 0), (256, 512)] {
+            let result = pyin(
+                &signal,
+                16000,
+                Some(frame_length),
+                Some(hop_length),
+                None,
+                None,
+                None,
+                None,
+            );
+            assert!(result.f0().is_empty());
+        }
+    }
+
     #[test]
     fn test_pyin_constants_are_sane() {
         assert!(MIN_F0 > 0.0);
@@ -491,4 +1199,3 @@ mod tests {
         assert!(PYIN_SIGMA > 0.0);
     }
 }
-
@@ -0,0 +1,136 @@
+use crate::audio::autotune::pyin::{PYINData, PyinConfig};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Analysis results stored next to an audio file, valid while the file and the analysis
+/// parameters stay the same
+#[derive(Debug, Serialize, Deserialize)]
+struct PyinCacheEntry {
+    file_hash: u64,
+    params: String,
+    data: PYINData,
+}
+
+/// Sidecar cache path of `file`, i.e. `<file>.pyin.json`
+pub fn cache_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".pyin.json");
+    PathBuf::from(name)
+}
+
+/// 64-bit FNV-1a hash of the file contents. Stable across builds, unlike `DefaultHasher`.
+fn file_hash(file: &Path) -> Result<u64> {
+    let bytes = fs::read(file)?;
+    Ok(bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    }))
+}
+
+/// Everything in `config` that changes the analysis result. Progress and cancel handles
+/// don't, so they are left out.
+fn params_key(config: &PyinConfig) -> String {
+    format!(
+        "frame_length={} hop_length={} fmin={} fmax={} threshold={} sigma={} \
+         silence_ratio={} silence_floor={:?} octave_guard={:?} octave_correction={} \
+         highpass_cutoff={:?} center={} pad_mode={:?} sub_lag_refinement={}",
+        config.frame_length,
+        config.hop_length,
+        config.fmin,
+        config.fmax,
+        config.threshold,
+        config.sigma,
+        config.silence_ratio,
+        config.silence_floor,
+        config.octave_guard,
+        config.octave_correction,
+        config.highpass_cutoff,
+        config.center,
+        config.pad_mode,
+        config.sub_lag_refinement,
+    )
+}
+
+/// PYIN data cached for `file` by `save`, if the cache exists and was made from the same
+/// file contents with the same parameters
+pub fn load(file: &Path, config: &PyinConfig) -> Option<PYINData> {
+    let path = cache_path(file);
+    let reader = BufReader::new(File::open(&path).ok()?);
+    let entry: PyinCacheEntry = match serde_json::from_reader(reader) {
+        Ok(entry) => entry,
+        Err(e) => {
+            debug!(?path, "Ignoring unreadable PYIN cache: {}", e);
+            return None;
+        }
+    };
+    if entry.params != params_key(config) {
+        debug!(?path, "PYIN cache was made with other parameters");
+        return None;
+    }
+    if entry.file_hash != file_hash(file).ok()? {
+        debug!(?path, "PYIN cache is out of date with the file");
+        return None;
+    }
+    Some(entry.data)
+}
+
+/// Writes `data`, analyzed from `file` with `config`, to the file's sidecar cache
+pub fn save(file: &Path, config: &PyinConfig, data: &PYINData) -> Result<()> {
+    let path = cache_path(file);
+    let entry = PyinCacheEntry {
+        file_hash: file_hash(file)?,
+        params: params_key(config),
+        data: data.clone(),
+    };
+    serde_json::to_writer(BufWriter::new(File::create(&path)?), &entry)?;
+    debug!(?path, n_frames = data.f0().len(), "Saved PYIN cache");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pyin_data() -> PYINData {
+        PYINData::new(
+            vec![0.0, 220.5, 221.25, 0.0],
+            vec![false, true, true, false],
+            vec![0.01, 0.97, 0.875, 0.125],
+        )
+    }
+
+    #[test]
+    fn test_pyin_data_json_roundtrip() {
+        let data = pyin_data();
+        let json = serde_json::to_string(&data).unwrap();
+        let restored: PYINData = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_cache_is_used_only_for_same_file_and_params() {
+        let file =
+            std::env::temp_dir().join(format!("autotune_pyin_cache_{}.wav", std::process::id()));
+        fs::write(&file, [1u8, 2, 3, 4]).unwrap();
+        let config = PyinConfig::default();
+        assert_eq!(load(&file, &config), None);
+
+        save(&file, &config, &pyin_data()).unwrap();
+        assert_eq!(load(&file, &config), Some(pyin_data()));
+
+        let narrower = PyinConfig {
+            fmax: 500.0,
+            ..Default::default()
+        };
+        assert_eq!(load(&file, &narrower), None);
+
+        fs::write(&file, [1u8, 2, 3, 5]).unwrap();
+        assert_eq!(load(&file, &config), None);
+
+        fs::remove_file(cache_path(&file)).unwrap();
+        fs::remove_file(&file).unwrap();
+    }
+}
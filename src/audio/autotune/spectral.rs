@@ -0,0 +1,153 @@
+use rustfft::{FftPlanner, num_complex::Complex};
+use std::f32::consts::PI;
+use tracing::debug;
+
+/// Window function applied to each frame before analysis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowKind {
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+    Rectangular,
+}
+
+impl WindowKind {
+    /// Builds a symmetric window of `length` samples.
+    pub fn window(&self, length: usize) -> Vec<f32> {
+        let mut window = vec![0.0; length];
+        self.fill(&mut window);
+        window
+    }
+
+    /// Overwrites `window` with a symmetric window of its length, without allocating.
+    pub fn fill(&self, window: &mut [f32]) {
+        if window.len() <= 1 {
+            window.fill(1.0);
+            return;
+        }
+        let denom = (window.len() - 1) as f32;
+        for (n, w) in window.iter_mut().enumerate() {
+            let x = 2.0 * PI * n as f32 / denom;
+            *w = match self {
+                WindowKind::Hann => 0.5 * (1.0 - x.cos()),
+                WindowKind::Hamming => 0.54 - 0.46 * x.cos(),
+                WindowKind::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                WindowKind::Rectangular => 1.0,
+            };
+        }
+    }
+}
+
+/// Short-time Fourier transform magnitudes of `signal`.
+/// Frames are laid out like PYIN's (frame `i` starts at `i * hop_length`, only complete
+/// frames are used) and each holds `frame_length / 2 + 1` bins from DC up to Nyquist.
+pub fn stft(
+    signal: &[f32],
+    frame_length: usize,
+    hop_length: usize,
+    window: WindowKind,
+) -> Vec<Vec<f32>> {
+    if frame_length == 0 || hop_length == 0 || signal.len() < frame_length {
+        return Vec::new();
+    }
+    let n_frames = (signal.len() - frame_length) / hop_length + 1;
+    debug!(
+        frame_length,
+        hop_length,
+        n_frames,
+        ?window,
+        "Computing STFT"
+    );
+
+    let window = window.window(frame_length);
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(frame_length);
+    let mut buffer = vec![Complex::new(0.0, 0.0); frame_length];
+
+    (0..n_frames)
+        .map(|i| {
+            let start = i * hop_length;
+            let frame = &signal[start..start + frame_length];
+            for ((b, &s), &w) in buffer.iter_mut().zip(frame).zip(&window) {
+                *b = Complex::new(s * w, 0.0);
+            }
+            fft.process(&mut buffer);
+            buffer[..frame_length / 2 + 1]
+                .iter()
+                .map(|c| c.norm())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stft_sine_peaks_in_expected_bin() {
+        let sr = 16000;
+        let frame_length = 1024;
+        // Exactly on bin 64: 64 * 16000 / 1024 = 1000 Hz
+        let freq = 1000.0;
+        let signal = crate::audio::sine_wave(freq, sr, sr as usize);
+
+        let frames = stft(&signal, frame_length, 256, WindowKind::Hann);
+        assert_eq!(frames.len(), (signal.len() - frame_length) / 256 + 1);
+        for frame in &frames {
+            assert_eq!(frame.len(), frame_length / 2 + 1);
+            let peak = frame
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(bin, _)| bin)
+                .unwrap();
+            assert_eq!(peak, 64);
+            // Energy is concentrated around the peak
+            let total: f32 = frame.iter().map(|m| m * m).sum();
+            let near: f32 = frame[62..=66].iter().map(|m| m * m).sum();
+            assert!(near / total > 0.99);
+        }
+    }
+
+    #[test]
+    fn test_stft_short_signal_is_empty() {
+        assert!(stft(&[0.0; 100], 1024, 256, WindowKind::Rectangular).is_empty());
+    }
+
+    #[test]
+    fn test_window_shapes() {
+        let endpoints = [
+            (WindowKind::Hann, 0.0),
+            (WindowKind::Hamming, 0.08),
+            (WindowKind::Blackman, 0.0),
+            (WindowKind::Rectangular, 1.0),
+        ];
+        for (kind, edge) in endpoints {
+            let window = kind.window(65);
+            assert_eq!(window.len(), 65);
+            assert!(
+                (window[0] - edge).abs() < 1e-6,
+                "{:?} starts at {}",
+                kind,
+                window[0]
+            );
+            assert!(
+                (window[64] - edge).abs() < 1e-6,
+                "{:?} ends at {}",
+                kind,
+                window[64]
+            );
+            // Symmetric, peaking at 1.0 in the middle
+            assert!(
+                (window[32] - 1.0).abs() < 1e-6,
+                "{:?} peaks at {}",
+                kind,
+                window[32]
+            );
+            for n in 0..65 {
+                assert!((window[n] - window[64 - n]).abs() < 1e-5);
+            }
+        }
+    }
+}
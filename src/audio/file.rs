@@ -4,7 +4,7 @@ use cpal::Sample;
 use hound::{WavSpec, WavWriter};
 use rodio::{Decoder, Source};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor};
 use std::path::{Path, PathBuf};
 
 /// Audio file with interleaved samples:
@@ -23,7 +23,17 @@ impl AudioFileData {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(&path)?;
         let source = Decoder::new(BufReader::new(file))?;
+        Self::from_source(source, path.as_ref().to_path_buf())
+    }
+
+    /// Like `load`, but decodes an encoded file held in memory (e.g. received over the
+    /// network). `file_path` is left empty.
+    pub fn load_from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let source = Decoder::new(Cursor::new(bytes))?;
+        Self::from_source(source, PathBuf::new())
+    }
 
+    fn from_source<S: Source>(source: S, file_path: PathBuf) -> Result<Self> {
         let sample_rate = source.sample_rate();
         let n_channels = source.channels() as usize;
 
@@ -32,7 +42,7 @@ impl AudioFileData {
         if n_channels == 0 {
             return Err(anyhow!(
                 "Decoder reported 0 channels for file {:?}",
-                path.as_ref()
+                file_path
             ));
         }
 
@@ -41,7 +51,7 @@ impl AudioFileData {
                 "Sample count {} is not divisible by channel count {} for file {:?}",
                 samples.len(),
                 n_channels,
-                path.as_ref()
+                file_path
             ));
         }
 
@@ -52,7 +62,7 @@ impl AudioFileData {
             sample_rate,
             n_samples,
             n_channels,
-            file_path: path.as_ref().to_path_buf(),
+            file_path,
         })
     }
 
@@ -85,6 +95,17 @@ impl AudioFileData {
 
     /// Save audio data to a WAV file (16-bit PCM, interleaved channels).
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_wav(path, None)
+    }
+
+    /// Like `save`, but adds TPDF dither before quantizing to 16 bits, so quiet passages
+    /// and fades don't turn into audible quantization steps. The noise comes from a
+    /// generator seeded with `seed`, so the same seed always writes the same file.
+    pub fn save_dithered<P: AsRef<Path>>(&self, path: P, seed: u64) -> Result<()> {
+        self.write_wav(path, Some(seed))
+    }
+
+    fn write_wav<P: AsRef<Path>>(&self, path: P, dither_seed: Option<u64>) -> Result<()> {
         let extension = path.as_ref().extension().and_then(|s| s.to_str());
         match extension {
             Some("wav") => {
@@ -96,11 +117,22 @@ impl AudioFileData {
                 };
 
                 let mut writer = WavWriter::create(path, spec)?;
+                let mut rng = dither_seed.map(XorShift64::new);
 
                 for &sample in &self.samples {
                     // Clamp to [-1.0, 1.0] before scaling to i16
                     let clamped = sample.clamp(-1.0, 1.0);
-                    let int_sample = (clamped * i16::MAX as f32) as i16;
+                    let int_sample = match rng.as_mut() {
+                        Some(rng) => {
+                            // Difference of two uniform values: triangular noise of ±1 LSB
+                            let noise = rng.next_f32() - rng.next_f32();
+                            (clamped * i16::MAX as f32 + noise)
+                                .round()
+                                .clamp(i16::MIN as f32, i16::MAX as f32)
+                                as i16
+                        }
+                        None => (clamped * i16::MAX as f32) as i16,
+                    };
                     writer.write_sample(int_sample)?;
                 }
 
@@ -112,7 +144,7 @@ impl AudioFileData {
     }
     pub fn to_audio(&self) -> Audio {
         if self.n_channels == 1 {
-            return Audio::new(self.sample_rate, self.samples.clone(), self.samples.clone());
+            return Audio::new_mono(self.sample_rate, self.samples.clone());
         }
         let mut left = Vec::with_capacity(self.n_samples);
         let mut right = Vec::with_capacity(self.n_samples);
@@ -124,7 +156,13 @@ impl AudioFileData {
         Audio::new(self.sample_rate, left, right)
     }
     pub fn from_audio(audio: &Audio) -> Self {
-        let n_channels = 2;
+        Self::from_audio_with_channels(audio, 2)
+    }
+
+    /// Like `from_audio`, but keeps the channel layout of the original file:
+    /// with `n_channels == 1` only the left channel is written.
+    pub fn from_audio_with_channels(audio: &Audio, n_channels: usize) -> Self {
+        let n_channels = n_channels.clamp(1, 2);
         let n_samples = audio.left().len();
         let mut samples = Vec::with_capacity(n_samples * n_channels);
 
@@ -169,3 +207,89 @@ impl AudioFileData {
         &self.samples
     }
 }
+
+/// Small deterministic xorshift64* generator used for dither noise.
+/// Not suitable for anything needing real randomness.
+struct XorShift64(u64);
+
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+        // The state must never be zero
+        let state = seed ^ MIX;
+        Self(if state == 0 { MIX } else { state })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform value in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i16(path: &Path) -> Vec<i16> {
+        hound::WavReader::open(path)
+            .unwrap()
+            .samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect()
+    }
+
+    fn temp_wav(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("autotune_{}_{}.wav", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_dither_is_non_constant_and_reproducible() {
+        // A third of an LSB: truncates to silence without dither
+        let level = 0.3 / i16::MAX as f32;
+        let file = AudioFileData::new(vec![level; 2000], 44100, 1).unwrap();
+        let (plain, first, second, other) = (
+            temp_wav("dither_plain"),
+            temp_wav("dither_a"),
+            temp_wav("dither_b"),
+            temp_wav("dither_c"),
+        );
+
+        file.save(&plain).unwrap();
+        file.save_dithered(&first, 7).unwrap();
+        file.save_dithered(&second, 7).unwrap();
+        file.save_dithered(&other, 8).unwrap();
+
+        assert!(read_i16(&plain).iter().all(|&s| s == 0));
+        let dithered = read_i16(&first);
+        assert!(dithered.iter().any(|&s| s != dithered[0]));
+        assert!(dithered.iter().all(|&s| s.abs() <= 2));
+        assert_eq!(dithered, read_i16(&second));
+        assert_ne!(dithered, read_i16(&other));
+
+        for path in [plain, first, second, other] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_from_bytes_matches_load_from_path() {
+        let bytes = include_bytes!("../../audio/undressed.wav");
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("audio/undressed.wav");
+
+        let from_bytes = AudioFileData::load_from_bytes(bytes.to_vec()).unwrap();
+        let from_path = AudioFileData::load(&path).unwrap();
+        assert_eq!(from_bytes.sample_rate, from_path.sample_rate);
+        assert_eq!(from_bytes.n_channels, from_path.n_channels);
+        assert_eq!(from_bytes.samples, from_path.samples);
+        assert!(!from_bytes.samples.is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::audio::scales::midi_note_to_frequency;
+use anyhow::{Result, bail};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::path::Path;
+use tracing::debug;
+
+/// Tempo of a MIDI file until its first tempo event: 120 BPM, in microseconds per quarter note
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// One note of a melody read from a MIDI file, timed in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MelodyNote {
+    pub start: f32,
+    pub end: f32,
+    /// MIDI note number, 69 is A4
+    pub key: u8,
+}
+
+/// A note on/off or tempo change at an absolute tick
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    NoteOn(u8),
+    NoteOff(u8),
+    Tempo(u32),
+}
+
+/// Reads a standard MIDI file (format 0 or 1) from `path`, see `parse_melody`.
+pub fn load_melody<P: AsRef<Path>>(path: P) -> Result<Vec<MelodyNote>> {
+    parse_melody(&std::fs::read(path)?)
+}
+
+/// Notes of a standard MIDI file, sorted by start time. Note events of every track and
+/// channel are merged, tempo changes are honoured. Files timed in SMPTE frames instead of
+/// ticks per quarter note are not supported.
+pub fn parse_melody(bytes: &[u8]) -> Result<Vec<MelodyNote>> {
+    let smf = Smf::parse(bytes)?;
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) if ticks.as_int() > 0 => ticks.as_int(),
+        timing => bail!("Unsupported MIDI timing {:?}", timing),
+    };
+
+    // Note on/off and tempo events of every track at absolute ticks, note ons as Some(key)
+    let mut events = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    events.push((tick, Event::Tempo(tempo.as_int())));
+                }
+                TrackEventKind::Midi { message, .. } => match message {
+                    // A note on with velocity 0 is a note off
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        events.push((tick, Event::NoteOn(key.as_int())));
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        events.push((tick, Event::NoteOff(key.as_int())));
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+    // Note offs first, so a note ending where the next starts doesn't swallow it
+    events.sort_by_key(|&(tick, event)| (tick, !matches!(event, Event::NoteOff(_))));
+
+    let mut seconds = 0.0f64;
+    let mut last_tick = 0u64;
+    let mut tempo = DEFAULT_TEMPO;
+    let mut held: Vec<(u8, f32)> = Vec::new();
+    let mut notes = Vec::new();
+    for (tick, event) in events {
+        seconds += (tick - last_tick) as f64 * tempo as f64 / 1e6 / ticks_per_beat as f64;
+        last_tick = tick;
+        let time = seconds as f32;
+        match event {
+            Event::Tempo(microseconds) => tempo = microseconds,
+            Event::NoteOn(key) => held.push((key, time)),
+            Event::NoteOff(key) => {
+                if let Some(i) = held.iter().position(|&(k, _)| k == key) {
+                    let (_, start) = held.remove(i);
+                    notes.push(MelodyNote {
+                        start,
+                        end: time,
+                        key,
+                    });
+                }
+            }
+        }
+    }
+    notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+    debug!(n_notes = notes.len(), "Parsed MIDI melody");
+    Ok(notes)
+}
+
+/// Desired F0 for `n_frames` pitch frames taken every `hop_length` samples, following
+/// `notes`: frame i (at i * hop_length / sample_rate seconds) gets the frequency of the
+/// note sounding then, rests get 0.0. Where notes overlap the latest one wins.
+pub fn melody_to_f0(
+    notes: &[MelodyNote],
+    n_frames: usize,
+    sample_rate: u32,
+    hop_length: usize,
+) -> Vec<f32> {
+    (0..n_frames)
+        .map(|i| {
+            let time = (i * hop_length) as f32 / sample_rate as f32;
+            notes
+                .iter()
+                .rev()
+                .find(|note| note.start <= time && time < note.end)
+                .map_or(0.0, |note| midi_note_to_frequency(note.key as f32))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Format 0 file at 480 ticks per quarter note and 120 BPM: C4 for a quarter note, a
+    /// half rest, then A4 for a quarter note (its note off sent with running status)
+    fn two_notes() -> Vec<u8> {
+        let track: Vec<u8> = vec![
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // Tempo 500000
+            0x00, 0x90, 60, 100, // C4 on
+            0x83, 0x60, 0x80, 60, 0, // 480 ticks later, C4 off
+            0x87, 0x40, 0x90, 69, 100, // 960 ticks later, A4 on
+            0x83, 0x60, 69, 0, // 480 ticks later, A4 off (running status, velocity 0)
+            0x00, 0xFF, 0x2F, 0x00, // End of track
+        ];
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend([0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        bytes.extend(b"MTrk");
+        bytes.extend((track.len() as u32).to_be_bytes());
+        bytes.extend(track);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_melody_reads_notes_and_timing() {
+        let notes = parse_melody(&two_notes()).unwrap();
+        assert_eq!(
+            notes,
+            [
+                MelodyNote {
+                    start: 0.0,
+                    end: 0.5,
+                    key: 60
+                },
+                MelodyNote {
+                    start: 1.5,
+                    end: 2.0,
+                    key: 69
+                },
+            ]
+        );
+        assert!(parse_melody(b"RIFF0000").is_err());
+        // SMPTE timing: 25 fps, 40 ticks per frame
+        let mut smpte = two_notes();
+        smpte[12..14].copy_from_slice(&[0xE7, 0x28]);
+        assert!(parse_melody(&smpte).is_err());
+    }
+
+    #[test]
+    fn test_melody_to_f0_follows_notes_on_the_frame_grid() {
+        let notes = parse_melody(&two_notes()).unwrap();
+        // 16 ms frames: C4 until 0.5s, a rest, A4 from 1.5s to 2.0s
+        let f0 = melody_to_f0(&notes, 140, 16000, 256);
+        let c4 = midi_note_to_frequency(60.0);
+        assert!(f0[..32].iter().all(|&f| f == c4));
+        assert!(f0[32..94].iter().all(|&f| f == 0.0));
+        assert!(f0[94..125].iter().all(|&f| f == 440.0));
+        assert!(f0[125..].iter().all(|&f| f == 0.0));
+    }
+}
@@ -1,23 +1,97 @@
 pub mod audio_controller;
 pub mod autotune;
 pub mod file;
+pub mod midi;
+pub mod monitor;
+pub mod playback;
+pub mod recorder;
 pub mod scales;
 
-use crate::audio::autotune::pyin::{self, PYINData};
-use std::sync::{Arc, RwLock};
+use crate::audio::autotune::detector::{PitchDetector, PyinDetector};
+use crate::audio::autotune::pyin::{self, PYINData, PyinConfig};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::thread;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
-/// Represents stereo audio data along with associated PYIN analysis.
+/// Decay time of the level detector in `Audio::noise_gate`. Long enough to ride over the
+/// zero crossings of a 50 Hz voice, short enough that the gate closes soon after a phrase.
+const NOISE_GATE_ENVELOPE_MS: f32 = 10.0;
+
+/// `Audio::mono_compatibility` below which exports warn about the mix cancelling out when
+/// folded to mono, i.e. the channels are more anti-correlated than correlated
+pub const LOW_MONO_COMPATIBILITY: f32 = 0.5;
+
+/// Decay time of the sibilance detector in `Audio::deess`. Holds the gain reduction through
+/// a whole "s" instead of following the individual cycles of the hiss.
+const DEESS_RELEASE_MS: f32 = 20.0;
+
+/// Handle to a PYIN analysis running on a background thread.
+/// Dropping the handle does not stop the analysis; call `cancel` for that.
+#[derive(Debug)]
+pub struct PyinTask {
+    handle: thread::JoinHandle<()>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl PyinTask {
+    /// Asks the analysis to stop at the next frame. Its partial result is discarded.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Blocks until the analysis thread exits.
+    pub fn join(self) {
+        if self.handle.join().is_err() {
+            info!("PYIN analysis thread panicked");
+        }
+    }
+}
+
+/// One of the two channels of an `Audio`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Left,
+    Right,
+}
+
+/// How `Audio::perform_pyin_per_channel` merges the two channels' pitch into one track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelCombination {
+    /// Per frame, the f0 of the channel with the higher voiced probability
+    #[default]
+    MaxProb,
+    /// Per frame, the average of both channels' f0 weighted by their voiced probability
+    /// where both are voiced, otherwise the voiced one
+    WeightedProb,
+}
+
+/// Represents mono or stereo audio data along with associated PYIN analysis.
+/// Mono audio stores a single channel that `left` and `right` both read.
 /// Thread-safe access to PYIN data is ensured via RwLock.
 #[derive(Clone, Debug)]
 pub struct Audio {
     sample_rate: u32,
     length: usize,
-    left: Vec<f32>,
-    right: Vec<f32>,
+    /// One buffer for mono, left then right for stereo, all `length` samples long
+    channels: Vec<Vec<f32>>,
     pyin: Arc<RwLock<Option<PYINData>>>, // To ensure thread-safe access
+    pyin_progress: Arc<AtomicU32>,
+    pyin_failed: Arc<AtomicBool>, // Set when the most recent analysis panicked
+    /// Bumped whenever the samples change, PYIN data is current while its generation matches
+    analysis_generation: u64,
     pub desired_f0: Option<Vec<f32>>,
+    /// Per PYIN frame, whether the frame gets tuned. Frames set to false (e.g. a spoken
+    /// word) are played unprocessed; frames past the end repeat the last value.
+    pub tune_mask: Option<Vec<bool>>,
 }
 
 impl Audio {
@@ -27,17 +101,63 @@ impl Audio {
             right.len(),
             "Left and right channel lengths must match"
         );
-        let length = left.len().max(right.len());
+        Self::from_channels(sample_rate, vec![left, right])
+    }
+
+    /// Mono audio, stored once and read back through both `left` and `right`.
+    pub fn new_mono(sample_rate: u32, samples: Vec<f32>) -> Self {
+        Self::from_channels(sample_rate, vec![samples])
+    }
+
+    fn from_channels(sample_rate: u32, channels: Vec<Vec<f32>>) -> Self {
+        let length = channels[0].len();
         Self {
             sample_rate,
             length,
-            left,
-            right,
+            channels,
             desired_f0: None,
+            tune_mask: None,
             pyin: Arc::new(RwLock::new(None)),
+            pyin_progress: Arc::new(AtomicU32::new(0)),
+            pyin_failed: Arc::new(AtomicBool::new(false)),
+            analysis_generation: 0,
         }
     }
 
+    /// Builds audio from interleaved samples with `channels` channels per frame.
+    /// Mono stays mono; channels past the second are summed into both at half gain.
+    pub fn from_interleaved(
+        samples: &[f32],
+        channels: usize,
+        sample_rate: u32,
+    ) -> anyhow::Result<Audio> {
+        if channels == 0 {
+            anyhow::bail!("channels must be > 0");
+        }
+        if !samples.len().is_multiple_of(channels) {
+            anyhow::bail!(
+                "samples length {} is not divisible by channels {}",
+                samples.len(),
+                channels
+            );
+        }
+        if channels == 1 {
+            return Ok(Audio::new_mono(sample_rate, samples.to_vec()));
+        }
+        let n_frames = samples.len() / channels;
+        let mut left = Vec::with_capacity(n_frames);
+        let mut right = Vec::with_capacity(n_frames);
+        for frame in samples.chunks_exact(channels) {
+            let [l, r, rest @ ..] = frame else {
+                unreachable!("mono is handled above");
+            };
+            let extra = rest.iter().sum::<f32>() * 0.5;
+            left.push(l + extra);
+            right.push(r + extra);
+        }
+        Ok(Audio::new(sample_rate, left, right))
+    }
+
     pub fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
@@ -46,35 +166,123 @@ impl Audio {
         self.length
     }
 
+    /// Number of stored channels: 1 for mono, 2 for stereo
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
     pub fn left(&self) -> &[f32] {
-        &self.left
+        &self.channels[0]
     }
 
+    /// The right channel, the same samples as `left` for mono audio
     pub fn right(&self) -> &[f32] {
-        &self.right
+        &self.channels[self.channels.len() - 1]
+    }
+
+    /// Gives mono audio its own right channel, for processing that treats the sides
+    /// differently
+    fn make_stereo(&mut self) {
+        if self.channels.len() == 1 {
+            let right = self.channels[0].clone();
+            self.channels.push(right);
+        }
+    }
+
+    /// Samples `[start, end)` of `channel`, clamped to the audio. Empty if the range lies
+    /// entirely past the end or `start >= end`.
+    pub fn samples_range(&self, channel: Channel, start: usize, end: usize) -> &[f32] {
+        let samples = match channel {
+            Channel::Left => self.left(),
+            Channel::Right => self.right(),
+        };
+        let end = end.min(samples.len());
+        if start >= end {
+            return &[];
+        }
+        &samples[start..end]
+    }
+
+    /// Largest absolute sample difference to `other` over both channels.
+    /// Infinite if the sample rates or lengths differ, since the buffers can't be lined up.
+    pub fn max_abs_diff(&self, other: &Audio) -> f32 {
+        if self.sample_rate != other.sample_rate || self.length != other.length {
+            return f32::INFINITY;
+        }
+        self.left()
+            .iter()
+            .zip(other.left())
+            .chain(self.right().iter().zip(other.right()))
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    /// Whether `other` has the same sample rate and length and every sample of both
+    /// channels is within `tol` of this one. Meant for comparing DSP output in tests.
+    pub fn approx_eq(&self, other: &Audio, tol: f32) -> bool {
+        self.max_abs_diff(other) <= tol
+    }
+
+    /// How well the audio survives being folded to mono, from the correlation between the
+    /// channels mapped to 0-1: 1.0 for identical channels (and mono or silent audio), 0.5
+    /// for unrelated ones and 0.0 for one channel being the other inverted, which cancels
+    /// out completely.
+    pub fn mono_compatibility(&self) -> f32 {
+        let [left, right] = &self.channels[..] else {
+            return 1.0;
+        };
+        let (mut cross, mut left_energy, mut right_energy) = (0.0f64, 0.0f64, 0.0f64);
+        for (&l, &r) in left.iter().zip(right) {
+            cross += l as f64 * r as f64;
+            left_energy += l as f64 * l as f64;
+            right_energy += r as f64 * r as f64;
+        }
+        let norm = (left_energy * right_energy).sqrt();
+        if norm == 0.0 {
+            return 1.0;
+        }
+        ((cross / norm + 1.0) / 2.0) as f32
+    }
+
+    /// Average of both channels, the signal pitch analysis runs on. A single desired F0
+    /// drives both channels, so it has to describe the sum rather than either channel.
+    pub fn mono_mix(&self) -> Vec<f32> {
+        if self.channels() == 1 {
+            return self.channels[0].clone();
+        }
+        self.left()
+            .iter()
+            .zip(self.right())
+            .map(|(l, r)| (l + r) * 0.5)
+            .collect()
     }
 
     /// Get a cloned PYIN data (if available) in a thread-safe way.
+    /// A lock poisoned by a panicked writer is recovered, returning the last stored data.
     pub fn get_pyin(&self) -> Option<PYINData> {
-        self.pyin.read().ok().and_then(|g| g.clone())
+        read_pyin(&self.pyin).clone()
+    }
+
+    /// Stores PYIN data computed elsewhere (e.g. loaded from a cache) as this audio's analysis.
+    /// The data is taken to match the current samples.
+    pub fn set_pyin(&mut self, mut data: PYINData) {
+        data.set_generation(self.analysis_generation);
+        *write_pyin(&self.pyin) = Some(data);
+        self.pyin_failed.store(false, Ordering::Relaxed);
     }
 
     /// Gets the PYIN data, blocking until it is available.
+    /// Returns None if the analysis failed before storing any data.
     #[allow(dead_code)]
     pub fn get_pyin_blocking(&self) -> Option<PYINData> {
         use std::time::Duration;
 
         loop {
-            match self.pyin.read() {
-                Ok(guard) => {
-                    if let Some(data) = guard.clone() {
-                        return Some(data);
-                    }
-                }
-                Err(e) => {
-                    info!("Failed to acquire PYIN read lock: {:?}", e);
-                    return None;
-                }
+            if let Some(data) = read_pyin(&self.pyin).clone() {
+                return Some(data);
+            }
+            if self.pyin_failed() {
+                return None;
             }
             // Avoid busy-waiting
             thread::sleep(Duration::from_millis(10));
@@ -87,38 +295,451 @@ impl Audio {
         Arc::clone(&self.pyin)
     }
 
+    /// Progress of the most recent PYIN analysis as a percentage (0-100).
+    pub fn pyin_progress(&self) -> u32 {
+        self.pyin_progress.load(Ordering::Relaxed)
+    }
+
+    /// Generation of the samples, bumped by every edit that changes them.
+    pub fn analysis_generation(&self) -> u64 {
+        self.analysis_generation
+    }
+
+    /// Whether PYIN data is stored and was computed from the current samples. Data from
+    /// before an edit (e.g. `insert_audio_at`) stays readable through `get_pyin` until the
+    /// re-analysis replaces it, but is no longer current.
+    pub fn pyin_is_current(&self) -> bool {
+        read_pyin(&self.pyin)
+            .as_ref()
+            .is_some_and(|data| data.generation() == self.analysis_generation)
+    }
+
+    /// Marks stored PYIN data as stale after the samples were edited.
+    fn samples_changed(&mut self) {
+        self.analysis_generation += 1;
+    }
+
+    /// Whether the most recent pitch analysis panicked instead of storing a result.
+    /// Any PYIN data from before it is left as it was.
+    pub fn pyin_failed(&self) -> bool {
+        self.pyin_failed.load(Ordering::Relaxed)
+    }
+
     /// Synchronous wrapper.
     /// NOTE: Do NOT call this on the GUI thread; prefer `perform_pyin_background`.
     pub fn perform_pyin(&mut self) {
+        self.perform_pyin_with_config(PyinConfig::default());
+    }
+
+    /// Like `perform_pyin`, with custom analysis parameters (e.g. a narrower fmin/fmax).
+    /// The config's progress and cancel handles are replaced by the Audio's own.
+    pub fn perform_pyin_with_config(&mut self, config: PyinConfig) {
+        let detector = PyinDetector::new(PyinConfig {
+            progress: Some(Arc::clone(&self.pyin_progress)),
+            ..config
+        });
+        self.perform_pitch_detection(&detector);
+    }
+
+    /// Runs any pitch detector synchronously and stores its result as the PYIN data.
+    pub fn perform_pitch_detection(&mut self, detector: &dyn PitchDetector) {
         compute_pyin_blocking(
             self.sample_rate,
-            self.left.clone(),
-            self.right.clone(),
+            self.mono_mix(),
             self.pyin_handle(),
+            Arc::clone(&self.pyin_failed),
+            Arc::new(AtomicBool::new(false)),
+            self.analysis_generation,
+            detector,
         );
     }
 
+    /// Like `perform_pyin_with_config`, but analyzes each channel on its own and merges the
+    /// two pitch tracks per frame with `combination`, instead of analyzing their sum. Helps
+    /// when one channel is much cleaner than the other. Mono audio is analyzed as usual.
+    /// NOTE: Blocks like `perform_pyin` and runs PYIN twice.
+    pub fn perform_pyin_per_channel(
+        &mut self,
+        config: PyinConfig,
+        combination: ChannelCombination,
+    ) {
+        let detector = PyinDetector::new(PyinConfig {
+            progress: Some(Arc::clone(&self.pyin_progress)),
+            ..config
+        });
+        if self.channels() == 1 || self.length() < detector.frame_length() {
+            self.perform_pitch_detection(&detector);
+            return;
+        }
+        let left = detector.detect(self.left(), self.sample_rate);
+        let right = detector.detect(self.right(), self.sample_rate);
+        self.set_pyin(combine_channel_pitch(&left, &right, combination));
+    }
+
+    /// Re-runs PYIN on samples `[start, start + len)` only and splices the result into the
+    /// stored PYIN data, leaving the frames that don't overlap the region as they were.
+    /// Every frame whose window overlaps the region is recomputed. The silence threshold and
+    /// pitch tracking only see the region, so frames near its edges can differ slightly from
+    /// a whole-track analysis.
+    /// `edited_from` is the `analysis_generation` from before the edit of the region. The
+    /// data only counts as current afterwards if it was current then: an earlier edit
+    /// elsewhere (e.g. `insert_audio_at`) still leaves the frames outside the region stale.
+    /// Without stored data there is nothing to splice into, run a full analysis instead.
+    /// Pass the config the stored data was computed with (e.g. the track's fmin/fmax), its
+    /// progress handle is replaced by the Audio's own.
+    /// NOTE: Blocks like `perform_pyin`, keep regions short on the GUI thread.
+    pub fn analyze_region(
+        &mut self,
+        start: usize,
+        len: usize,
+        config: PyinConfig,
+        edited_from: u64,
+    ) {
+        let config = PyinConfig {
+            progress: Some(Arc::clone(&self.pyin_progress)),
+            ..config
+        };
+        let (frame_length, hop_length) = (config.frame_length, config.hop_length);
+        let end = start.saturating_add(len).min(self.length());
+        if self.length() < frame_length || start >= end || read_pyin(&self.pyin).is_none() {
+            debug!(start, len, "Nothing to analyze in region");
+            return;
+        }
+
+        // Frame i covers [i * hop, i * hop + frame_length)
+        let n_frames = (self.length() - frame_length) / hop_length + 1;
+        let first_frame = (start + 1)
+            .saturating_sub(frame_length)
+            .div_ceil(hop_length);
+        let last_frame = end.div_ceil(hop_length).min(n_frames);
+        let region_start = first_frame * hop_length;
+        let region_end = (last_frame - 1) * hop_length + frame_length;
+        debug!(
+            first_frame,
+            last_frame, region_start, region_end, "Analyzing region"
+        );
+        let mut signal = self.mono_mix();
+        signal.truncate(region_end);
+        signal.drain(..region_start);
+        let region = pyin::pyin_with_config(&signal, self.sample_rate, &config);
+
+        let mut guard = write_pyin(&self.pyin);
+        let Some(data) = guard.as_mut() else {
+            return;
+        };
+        let was_current = data.generation() == edited_from;
+        data.splice_frames(first_frame, &region);
+        if was_current {
+            data.set_generation(self.analysis_generation);
+        }
+    }
+
     /// Starts PYIN analysis on a background OS thread and returns immediately.
-    /// Store the JoinHandle if you want (optional). If you drop it, it still runs.
-    pub fn perform_pyin_background(&mut self) -> thread::JoinHandle<()> {
+    /// Keep the returned task to cancel the analysis later. If you drop it, it still runs.
+    pub fn perform_pyin_background(&mut self) -> PyinTask {
+        self.perform_pyin_background_with_config(PyinConfig::default())
+    }
+
+    /// Like `perform_pyin_background`, with custom analysis parameters.
+    pub fn perform_pyin_background_with_config(&mut self, config: PyinConfig) -> PyinTask {
         self.pyin = Arc::new(RwLock::new(None)); // Reset PYIN data
-        let left = self.left.clone();
-        let right = self.right.clone();
+        self.pyin_progress = Arc::new(AtomicU32::new(0));
+        self.pyin_failed = Arc::new(AtomicBool::new(false));
+        let signal = self.mono_mix();
         let sample_rate = self.sample_rate;
+        let generation = self.analysis_generation;
         let pyin_ref = self.pyin_handle();
+        let failed = Arc::clone(&self.pyin_failed);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_for_thread = Arc::clone(&cancel);
+        let detector = PyinDetector::new(PyinConfig {
+            progress: Some(Arc::clone(&self.pyin_progress)),
+            cancel: Some(Arc::clone(&cancel)),
+            ..config
+        });
+
+        let handle = thread::spawn(move || {
+            compute_pyin_blocking(
+                sample_rate,
+                signal,
+                pyin_ref,
+                failed,
+                cancel_for_thread,
+                generation,
+                &detector,
+            );
+        });
+        PyinTask { handle, cancel }
+    }
 
-        thread::spawn(move || {
-            compute_pyin_blocking(sample_rate, left, right, pyin_ref);
+    /// Pans the audio using an equal-power pan law, `pan` ranges from -1.0 (left) to 1.0 (right).
+    /// The law is normalized so the centre position is unity gain: at 0.0 both channels are
+    /// left unchanged and at the extremes the opposite channel is silenced.
+    pub fn apply_pan(&mut self, pan: f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        let left_gain = (angle.cos() * std::f32::consts::SQRT_2).min(1.0);
+        let right_gain = (angle.sin() * std::f32::consts::SQRT_2).min(1.0);
+        debug!(pan, left_gain, right_gain, "Applying pan");
+        self.samples_changed();
+        if left_gain == right_gain {
+            for s in self.channels.iter_mut().flatten() {
+                *s *= left_gain;
+            }
+            return;
+        }
+        self.make_stereo();
+        for s in &mut self.channels[0] {
+            *s *= left_gain;
+        }
+        for s in &mut self.channels[1] {
+            *s *= right_gain;
+        }
+    }
+
+    /// Adjusts the stereo width using mid/side processing: the side signal is scaled by
+    /// `width`, so 1.0 leaves the audio unchanged, 0.0 collapses it to mono and values
+    /// above 1.0 widen it. Negative widths are treated as 0.0.
+    pub fn set_stereo_width(&mut self, width: f32) {
+        let width = width.max(0.0);
+        debug!(width, "Setting stereo width");
+        // Mono audio has no side signal to scale
+        let [left, right] = &mut self.channels[..] else {
+            return;
+        };
+        // The mid (the PYIN analysis signal) is kept, so the analysis stays current
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let mid = (*l + *r) * 0.5;
+            let side = (*l - *r) * 0.5 * width;
+            *l = mid + side;
+            *r = mid - side;
+        }
+    }
+
+    /// Iterates over analysis frames of `frame_length` samples spaced `hop_length` apart,
+    /// yielding the left and right slices. Only complete frames are returned, so there are
+    /// `(length - frame_length) / hop_length + 1` of them, the same framing PYIN uses.
+    pub fn frames(
+        &self,
+        frame_length: usize,
+        hop_length: usize,
+    ) -> impl Iterator<Item = (&[f32], &[f32])> {
+        assert!(hop_length > 0, "hop_length must be greater than 0");
+        let n_frames = if frame_length == 0 || self.length < frame_length {
+            0
+        } else {
+            (self.length - frame_length) / hop_length + 1
+        };
+        (0..n_frames).map(move |i| {
+            let start = i * hop_length;
+            let end = start + frame_length;
+            (&self.left()[start..end], &self.right()[start..end])
         })
     }
 
+    /// Approximate integrated loudness in dB relative to full scale, from the mean square of
+    /// both channels. This is not ITU-R BS.1770 (no K-weighting or gating), but tracks it well
+    /// enough to even out levels between files. Silence returns `f32::NEG_INFINITY`.
+    pub fn loudness_db(&self) -> f32 {
+        let n = self.length * self.channels();
+        if n == 0 {
+            return f32::NEG_INFINITY;
+        }
+        let sum_squares: f64 = self
+            .channels
+            .iter()
+            .flatten()
+            .map(|&s| (s as f64) * (s as f64))
+            .sum();
+        let mean_square = sum_squares / n as f64;
+        if mean_square <= 0.0 {
+            return f32::NEG_INFINITY;
+        }
+        (10.0 * mean_square.log10()) as f32
+    }
+
+    /// Scales both channels so `loudness_db` becomes `target_db`. Silent audio is left as is.
+    /// No limiting is applied, so loud targets can push samples past full scale.
+    pub fn normalize_loudness(&mut self, target_db: f32) {
+        let current_db = self.loudness_db();
+        if !current_db.is_finite() {
+            debug!("Skipping loudness normalization of silent audio");
+            return;
+        }
+        let gain = 10f32.powf((target_db - current_db) / 20.0);
+        debug!(current_db, target_db, gain, "Normalizing loudness");
+        self.samples_changed();
+        for s in self.channels.iter_mut().flatten() {
+            *s *= gain;
+        }
+    }
+
+    /// Noise gate that fades out passages whose level stays below `threshold_db` (dBFS), e.g.
+    /// breaths between phrases. The level is a peak envelope of both channels, so they are
+    /// gated together. The gain rises to unity over `attack_ms` once the envelope crosses
+    /// the threshold and falls to silence over `release_ms` once it drops below.
+    pub fn noise_gate(&mut self, threshold_db: f32, attack_ms: f32, release_ms: f32) {
+        let threshold = 10f32.powf(threshold_db / 20.0);
+        let attack = smoothing_coefficient(attack_ms, self.sample_rate);
+        let release = smoothing_coefficient(release_ms, self.sample_rate);
+        let decay = smoothing_coefficient(NOISE_GATE_ENVELOPE_MS, self.sample_rate);
+        debug!(threshold_db, attack_ms, release_ms, "Applying noise gate");
+        self.samples_changed();
+
+        let mut envelope = 0.0f32;
+        let mut gain = 0.0f32;
+        for i in 0..self.length {
+            let level = self.channels.iter().map(|c| c[i].abs()).fold(0.0, f32::max);
+            envelope = if level > envelope {
+                level
+            } else {
+                level + decay * (envelope - level)
+            };
+            let target = if envelope >= threshold { 1.0 } else { 0.0 };
+            let coefficient = if target > gain { attack } else { release };
+            gain = target + coefficient * (gain - target);
+            for channel in &mut self.channels {
+                channel[i] *= gain;
+            }
+        }
+    }
+
+    /// Filters both channels with an RBJ cookbook biquad (`q` around 0.707 gives a flat
+    /// Butterworth pass band for the low and high pass), e.g. to clean up rumble before
+    /// tuning or to shape the tone.
+    pub fn biquad(&mut self, kind: FilterKind, cutoff_hz: f32, q: f32) {
+        debug!(?kind, cutoff_hz, q, "Applying biquad filter");
+        self.samples_changed();
+        for channel in &mut self.channels {
+            let mut filter = Biquad::new(kind, cutoff_hz, q, self.sample_rate);
+            for s in channel.iter_mut() {
+                *s = filter.process(*s);
+            }
+        }
+    }
+
+    /// De-esser that compresses the band above `freq_hz` (sibilance sits around 5-8 kHz)
+    /// whenever its level goes over `threshold_db` (dBFS), so PSOLA has less hiss to
+    /// exaggerate. The band is split off with a zero-phase high-pass (so taking it out and
+    /// putting it back at a reduced gain doesn't smear the rest), everything below it passes
+    /// untouched. The level is a peak envelope of both channels' bands, so they are
+    /// compressed together.
+    pub fn deess(&mut self, threshold_db: f32, freq_hz: f32) {
+        let threshold = 10f32.powf(threshold_db / 20.0);
+        let release = smoothing_coefficient(DEESS_RELEASE_MS, self.sample_rate);
+        debug!(threshold_db, freq_hz, "Applying de-esser");
+        self.samples_changed();
+
+        let bands: Vec<Vec<f32>> = self
+            .channels
+            .iter()
+            .map(|channel| zero_phase_highpass(channel, freq_hz, self.sample_rate))
+            .collect();
+        let mut envelope = 0.0f32;
+        for i in 0..self.length {
+            let level = bands.iter().map(|band| band[i].abs()).fold(0.0, f32::max);
+            envelope = if level > envelope {
+                level
+            } else {
+                level + release * (envelope - level)
+            };
+            let gain = if envelope > threshold {
+                threshold / envelope
+            } else {
+                1.0
+            };
+            for (channel, band) in self.channels.iter_mut().zip(&bands) {
+                channel[i] += band[i] * (gain - 1.0);
+            }
+        }
+    }
+
     /// Returns interleaved stereo samples as a Vec<f32>
     pub fn interleaved(&self) -> Vec<f32> {
         let mut out = vec![0.0; self.length * 2];
-        interleave_stereo(&self.left, &self.right, &mut out);
+        interleave_stereo(self.left(), self.right(), &mut out);
         out
     }
 
+    /// Cuts the audio into two clips at `position` (clamped to the length of the audio).
+    /// The first holds the samples before `position`, the second the rest. Neither clip
+    /// carries over PYIN data or desired F0, as those need to be recomputed for each part.
+    pub fn split_at(&self, position: usize) -> (Audio, Audio) {
+        let position = position.min(self.length);
+        let (first, second) = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let (a, b) = channel.split_at(position);
+                (a.to_vec(), b.to_vec())
+            })
+            .unzip();
+        (
+            Audio::from_channels(self.sample_rate, first),
+            Audio::from_channels(self.sample_rate, second),
+        )
+    }
+
+    /// Removes samples `[start, start + len)` (clamped to the length of the audio) from both
+    /// channels, the samples after the region move up to close the gap. PYIN data and desired
+    /// F0 are left as they were and need to be recomputed.
+    pub fn remove_region(&mut self, start: usize, len: usize) {
+        let start = start.min(self.length);
+        let end = start.saturating_add(len).min(self.length);
+        debug!(
+            start,
+            end,
+            self_length = self.length,
+            "Removing audio region"
+        );
+        self.samples_changed();
+        for channel in &mut self.channels {
+            channel.drain(start..end);
+        }
+        self.length -= end - start;
+    }
+
+    /// After `remove_region(start, len)`, drops the frames of the PYIN data, desired F0 and
+    /// tune mask that covered the removed samples, so the frames after the region stay on
+    /// the samples they were computed for (to within half a hop). Frames are `hop_length`
+    /// samples apart. The frames whose window spans the cut still need to be re-analyzed,
+    /// see `analyze_region`.
+    pub fn remove_region_frames(&mut self, start: usize, len: usize, hop_length: usize) {
+        let first = start.div_ceil(hop_length);
+        let frames = first..first + (len + hop_length / 2) / hop_length;
+        debug!(?frames, "Removing frames of removed audio region");
+        if let Some(data) = write_pyin(&self.pyin).as_mut() {
+            data.remove_frames(frames.clone());
+        }
+        if let Some(desired_f0) = &mut self.desired_f0 {
+            let end = frames.end.min(desired_f0.len());
+            desired_f0.drain(frames.start.min(end)..end);
+        }
+        if let Some(tune_mask) = &mut self.tune_mask {
+            let end = frames.end.min(tune_mask.len());
+            tune_mask.drain(frames.start.min(end)..end);
+        }
+    }
+
+    /// Appends silence to both channels until the audio is `length` samples long. Longer
+    /// audio is left untouched.
+    pub fn pad_to(&mut self, length: usize) {
+        if length > self.length {
+            self.set_length(length);
+        }
+    }
+
+    /// Pads both channels with silence or truncates them to exactly `length` samples.
+    /// PYIN data and desired F0 are left as they were and need to be recomputed.
+    pub fn set_length(&mut self, length: usize) {
+        debug!(length, self_length = self.length, "Setting audio length");
+        self.samples_changed();
+        for channel in &mut self.channels {
+            channel.resize(length, 0.0);
+        }
+        self.length = length;
+    }
+
     /// Inserts the audio from `other` into `self` starting at `position`. (Overwrites existing
     /// samples)
     /// If `other` extends beyond the current length of `self`, `self` is resized accordingly.
@@ -133,16 +754,15 @@ impl Audio {
         if self.sample_rate != other.sample_rate {
             anyhow::bail!("Sample rates must match to insert audio");
         }
+        self.samples_changed();
 
-        let end_position = position + other.length();
-        if end_position > self.length {
-            self.left.resize(end_position, 0.0);
-            self.right.resize(end_position, 0.0);
-            self.length = end_position;
+        self.pad_to(position + other.length());
+        if other.channels() > self.channels() {
+            self.make_stereo();
         }
-        for i in 0..other.length() {
-            self.left[position + i] = other.left().get(i).copied().unwrap_or(0.0);
-            self.right[position + i] = other.right().get(i).copied().unwrap_or(0.0);
+        for (k, channel) in self.channels.iter_mut().enumerate() {
+            let source = &other.channels[k.min(other.channels() - 1)];
+            channel[position..position + source.len()].copy_from_slice(source);
         }
         debug!(self_length = self.length, "Completed audio insertion");
         Ok(())
@@ -161,71 +781,291 @@ impl Audio {
         if self.sample_rate != other.sample_rate {
             anyhow::bail!("Sample rates must match to add audio");
         }
-        let end_position = position + other.length();
-        if end_position > self.length {
-            self.left.resize(end_position, 0.0);
-            self.right.resize(end_position, 0.0);
-            self.length = end_position;
+        self.samples_changed();
+        self.pad_to(position + other.length());
+        if other.channels() > self.channels() {
+            self.make_stereo();
         }
-        for i in 0..other.length() {
-            self.left[position + i] += other.left().get(i).copied().unwrap_or(0.0);
-            self.right[position + i] += other.right().get(i).copied().unwrap_or(0.0);
+        for (k, channel) in self.channels.iter_mut().enumerate() {
+            let source = &other.channels[k.min(other.channels() - 1)];
+            for (out, &s) in channel[position..].iter_mut().zip(source) {
+                *out += s;
+            }
         }
         debug!(self_length = self.length, "Completed audio addition");
         Ok(())
     }
+
+    /// Sums `clips`, each scaled by its gain, into new audio as long as the longest clip.
+    /// The result is stereo if any clip is, mono otherwise (also when there are no clips).
+    /// Returns an error if a clip's sample rate is not `sample_rate`.
+    pub fn mix(clips: &[(&Audio, f32)], sample_rate: u32) -> anyhow::Result<Audio> {
+        if let Some((clip, _)) = clips
+            .iter()
+            .find(|(clip, _)| clip.sample_rate != sample_rate)
+        {
+            anyhow::bail!(
+                "Sample rates must match to mix audio ({} Hz clip in a {} Hz mix)",
+                clip.sample_rate,
+                sample_rate
+            );
+        }
+        let length = clips.iter().map(|(clip, _)| clip.length).max().unwrap_or(0);
+        let n_channels = clips
+            .iter()
+            .map(|(clip, _)| clip.channels())
+            .max()
+            .unwrap_or(1);
+        debug!(n_clips = clips.len(), length, n_channels, "Mixing audio");
+
+        let mut channels = vec![vec![0.0; length]; n_channels];
+        for (clip, gain) in clips {
+            for (k, channel) in channels.iter_mut().enumerate() {
+                let source = &clip.channels[k.min(clip.channels() - 1)];
+                for (out, &s) in channel.iter_mut().zip(source) {
+                    *out += s * gain;
+                }
+            }
+        }
+        Ok(Self::from_channels(sample_rate, channels))
+    }
+}
+
+/// Read access to PYIN data that survives a writer panicking while holding the lock:
+/// the poison is cleared and the last stored data is used as is.
+fn read_pyin(lock: &RwLock<Option<PYINData>>) -> RwLockReadGuard<'_, Option<PYINData>> {
+    lock.read().unwrap_or_else(|poisoned| {
+        warn!("PYIN lock was poisoned, recovering the last stored data");
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
 }
 
-/// Internal helper: runs pyin on left/right on the current thread.
+/// Write access to PYIN data, recovering a poisoned lock like `read_pyin`.
+fn write_pyin(lock: &RwLock<Option<PYINData>>) -> RwLockWriteGuard<'_, Option<PYINData>> {
+    lock.write().unwrap_or_else(|poisoned| {
+        warn!("PYIN lock was poisoned, recovering the last stored data");
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Unit-amplitude sine of `freq` Hz, `len` samples long. The one test signal generator
+/// shared by the unit tests of every module.
+#[cfg(test)]
+pub(crate) fn sine_wave(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+/// Test signal constructors
+#[cfg(test)]
+impl Audio {
+    /// Mono unit-amplitude sine of `freq` Hz lasting `duration_s` seconds
+    pub fn synth_sine(freq: f32, sample_rate: u32, duration_s: f32) -> Self {
+        let len = (duration_s * sample_rate as f32).round() as usize;
+        Self::new_mono(sample_rate, sine_wave(freq, sample_rate, len))
+    }
+
+    /// Mono silence lasting `duration_s` seconds
+    pub fn synth_silence(sample_rate: u32, duration_s: f32) -> Self {
+        let len = (duration_s * sample_rate as f32).round() as usize;
+        Self::new_mono(sample_rate, vec![0.0; len])
+    }
+}
+
+/// Internal helper: runs `detector` on the mono analysis signal on the current thread.
 /// (Call this from a background thread to keep the GUI responsive.)
+/// The result is discarded if `cancel` is set by the time it finishes.
+/// Signals shorter than one frame (e.g. a freshly created, empty track) get empty data
+/// right away without running the detector. If the detector panics, `failed` is set and
+/// the stored data is left as it was. The result is tagged with `generation`, the
+/// `analysis_generation` of the samples `signal` was mixed from.
 fn compute_pyin_blocking(
     sample_rate: u32,
-    left: Vec<f32>,
-    right: Vec<f32>,
+    signal: Vec<f32>,
     pyin_ref: Arc<RwLock<Option<PYINData>>>,
+    failed: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+    generation: u64,
+    detector: &dyn PitchDetector,
 ) {
-    debug!("Starting PYIN analysis for both channels (background thread)");
     let start_time = std::time::Instant::now();
-    let (left_pyin, right_pyin) = rayon::join(
-        || pyin::pyin(&left, sample_rate, None, None, None, None, None, None),
-        || pyin::pyin(&right, sample_rate, None, None, None, None, None, None),
-    );
-
-    debug!(
-        right_len = right_pyin.f0().len(),
-        left_len = left_pyin.f0().len(),
-        "Completed PYIN analysis for both channels"
-    );
-
-    let length = left_pyin.f0().len().max(right_pyin.f0().len());
-    let mut f0 = vec![0.0; length];
-    let mut voiced_flags = vec![false; length];
-    let mut prob = vec![0.0; length];
-
-    for i in 0..length {
-        let left_prob = left_pyin.voiced_prob().get(i).copied().unwrap_or(0.0);
-        let right_prob = right_pyin.voiced_prob().get(i).copied().unwrap_or(0.0);
-        if left_prob >= right_prob {
-            f0[i] = left_pyin.f0().get(i).copied().unwrap_or(0.0);
-            voiced_flags[i] = left_pyin.voiced_flag().get(i).copied().unwrap_or(false);
-            prob[i] = left_prob;
-        } else {
-            f0[i] = right_pyin.f0().get(i).copied().unwrap_or(0.0);
-            voiced_flags[i] = right_pyin.voiced_flag().get(i).copied().unwrap_or(false);
-            prob[i] = right_prob;
+    let mut combined = if signal.len() < detector.frame_length() {
+        debug!(
+            len = signal.len(),
+            frame_length = detector.frame_length(),
+            "Signal shorter than one frame, skipping pitch detection"
+        );
+        PYINData::new(Vec::new(), Vec::new(), Vec::new())
+    } else {
+        debug!(
+            len = signal.len(),
+            "Starting pitch detection (background thread)"
+        );
+        let detected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            detector.detect(&signal, sample_rate)
+        }));
+        match detected {
+            Ok(data) => data,
+            Err(_) => {
+                error!("Pitch detection panicked, keeping the previous PYIN data");
+                failed.store(true, Ordering::Relaxed);
+                return;
+            }
         }
-    }
+    };
     let elapsed = start_time.elapsed();
-    debug!(time = ?elapsed, "Combined PYIN data from both channels");
+    if cancel.load(Ordering::Relaxed) {
+        debug!(time = ?elapsed, "PYIN analysis cancelled, discarding partial result");
+        return;
+    }
+    debug!(time = ?elapsed, "Completed pitch detection");
 
-    match pyin_ref.write() {
-        Ok(mut guard) => {
-            *guard = Some(PYINData::new(f0, voiced_flags, prob));
+    combined.set_generation(generation);
+    *write_pyin(&pyin_ref) = Some(combined);
+    failed.store(false, Ordering::Relaxed);
+}
+
+/// Merges the pitch tracks of two channels frame by frame, see `ChannelCombination`.
+/// The result is as long as the shorter track.
+fn combine_channel_pitch(
+    left: &PYINData,
+    right: &PYINData,
+    combination: ChannelCombination,
+) -> PYINData {
+    let frames = left.f0().len().min(right.f0().len());
+    let mut f0 = Vec::with_capacity(frames);
+    let mut voiced_flag = Vec::with_capacity(frames);
+    let mut voiced_prob = Vec::with_capacity(frames);
+    for i in 0..frames {
+        let (l_f0, l_voiced, l_prob) = (left.f0()[i], left.voiced_flag()[i], left.voiced_prob()[i]);
+        let (r_f0, r_voiced, r_prob) = (
+            right.f0()[i],
+            right.voiced_flag()[i],
+            right.voiced_prob()[i],
+        );
+        let blend = combination == ChannelCombination::WeightedProb
+            && l_voiced
+            && r_voiced
+            && l_prob + r_prob > 0.0;
+        if blend {
+            f0.push((l_f0 * l_prob + r_f0 * r_prob) / (l_prob + r_prob));
+            voiced_flag.push(true);
+            voiced_prob.push(l_prob.max(r_prob));
+        } else {
+            // A voiced channel beats an unvoiced one in the weighted mode
+            let take_left = match combination {
+                ChannelCombination::WeightedProb if l_voiced != r_voiced => l_voiced,
+                _ => l_prob >= r_prob,
+            };
+            if take_left {
+                f0.push(l_f0);
+                voiced_flag.push(l_voiced);
+                voiced_prob.push(l_prob);
+            } else {
+                f0.push(r_f0);
+                voiced_flag.push(r_voiced);
+                voiced_prob.push(r_prob);
+            }
         }
-        Err(e) => {
-            info!("Failed to acquire PYIN write lock: {:?}", e);
+    }
+    PYINData::new(f0, voiced_flag, voiced_prob)
+}
+
+/// Per-sample coefficient of a one-pole smoother that covers ~63% of a step in `time_ms`.
+/// Zero (or negative) times give 0.0, i.e. an instant jump.
+fn smoothing_coefficient(time_ms: f32, sample_rate: u32) -> f32 {
+    let samples = time_ms * 0.001 * sample_rate as f32;
+    if samples <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp()
+    }
+}
+
+/// Response of a `Biquad` filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    /// Boosts (or cuts, for negative gains) a band around the cutoff by `gain_db`
+    Peak {
+        gain_db: f32,
+    },
+}
+
+/// Second-order filter with RBJ cookbook coefficients, one per channel.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2], // Last two inputs, newest first
+    y: [f32; 2], // Last two outputs, newest first
+}
+
+impl Biquad {
+    fn new(kind: FilterKind, cutoff: f32, q: f32, sample_rate: u32) -> Self {
+        let cutoff = cutoff.clamp(1.0, sample_rate as f32 * 0.45);
+        let w0 = 2.0 * std::f32::consts::PI * cutoff / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q.max(0.01));
+        let (b, a) = match kind {
+            FilterKind::LowPass => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::HighPass => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::Peak { gain_db } => {
+                let amp = 10f32.powf(gain_db / 40.0);
+                (
+                    [1.0 + alpha * amp, -2.0 * cos, 1.0 - alpha * amp],
+                    [1.0 + alpha / amp, -2.0 * cos, 1.0 - alpha / amp],
+                )
+            }
+        };
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+            x: [0.0; 2],
+            y: [0.0; 2],
         }
     }
+
+    /// Butterworth high-pass, Q = 1/sqrt(2)
+    fn highpass(cutoff: f32, sample_rate: u32) -> Self {
+        Self::new(
+            FilterKind::HighPass,
+            cutoff,
+            std::f32::consts::FRAC_1_SQRT_2,
+            sample_rate,
+        )
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// Runs a Butterworth high-pass forwards and then backwards over `signal`, which cancels its
+/// phase shift. The magnitude response is squared, i.e. 4th order.
+fn zero_phase_highpass(signal: &[f32], cutoff: f32, sample_rate: u32) -> Vec<f32> {
+    let mut forward = Biquad::highpass(cutoff, sample_rate);
+    let mut filtered: Vec<f32> = signal.iter().map(|&s| forward.process(s)).collect();
+    let mut backward = Biquad::highpass(cutoff, sample_rate);
+    for s in filtered.iter_mut().rev() {
+        *s = backward.process(*s);
+    }
+    filtered
 }
 
 /// Helper function to interleave two stereo channels into a single output buffer.
@@ -236,3 +1076,758 @@ fn interleave_stereo(left: &[f32], right: &[f32], out: &mut [f32]) {
         frame[1] = right.get(i).copied().unwrap_or(0.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::autotune::{FRAME_LENGTH, HOP_LENGTH};
+
+    #[test]
+    fn test_mix_sums_clips_scaled_by_gain() {
+        let sr = 16000;
+        let low = Audio::synth_sine(220.0, sr, 0.5);
+        let high = Audio::synth_sine(330.0, sr, 0.25);
+
+        let mixed = Audio::mix(&[(&low, 0.5), (&high, 0.5)], sr).unwrap();
+        assert_eq!(mixed.length(), low.length());
+        assert_eq!(mixed.channels(), 1);
+        for i in 0..mixed.length() {
+            let expected = 0.5 * (low.left()[i] + high.left().get(i).unwrap_or(&0.0));
+            assert!((mixed.left()[i] - expected).abs() < 1e-6, "sample {}", i);
+        }
+
+        assert!(Audio::mix(&[(&low, 1.0)], 44100).is_err());
+        assert_eq!(Audio::mix(&[], sr).unwrap().length(), 0);
+    }
+
+    #[test]
+    fn test_synth_sine_has_expected_length_and_pitch() {
+        let audio = Audio::synth_sine(220.0, 16000, 0.5);
+        assert_eq!(audio.length(), 8000);
+        assert_eq!(audio.channels(), 1);
+        assert_eq!(audio.left(), &sine_wave(220.0, 16000, 8000)[..]);
+
+        let mut audio = audio;
+        audio.perform_pyin();
+        let pyin = audio.get_pyin().unwrap();
+        let voiced: Vec<f32> = pyin
+            .f0()
+            .iter()
+            .zip(pyin.voiced_flag())
+            .filter(|(_, voiced)| **voiced)
+            .map(|(&f0, _)| f0)
+            .collect();
+        assert!(voiced.len() > pyin.f0().len() / 2);
+        assert!(
+            voiced.iter().all(|f0| (f0 - 220.0).abs() < 5.0),
+            "{:?}",
+            voiced
+        );
+
+        let silence = Audio::synth_silence(44100, 0.25);
+        assert_eq!(silence.length(), 11025);
+        assert!(silence.left().iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_mono_compatibility_scores_channel_correlation() {
+        let left = sine_wave(220.0, 44100, 4410);
+        let inverted: Vec<f32> = left.iter().map(|s| -s).collect();
+
+        let identical = Audio::new(44100, left.clone(), left.clone());
+        assert!((identical.mono_compatibility() - 1.0).abs() < 1e-4);
+        let out_of_phase = Audio::new(44100, left.clone(), inverted);
+        assert!(out_of_phase.mono_compatibility() < 1e-4);
+        assert!(out_of_phase.mono_compatibility() < LOW_MONO_COMPATIBILITY);
+
+        // A quarter period apart: unrelated, half way
+        let shifted: Vec<f32> = sine_wave(220.0, 44100, 4410 + 50)[50..].to_vec();
+        let quadrature = Audio::new(44100, left.clone(), shifted);
+        assert!((quadrature.mono_compatibility() - 0.5).abs() < 0.05);
+
+        assert_eq!(Audio::new_mono(44100, left).mono_compatibility(), 1.0);
+        assert_eq!(Audio::synth_silence(44100, 0.1).mono_compatibility(), 1.0);
+    }
+
+    #[test]
+    fn test_approx_eq_and_max_abs_diff() {
+        let left: Vec<f32> = (0..100).map(|n| (n as f32 * 0.1).sin() * 0.5).collect();
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+        let audio = Audio::new(44100, left.clone(), right.clone());
+        assert_eq!(audio.max_abs_diff(&audio), 0.0);
+        assert!(audio.approx_eq(&audio, 0.0));
+
+        // Scaling by 0.9 moves the largest sample the most
+        let peak = left.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        let scaled = Audio::new(
+            44100,
+            left.iter().map(|s| s * 0.9).collect(),
+            right.iter().map(|s| s * 0.9).collect(),
+        );
+        assert!((audio.max_abs_diff(&scaled) - 0.1 * peak).abs() < 1e-6);
+        assert!(audio.approx_eq(&scaled, 0.1 * peak + 1e-6));
+        assert!(!audio.approx_eq(&scaled, 0.05 * peak));
+
+        // Mismatched formats never compare equal
+        let resampled = Audio::new(48000, left.clone(), right);
+        assert!(!audio.approx_eq(&resampled, 1.0));
+        let shorter = Audio::new(44100, left[..50].to_vec(), left[..50].to_vec());
+        assert_eq!(audio.max_abs_diff(&shorter), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_samples_range_clamps_to_audio() {
+        let audio = Audio::new(44100, vec![0.0, 0.1, 0.2, 0.3], vec![1.0, 1.1, 1.2, 1.3]);
+        assert_eq!(audio.samples_range(Channel::Left, 1, 3), [0.1, 0.2]);
+        assert_eq!(audio.samples_range(Channel::Right, 1, 3), [1.1, 1.2]);
+        // The end is clamped to the length
+        assert_eq!(audio.samples_range(Channel::Right, 2, 100), [1.2, 1.3]);
+        // Fully out of range or reversed ranges are empty
+        assert!(audio.samples_range(Channel::Left, 4, 10).is_empty());
+        assert!(audio.samples_range(Channel::Left, 50, 100).is_empty());
+        assert!(audio.samples_range(Channel::Left, 3, 1).is_empty());
+    }
+
+    #[test]
+    fn test_from_interleaved_keeps_mono() {
+        let audio = Audio::from_interleaved(&[0.1, 0.2, 0.3], 1, 22050).unwrap();
+        assert_eq!(audio.sample_rate(), 22050);
+        assert_eq!(audio.channels(), 1);
+        assert_eq!(audio.left(), [0.1, 0.2, 0.3]);
+        assert_eq!(audio.right(), [0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn test_mono_audio_stores_one_channel() {
+        let samples = sine_wave(220.0, 16000, 4096);
+        let mono = Audio::new_mono(16000, samples.clone());
+        let duplicated = Audio::new(16000, samples.clone(), samples.clone());
+        assert_eq!(mono.channels(), 1);
+        assert_eq!(duplicated.channels(), 2);
+        assert_eq!(mono.length(), 4096);
+        assert_eq!(mono.left(), mono.right());
+        assert!(mono.approx_eq(&duplicated, 0.0));
+
+        let stored = |audio: &Audio| audio.channels.iter().map(Vec::capacity).sum::<usize>();
+        assert_eq!(2 * stored(&mono), stored(&duplicated));
+    }
+
+    #[test]
+    fn test_mono_processing_matches_duplicated_stereo() {
+        let samples: Vec<f32> = sine_wave(220.0, 16000, 4096)
+            .iter()
+            .enumerate()
+            .map(|(i, s)| if i < 2048 { s * 0.5 } else { s * 0.001 })
+            .collect();
+        let mono = Audio::new_mono(16000, samples.clone());
+        let duplicated = Audio::new(16000, samples.clone(), samples);
+        let check = |process: &dyn Fn(&mut Audio)| {
+            let (mut a, mut b) = (mono.clone(), duplicated.clone());
+            process(&mut a);
+            process(&mut b);
+            assert!(a.approx_eq(&b, 1e-6));
+        };
+        check(&|audio| audio.normalize_loudness(-12.0));
+        check(&|audio| audio.noise_gate(-30.0, 1.0, 10.0));
+        check(&|audio| audio.deess(-30.0, 4000.0));
+        check(&|audio| audio.set_stereo_width(2.0));
+        check(&|audio| audio.remove_region(100, 50));
+        check(&|audio| audio.set_length(5000));
+        assert_eq!(mono.loudness_db(), duplicated.loudness_db());
+        assert_eq!(mono.interleaved(), duplicated.interleaved());
+        assert_eq!(mono.mono_mix(), duplicated.mono_mix());
+
+        // Panning makes the sides differ, so mono audio turns stereo
+        let mut panned = mono.clone();
+        panned.apply_pan(0.5);
+        let mut expected = duplicated.clone();
+        expected.apply_pan(0.5);
+        assert_eq!(panned.channels(), 2);
+        assert!(panned.approx_eq(&expected, 0.0));
+
+        // Mixing mono into stereo and stereo into mono
+        let stereo = Audio::new(16000, vec![0.25; 10], vec![-0.25; 10]);
+        let mut mixed = mono.clone();
+        mixed.add_audio_at(4090, &stereo).unwrap();
+        let mut expected = duplicated.clone();
+        expected.add_audio_at(4090, &stereo).unwrap();
+        assert_eq!(mixed.channels(), 2);
+        assert!(mixed.approx_eq(&expected, 0.0));
+        let mut mixed = stereo.clone();
+        mixed
+            .insert_audio_at(2, &Audio::new_mono(16000, vec![1.0; 3]))
+            .unwrap();
+        assert_eq!(mixed.left()[..6], [0.25, 0.25, 1.0, 1.0, 1.0, 0.25]);
+        assert_eq!(mixed.right()[..6], [-0.25, -0.25, 1.0, 1.0, 1.0, -0.25]);
+
+        let (first, second) = mono.split_at(1000);
+        assert_eq!((first.channels(), second.channels()), (1, 1));
+        assert_eq!(first.length() + second.length(), 4096);
+    }
+
+    #[test]
+    fn test_from_interleaved_splits_stereo() {
+        let audio = Audio::from_interleaved(&[0.1, -0.1, 0.2, -0.2], 2, 44100).unwrap();
+        assert_eq!(audio.length(), 2);
+        assert_eq!(audio.left(), [0.1, 0.2]);
+        assert_eq!(audio.right(), [-0.1, -0.2]);
+
+        // Extra channels are folded into both sides
+        let audio = Audio::from_interleaved(&[0.25, 0.5, 0.5, 0.0, 0.0, 1.0], 3, 44100).unwrap();
+        assert_eq!(audio.left(), [0.5, 0.5]);
+        assert_eq!(audio.right(), [0.75, 0.5]);
+
+        assert!(Audio::from_interleaved(&[0.1, 0.2, 0.3], 2, 44100).is_err());
+        assert!(Audio::from_interleaved(&[0.1], 0, 44100).is_err());
+    }
+
+    #[test]
+    fn test_mono_audio_analysis_matches_single_channel() {
+        let sr = 16000;
+        let samples = sine_wave(220.0, sr, sr as usize / 2);
+        let mut audio = Audio::new(sr, samples.clone(), samples.clone());
+        assert_eq!(audio.mono_mix(), samples);
+
+        audio.perform_pyin();
+        let stored = audio.get_pyin().expect("PYIN data should be stored");
+        let single = pyin::pyin(&samples, sr, None, None, None, None, None, None);
+        assert!(!stored.f0().is_empty());
+        assert_eq!(stored, single);
+    }
+
+    /// Records every signal it is asked to analyze
+    struct RecordingDetector(std::sync::Mutex<Vec<Vec<f32>>>);
+
+    impl PitchDetector for RecordingDetector {
+        fn detect(&self, signal: &[f32], _sample_rate: u32) -> PYINData {
+            self.0.lock().unwrap().push(signal.to_vec());
+            PYINData::new(vec![1.0], vec![true], vec![1.0])
+        }
+    }
+
+    #[test]
+    fn test_pitch_detection_runs_on_channel_sum() {
+        let sr = 16000;
+        let left = sine_wave(220.0, sr, 4096);
+        let right: Vec<f32> = sine_wave(330.0, sr, 4096).iter().map(|s| s * 0.5).collect();
+        let mut audio = Audio::new(sr, left.clone(), right.clone());
+
+        let detector = RecordingDetector(std::sync::Mutex::new(Vec::new()));
+        audio.perform_pitch_detection(&detector);
+        let signals = detector.0.into_inner().unwrap();
+        // One analysis of the summed signal instead of one per channel
+        assert_eq!(signals.len(), 1);
+        for ((&mixed, &l), &r) in signals[0].iter().zip(&left).zip(&right) {
+            assert!((mixed - (l + r) * 0.5).abs() < 1e-6);
+        }
+
+        // With real PYIN, the stored f0 tracks the sum too
+        audio.perform_pyin();
+        let stored = audio.get_pyin().unwrap();
+        let summed = pyin::pyin(&audio.mono_mix(), sr, None, None, None, None, None, None);
+        assert_eq!(stored, summed);
+    }
+
+    /// Fails the test if it is ever asked to analyze anything
+    struct UnreachableDetector;
+
+    impl PitchDetector for UnreachableDetector {
+        fn detect(&self, signal: &[f32], _sample_rate: u32) -> PYINData {
+            panic!("detector ran on {} samples", signal.len());
+        }
+
+        fn frame_length(&self) -> usize {
+            2048
+        }
+    }
+
+    #[test]
+    fn test_too_short_audio_skips_pitch_detection() {
+        for length in [0, 100, 2047] {
+            let mut audio = Audio::new(44100, vec![0.1; length], vec![0.1; length]);
+            audio.perform_pitch_detection(&UnreachableDetector);
+            let pyin = audio.get_pyin().unwrap();
+            assert!(pyin.f0().is_empty());
+            assert!(pyin.voiced_flag().is_empty());
+            assert!(pyin.voiced_prob().is_empty());
+        }
+
+        // What a new, empty track does
+        let mut audio = Audio::new(44100, Vec::new(), Vec::new());
+        audio.perform_pyin_background().join();
+        assert_eq!(
+            audio.get_pyin(),
+            Some(PYINData::new(Vec::new(), Vec::new(), Vec::new()))
+        );
+    }
+
+    struct FixedPitchDetector(f32);
+
+    impl PitchDetector for FixedPitchDetector {
+        fn detect(&self, signal: &[f32], _sample_rate: u32) -> PYINData {
+            let n_frames = signal.len() / 256;
+            PYINData::new(
+                vec![self.0; n_frames],
+                vec![true; n_frames],
+                vec![1.0; n_frames],
+            )
+        }
+    }
+
+    #[test]
+    fn test_perform_pitch_detection_stores_detector_result() {
+        let left = sine_wave(220.0, 16000, 4096);
+        let right = sine_wave(330.0, 16000, 4096);
+        let mut audio = Audio::new(16000, left, right);
+
+        audio.perform_pitch_detection(&FixedPitchDetector(123.0));
+        let stored = audio.get_pyin().expect("detector result should be stored");
+        assert_eq!(stored.f0(), &vec![123.0; 16]);
+        assert!(stored.voiced_flag().iter().all(|&v| v));
+    }
+
+    #[test]
+    fn test_get_pyin_recovers_from_poisoned_lock() {
+        let mut audio = Audio::new(16000, vec![0.1; 4096], vec![0.1; 4096]);
+        audio.perform_pitch_detection(&FixedPitchDetector(123.0));
+        let good = audio.get_pyin().unwrap();
+
+        let handle = audio.pyin_handle();
+        let writer = thread::spawn(move || {
+            let _guard = handle.write().unwrap();
+            panic!("writer panicked while holding the PYIN lock");
+        });
+        assert!(writer.join().is_err());
+        assert!(audio.pyin_handle().is_poisoned());
+
+        assert_eq!(audio.get_pyin(), Some(good));
+        assert!(!audio.pyin_handle().is_poisoned());
+
+        // Later analyses store their results again
+        audio.perform_pitch_detection(&FixedPitchDetector(456.0));
+        assert_eq!(audio.get_pyin().unwrap().f0(), &vec![456.0; 16]);
+    }
+
+    #[test]
+    fn test_inserting_audio_marks_pyin_stale() {
+        let mut audio = Audio::new(16000, vec![0.1; 4096], vec![0.1; 4096]);
+        assert!(!audio.pyin_is_current());
+        audio.perform_pitch_detection(&FixedPitchDetector(123.0));
+        assert!(audio.pyin_is_current());
+        let before = audio.get_pyin().unwrap();
+
+        audio
+            .insert_audio_at(1024, &Audio::new_mono(16000, vec![0.5; 512]))
+            .unwrap();
+        assert!(!audio.pyin_is_current());
+        // The old frames stay readable until the re-analysis replaces them
+        assert_eq!(audio.get_pyin(), Some(before));
+
+        audio.perform_pitch_detection(&FixedPitchDetector(123.0));
+        assert!(audio.pyin_is_current());
+    }
+
+    #[test]
+    fn test_weighted_channel_combination_blends_f0_by_prob() {
+        let left = PYINData::new(vec![200.0, 200.0], vec![true, true], vec![0.8, 0.9]);
+        let right = PYINData::new(vec![210.0, 300.0], vec![true, false], vec![0.2, 0.95]);
+
+        let max_prob = combine_channel_pitch(&left, &right, ChannelCombination::default());
+        assert_eq!(max_prob.f0(), &vec![200.0, 300.0]);
+        assert_eq!(max_prob.voiced_flag(), &vec![true, false]);
+
+        // Both voiced: 0.8 * 200 + 0.2 * 210, only the left voiced: the left as it is
+        let weighted = combine_channel_pitch(&left, &right, ChannelCombination::WeightedProb);
+        assert!(
+            (weighted.f0()[0] - 202.0).abs() < 1e-3,
+            "{}",
+            weighted.f0()[0]
+        );
+        assert_eq!(weighted.f0()[1], 200.0);
+        assert_eq!(weighted.voiced_flag(), &vec![true, true]);
+    }
+
+    #[test]
+    fn test_analyze_region_keeps_other_edits_stale() {
+        let sr = 16000;
+        let samples = sine_wave(220.0, sr, sr as usize);
+        let mut audio = Audio::new(sr, samples.clone(), samples);
+        audio.perform_pyin();
+
+        // The only edit since the analysis is re-analyzed, so the data is current again
+        let edited_from = audio.analysis_generation();
+        audio.remove_region(4000, 1000);
+        assert!(!audio.pyin_is_current());
+        audio.analyze_region(3999, 2, PyinConfig::default(), edited_from);
+        assert!(audio.pyin_is_current());
+
+        // An insert elsewhere moved frames the region analysis doesn't cover
+        audio
+            .insert_audio_at(12000, &Audio::new_mono(sr, vec![0.5; 512]))
+            .unwrap();
+        let edited_from = audio.analysis_generation();
+        audio.remove_region(4000, 1000);
+        audio.analyze_region(3999, 2, PyinConfig::default(), edited_from);
+        assert!(!audio.pyin_is_current());
+    }
+
+    #[test]
+    fn test_panicking_detector_marks_analysis_failed() {
+        let mut audio = Audio::new(16000, vec![0.1; 4096], vec![0.1; 4096]);
+        audio.perform_pitch_detection(&FixedPitchDetector(123.0));
+        assert!(!audio.pyin_failed());
+
+        audio.perform_pitch_detection(&UnreachableDetector);
+        assert!(audio.pyin_failed());
+        assert_eq!(audio.get_pyin().unwrap().f0(), &vec![123.0; 16]);
+        assert!(!audio.pyin_handle().is_poisoned());
+
+        // A successful analysis clears the failure
+        audio.perform_pitch_detection(&FixedPitchDetector(456.0));
+        assert!(!audio.pyin_failed());
+    }
+
+    #[test]
+    fn test_analyze_region_only_updates_overlapping_frames() {
+        let sr = 16000;
+        let samples = sine_wave(220.0, sr, sr as usize);
+        let mut audio = Audio::new(sr, samples.clone(), samples);
+        let n_frames = (audio.length() - FRAME_LENGTH) / HOP_LENGTH + 1;
+        let stale = PYINData::new(
+            vec![1.0; n_frames],
+            vec![true; n_frames],
+            vec![1.0; n_frames],
+        );
+        *audio.pyin_handle().write().unwrap() = Some(stale.clone());
+
+        let (start, len) = (4000, 3000);
+        audio.analyze_region(start, len, PyinConfig::default(), 0);
+        let pyin = audio.get_pyin().unwrap();
+        assert_eq!(pyin.f0().len(), n_frames);
+        for i in 0..n_frames {
+            let overlaps = i * HOP_LENGTH < start + len && i * HOP_LENGTH + FRAME_LENGTH > start;
+            if overlaps {
+                assert!(pyin.voiced_flag()[i], "frame {}", i);
+                assert!((pyin.f0()[i] - 220.0).abs() < 5.0, "frame {}", i);
+            } else {
+                assert_eq!(pyin.f0()[i], stale.f0()[i], "frame {}", i);
+                assert_eq!(pyin.voiced_prob()[i], stale.voiced_prob()[i], "frame {}", i);
+            }
+        }
+
+        // The pitch range of the config is honoured, 220 Hz is below this one
+        let narrow = PyinConfig {
+            fmin: 400.0,
+            fmax: 2000.0,
+            ..Default::default()
+        };
+        audio.analyze_region(start, len, narrow, 0);
+        let pyin = audio.get_pyin().unwrap();
+        let first = start / HOP_LENGTH;
+        assert!(
+            pyin.f0()[first..first + 4]
+                .iter()
+                .all(|&f| f == 0.0 || f >= 320.0)
+        );
+    }
+
+    #[test]
+    fn test_pyin_with_config_excludes_out_of_range_pitch() {
+        let sr = 16000;
+        let samples = sine_wave(110.0, sr, sr as usize / 2);
+        let mut audio = Audio::new(sr, samples.clone(), samples);
+
+        audio.perform_pyin();
+        let full_range = audio.get_pyin().unwrap();
+        assert!(full_range.f0().iter().any(|&f| (f - 110.0).abs() < 5.0));
+
+        // A range tuned for a whistle should not report the 110 Hz fundamental
+        let (fmin, fmax) = (400.0, 2000.0);
+        audio.perform_pyin_with_config(PyinConfig {
+            fmin,
+            fmax,
+            ..Default::default()
+        });
+        let narrow = audio.get_pyin().unwrap();
+        assert_eq!(narrow.f0().len(), full_range.f0().len());
+        for (&f0, &voiced) in narrow.f0().iter().zip(narrow.voiced_flag()) {
+            if voiced {
+                assert!(
+                    f0 >= fmin * 0.8 && f0 <= fmax * 1.2,
+                    "f0 {} out of range",
+                    f0
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_pan_hard_left_silences_right() {
+        let mut audio = Audio::new(44100, vec![0.5; 10], vec![0.5; 10]);
+        audio.apply_pan(-1.0);
+        assert!(audio.left().iter().all(|&s| (s - 0.5).abs() < 1e-6));
+        assert!(audio.right().iter().all(|&s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_apply_pan_centre_is_unchanged() {
+        let left: Vec<f32> = (0..10).map(|i| i as f32 / 10.0).collect();
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+        let mut audio = Audio::new(44100, left.clone(), right.clone());
+        audio.apply_pan(0.0);
+        for i in 0..10 {
+            assert!((audio.left()[i] - left[i]).abs() < 1e-6);
+            assert!((audio.right()[i] - right[i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_stereo_width_zero_is_mono() {
+        let left = sine_wave(220.0, 44100, 100);
+        let right = sine_wave(330.0, 44100, 100);
+        let mut audio = Audio::new(44100, left, right);
+        audio.set_stereo_width(0.0);
+        assert_eq!(audio.left(), audio.right());
+    }
+
+    #[test]
+    fn test_stereo_width_one_is_unchanged() {
+        let left = sine_wave(220.0, 44100, 100);
+        let right = sine_wave(330.0, 44100, 100);
+        let mut audio = Audio::new(44100, left.clone(), right.clone());
+        audio.set_stereo_width(1.0);
+        for i in 0..100 {
+            assert!((audio.left()[i] - left[i]).abs() < 1e-6);
+            assert!((audio.right()[i] - right[i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_split_at_yields_contiguous_clips() {
+        let left: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+        let audio = Audio::new(22050, left.clone(), right.clone());
+
+        let (first, second) = audio.split_at(40);
+        assert_eq!(first.length(), 40);
+        assert_eq!(second.length(), 60);
+        assert_eq!(first.sample_rate(), 22050);
+        assert_eq!(second.sample_rate(), 22050);
+        assert_eq!([first.left(), second.left()].concat(), left);
+        assert_eq!([first.right(), second.right()].concat(), right);
+    }
+
+    #[test]
+    fn test_remove_region_closes_gap() {
+        let samples: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let mut audio = Audio::new(44100, samples.clone(), samples);
+        audio.remove_region(2, 3);
+        assert_eq!(audio.length(), 7);
+        assert_eq!(audio.left(), [0.0, 1.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(audio.left(), audio.right());
+
+        // Regions reaching past the end are clamped
+        audio.remove_region(5, 100);
+        assert_eq!(audio.left(), [0.0, 1.0, 5.0, 6.0, 7.0]);
+        audio.remove_region(50, 1);
+        assert_eq!(audio.length(), 5);
+    }
+
+    #[test]
+    fn test_pad_to_appends_silence_without_truncating() {
+        let mut audio = Audio::new(44100, vec![0.5; 4], vec![-0.5; 4]);
+        audio.pad_to(6);
+        assert_eq!(audio.length(), 6);
+        assert_eq!(audio.left(), [0.5, 0.5, 0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(audio.right(), [-0.5, -0.5, -0.5, -0.5, 0.0, 0.0]);
+
+        audio.pad_to(2);
+        assert_eq!(audio.length(), 6);
+    }
+
+    #[test]
+    fn test_set_length_pads_or_truncates() {
+        let samples: Vec<f32> = (0..5).map(|i| i as f32).collect();
+        let mut audio = Audio::new(44100, samples.clone(), samples);
+        audio.set_length(3);
+        assert_eq!(audio.length(), 3);
+        assert_eq!(audio.left(), [0.0, 1.0, 2.0]);
+        assert_eq!(audio.left(), audio.right());
+
+        audio.set_length(4);
+        assert_eq!(audio.left(), [0.0, 1.0, 2.0, 0.0]);
+        assert_eq!(audio.left(), audio.right());
+    }
+
+    #[test]
+    fn test_split_at_clamps_position() {
+        let audio = Audio::new(44100, vec![0.5; 10], vec![0.5; 10]);
+        let (first, second) = audio.split_at(25);
+        assert_eq!(first.length(), 10);
+        assert_eq!(second.length(), 0);
+    }
+
+    #[test]
+    fn test_frames_yields_complete_frames() {
+        let ramp: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+        let negated: Vec<f32> = ramp.iter().map(|s| -s).collect();
+        let audio = Audio::new(44100, ramp, negated);
+        let (frame_length, hop_length) = (256, 100);
+
+        let frames: Vec<_> = audio.frames(frame_length, hop_length).collect();
+        let n_frames = (1000 - frame_length) / hop_length + 1;
+        assert_eq!(frames.len(), n_frames);
+        assert!(
+            frames
+                .iter()
+                .all(|(l, r)| l.len() == frame_length && r.len() == frame_length)
+        );
+
+        // The ramp's values are its sample indices, so the first sample is the start index
+        let (last_left, last_right) = frames.last().unwrap();
+        assert_eq!(last_left[0] as usize, (n_frames - 1) * hop_length);
+        assert_eq!(last_right[0], -last_left[0]);
+    }
+
+    #[test]
+    fn test_frames_empty_when_shorter_than_frame() {
+        let audio = Audio::new(44100, vec![0.0; 10], vec![0.0; 10]);
+        assert_eq!(audio.frames(2048, 256).count(), 0);
+    }
+
+    #[test]
+    fn test_normalize_loudness_evens_out_levels() {
+        let sr = 16000;
+        let quiet: Vec<f32> = sine_wave(220.0, sr, sr as usize)
+            .iter()
+            .map(|s| s * 0.05)
+            .collect();
+        let loud: Vec<f32> = sine_wave(330.0, sr, sr as usize)
+            .iter()
+            .map(|s| s * 0.8)
+            .collect();
+        let mut quiet = Audio::new(sr, quiet.clone(), quiet);
+        let mut loud = Audio::new(sr, loud.clone(), loud);
+        assert!((loud.loudness_db() - quiet.loudness_db()).abs() > 20.0);
+
+        quiet.normalize_loudness(-20.0);
+        loud.normalize_loudness(-20.0);
+        assert!((quiet.loudness_db() - loud.loudness_db()).abs() < 1.0);
+        assert!((quiet.loudness_db() + 20.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_normalize_loudness_leaves_silence_alone() {
+        let mut audio = Audio::new(44100, vec![0.0; 100], vec![0.0; 100]);
+        assert_eq!(audio.loudness_db(), f32::NEG_INFINITY);
+        audio.normalize_loudness(-14.0);
+        assert!(audio.left().iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_noise_gate_attenuates_quiet_noise_between_loud_passages() {
+        let sr = 16000;
+        let section = sr as usize / 4;
+        let mut state = 1u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((state >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.02
+        };
+        let loud = sine_wave(220.0, sr, section);
+        let mut samples = Vec::new();
+        for _ in 0..2 {
+            samples.extend(loud.iter().map(|s| s * 0.5));
+            samples.extend((0..section).map(|_| noise()));
+        }
+        let mut audio = Audio::new(sr, samples.clone(), samples.clone());
+        audio.noise_gate(-30.0, 1.0, 20.0);
+
+        let rms = |x: &[f32]| (x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32).sqrt();
+        let settle = sr as usize / 10;
+        for i in 0..2 {
+            let loud_start = 2 * i * section;
+            let quiet_start = loud_start + section;
+            // Loud passages pass once the gate has opened
+            let loud_range = loud_start + sr as usize / 100..quiet_start;
+            let ratio = rms(&audio.left()[loud_range.clone()]) / rms(&samples[loud_range]);
+            assert!(ratio > 0.99, "loud passage gain {}", ratio);
+            // Quiet passages (around -45 dBFS) end up below -60 dBFS once released
+            let quiet = &audio.left()[quiet_start + settle..quiet_start + section];
+            assert!(rms(quiet) < 1e-3, "quiet rms {}", rms(quiet));
+            assert_eq!(audio.left(), audio.right());
+        }
+    }
+
+    #[test]
+    fn test_deess_attenuates_sibilant_burst_only() {
+        let sr = 44100;
+        let tone: Vec<f32> = sine_wave(220.0, sr, sr as usize / 2)
+            .iter()
+            .map(|s| s * 0.5)
+            .collect();
+        let burst_range = sr as usize / 5..sr as usize * 3 / 10;
+        let burst: Vec<f32> = sine_wave(7000.0, sr, burst_range.len())
+            .iter()
+            .map(|s| s * 0.3)
+            .collect();
+        let mut samples = tone.clone();
+        for (s, b) in samples[burst_range.clone()].iter_mut().zip(&burst) {
+            *s += b;
+        }
+        let mut audio = Audio::new(sr, samples.clone(), samples);
+        audio.deess(-30.0, 4000.0);
+
+        // The low tone alone stays below the threshold and passes untouched (up to a few
+        // ms before the burst, where the backward filter pass rings)
+        let guard = sr as usize / 200;
+        for (&out, &original) in audio.left()[..burst_range.start - guard].iter().zip(&tone) {
+            assert!((out - original).abs() < 1e-4, "{} vs {}", out, original);
+        }
+        // What is left of the burst once the tone is taken out
+        let residual: Vec<f32> = audio.left()[burst_range.clone()]
+            .iter()
+            .zip(&tone[burst_range])
+            .map(|(out, t)| out - t)
+            .collect();
+        let rms = |x: &[f32]| (x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32).sqrt();
+        let ratio = rms(&residual) / rms(&burst);
+        assert!(ratio < 0.25, "burst gain {}", ratio);
+        assert_eq!(audio.left(), audio.right());
+    }
+
+    #[test]
+    fn test_biquad_lowpass_attenuates_high_frequencies() {
+        let sr = 44100;
+        let rms = |x: &[f32]| (x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32).sqrt();
+        // RMS after filtering relative to before, skipping the filter's settling time
+        let gain = |freq: f32, kind: FilterKind| {
+            let samples = sine_wave(freq, sr, sr as usize / 2);
+            let mut audio = Audio::new(sr, samples.clone(), samples.clone());
+            audio.biquad(kind, 1000.0, std::f32::consts::FRAC_1_SQRT_2);
+            assert_eq!(audio.left(), audio.right());
+            let settled = sr as usize / 20..;
+            rms(&audio.left()[settled.clone()]) / rms(&samples[settled])
+        };
+
+        let low = gain(100.0, FilterKind::LowPass);
+        let high = gain(10_000.0, FilterKind::LowPass);
+        assert!((low - 1.0).abs() < 0.05, "100 Hz gain {}", low);
+        assert!(high < 0.02, "10 kHz gain {}", high);
+        assert!(gain(100.0, FilterKind::HighPass) < 0.02);
+
+        // 6 dB is twice the amplitude at the centre of the band
+        let peak = gain(1000.0, FilterKind::Peak { gain_db: 6.0 });
+        assert!((peak - 2.0).abs() < 0.05, "peak gain {}", peak);
+    }
+
+    #[test]
+    fn test_cancelled_background_pyin_stores_nothing() {
+        let mut audio = Audio::synth_sine(220.0, 16000, 5.0);
+        let task = audio.perform_pyin_background();
+        task.cancel();
+        assert!(task.is_cancelled());
+        task.join();
+
+        assert!(audio.get_pyin().is_none());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::audio::autotune::detector::{AutocorrelationDetector, PitchDetector};
+use crate::audio::autotune::snap_to_scale;
+use crate::audio::autotune::spectral::WindowKind;
+use crate::audio::scales::{Key, Note, Scale};
+use tracing::debug;
+
+/// Lowest pitch the monitor tracks, in Hz. Sets the longest grain and with it the latency.
+pub const MONITOR_FMIN: f32 = 80.0;
+
+/// Input frames between pitch estimates
+pub const MONITOR_HOP: usize = 512;
+
+/// Pitch period used while the input is unvoiced, in Hz. Unvoiced grains aren't shifted.
+const UNVOICED_F0: f32 = 200.0;
+
+/// Streaming autotune for monitoring the input device live: pitch marks and grains are
+/// placed as input arrives (TD-PSOLA with a lookahead of one pitch period), and each
+/// detected pitch is snapped to the nearest semitone.
+/// Lighter than the offline path: one autocorrelation estimate every `MONITOR_HOP` frames
+/// instead of PYIN, and no smoothing between estimates.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    sample_rate: u32,
+    channels: usize,
+    key: Key,
+    detector: AutocorrelationDetector,
+    /// Longest pitch period tracked, in samples. Grains reach at most this far from their mark.
+    max_period: usize,
+    /// Mono input, `input[0]` is input frame `input_start`
+    input: Vec<f32>,
+    input_start: usize,
+    /// Input frames received so far
+    received: usize,
+    /// Overlap-add accumulator, `output[0]` is output frame `output_start`
+    output: Vec<f32>,
+    output_start: usize,
+    /// Hann window of the current grain length, reused between grains
+    window: Vec<f32>,
+    /// Input frames received since the last pitch estimate
+    since_detection: usize,
+    /// Current pitch period in samples and the factor the pitch is shifted by
+    period: f32,
+    ratio: f32,
+    analysis_mark: f32,
+    synthesis_mark: f32,
+}
+
+impl Monitor {
+    /// Monitor for input with the given format, tuning to the nearest semitone
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let max_period = (sample_rate as f32 / MONITOR_FMIN).ceil() as usize;
+        let detector = AutocorrelationDetector {
+            frame_length: (2 * max_period + 2).next_power_of_two(),
+            hop_length: MONITOR_HOP,
+            fmin: MONITOR_FMIN,
+            ..Default::default()
+        };
+        // The first grain needs a whole period of input before its mark
+        let first_mark = max_period as f32;
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            key: Key::new(Note::C, Scale::Chromatic),
+            detector,
+            max_period,
+            input: Vec::new(),
+            input_start: 0,
+            received: 0,
+            output: Vec::new(),
+            output_start: 0,
+            window: Vec::with_capacity(2 * max_period + 2),
+            since_detection: 0,
+            period: sample_rate as f32 / UNVOICED_F0,
+            ratio: 1.0,
+            analysis_mark: first_mark,
+            synthesis_mark: first_mark,
+        }
+    }
+
+    /// Input frames received but not yet returned as output
+    pub fn latency_frames(&self) -> usize {
+        self.received - self.output_start
+    }
+
+    /// Upper bound of `latency_frames`: the lookahead of the current grain, plus the
+    /// longest grain half the output has to wait for before it is complete
+    pub fn max_latency_frames(&self) -> usize {
+        3 * self.max_period
+    }
+
+    /// Takes a block of interleaved input samples and appends the mono output frames that
+    /// are complete so far to `output`. The output trails the input by `latency_frames`.
+    /// Only allocates while its buffers grow towards their steady-state size, so it can run
+    /// in an input callback.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let n_frames = input.len() / self.channels;
+        self.input.extend(
+            input
+                .chunks_exact(self.channels)
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32),
+        );
+        self.received += n_frames;
+        self.since_detection += n_frames;
+        if self.since_detection >= MONITOR_HOP {
+            self.since_detection = 0;
+            self.update_pitch();
+        }
+
+        // Place every grain whose input has fully arrived
+        loop {
+            while self.analysis_mark + self.period * 0.5 < self.synthesis_mark {
+                self.analysis_mark += self.period;
+            }
+            let half = self.period.round() as usize;
+            let analysis = self.analysis_mark.round() as usize;
+            if analysis + half > self.received {
+                break;
+            }
+            if self.window.len() != 2 * half {
+                self.window.resize(2 * half, 0.0);
+                WindowKind::Hann.fill(&mut self.window);
+            }
+            let src = analysis - half - self.input_start;
+            let dst = self.synthesis_mark.round() as usize - half - self.output_start;
+            if self.output.len() < dst + 2 * half {
+                self.output.resize(dst + 2 * half, 0.0);
+            }
+            for (j, w) in self.window.iter().enumerate() {
+                self.output[dst + j] += self.input[src + j] * w;
+            }
+            self.synthesis_mark += self.period / self.ratio;
+        }
+
+        // Later grains start at least `max_period` before the next synthesis mark
+        let ready = (self.synthesis_mark as usize).saturating_sub(self.max_period);
+        let n_ready = ready.saturating_sub(self.output_start);
+        if self.output.len() < n_ready {
+            self.output.resize(n_ready, 0.0);
+        }
+        output.extend(self.output.drain(..n_ready));
+        self.output_start += n_ready;
+
+        // Keep the pitch window and the input later grains can still reach
+        let keep_from = (self.analysis_mark as usize)
+            .saturating_sub(self.max_period)
+            .min(self.received.saturating_sub(self.detector.frame_length));
+        if keep_from > self.input_start {
+            self.input.drain(..keep_from - self.input_start);
+            self.input_start = keep_from;
+        }
+    }
+
+    /// Estimates the pitch of the latest input and sets the period and shift from it
+    fn update_pitch(&mut self) {
+        let frame_length = self.detector.frame_length;
+        let f0 = if self.input.len() >= frame_length {
+            let frame = &self.input[self.input.len() - frame_length..];
+            let pitch = self.detector.detect(frame, self.sample_rate);
+            pitch.f0().first().copied().unwrap_or(0.0)
+        } else {
+            0.0
+        };
+        if f0 > 0.0 {
+            let target = snap_to_scale(&[f0], self.key)[0];
+            self.period = self.sample_rate as f32 / f0;
+            self.ratio = target / f0;
+        } else {
+            self.period = self.sample_rate as f32 / UNVOICED_F0;
+            self.ratio = 1.0;
+        }
+        debug!(f0, ratio = self.ratio, "Monitor pitch estimate");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::sine_wave;
+
+    #[test]
+    fn test_monitor_output_keeps_up_with_input() {
+        let sr = 44100;
+        let block = 256;
+        let stereo: Vec<f32> = sine_wave(228.0, sr, sr as usize)
+            .iter()
+            .flat_map(|&s| [s, s])
+            .collect();
+        let mut monitor = Monitor::new(sr, 2);
+
+        let mut output = Vec::new();
+        for chunk in stereo.chunks(block * 2) {
+            monitor.process(chunk, &mut output);
+            assert!(
+                monitor.latency_frames() <= monitor.max_latency_frames(),
+                "{} frames buffered",
+                monitor.latency_frames()
+            );
+        }
+        assert!(output.len() >= sr as usize - monitor.max_latency_frames());
+        assert!(monitor.max_latency_frames() < sr as usize / 20);
+
+        // 228 Hz is closest to A#3 (233.08 Hz)
+        let detector = AutocorrelationDetector::default();
+        let settled = &output[sr as usize / 2..];
+        let pitch = detector.detect(settled, sr);
+        let voiced: Vec<f32> = pitch.f0().iter().copied().filter(|&f| f > 0.0).collect();
+        assert!(!voiced.is_empty());
+        let median = voiced[voiced.len() / 2];
+        assert!((median - 233.08).abs() < 2.0, "{}", median);
+    }
+}
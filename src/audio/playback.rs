@@ -0,0 +1,556 @@
+use crate::audio::Audio;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Minimum capacity of the playback ring buffer in frames, ~186ms at 44.1kHz
+pub const RING_BUFFER_FRAMES: usize = 8192;
+
+/// How long the feeder thread sleeps between top-ups of the ring buffer
+const FEEDER_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Capacity of the preview tone ring buffer in frames, ~1.5s at 44.1kHz
+pub const PREVIEW_BUFFER_FRAMES: usize = 65536;
+
+/// Peak amplitude of synthesized preview tones, well below full scale so they can be
+/// heard on top of the mix
+const TONE_AMPLITUDE: f32 = 0.3;
+
+/// Fade in/out applied to preview tones so they start and stop without a click
+const TONE_FADE_MS: f32 = 5.0;
+
+/// Slowest and fastest varispeed playback rates
+pub const MIN_PLAYBACK_RATE: f32 = 0.25;
+pub const MAX_PLAYBACK_RATE: f32 = 4.0;
+
+/// Peak and RMS level of an output block
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputLevels {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl OutputLevels {
+    /// Levels of interleaved `samples`, over all channels
+    pub fn measure(samples: &[f32]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let (peak, energy) = samples.iter().fold((0.0f32, 0.0f32), |(peak, energy), &s| {
+            (peak.max(s.abs()), energy + s * s)
+        });
+        Self {
+            peak,
+            rms: (energy / samples.len() as f32).sqrt(),
+        }
+    }
+}
+
+/// Playback state shared between the AudioController, the feeder thread and the CPAL
+/// callback. Everything is atomic so the callback never has to take a lock.
+#[derive(Debug)]
+pub struct PlaybackState {
+    playing: AtomicBool,
+    /// Bits of the target volume as an f32
+    volume: AtomicU32,
+    /// Bits of the varispeed rate as an f32, mix frames played per output frame
+    rate: AtomicU32,
+    /// Bits of the stereo width of the mix as an f32, applied by the callback so changing it
+    /// doesn't need a remix
+    stereo_width: AtomicU32,
+    /// Frames handed to the output device since the last seek target
+    position: AtomicUsize,
+    /// Seeks are requested by bumping `seek_epoch`; the callback flushes the ring buffer and
+    /// sets `seek_ack` to the same value, after which the feeder refills from `seek_target`
+    seek_epoch: AtomicU64,
+    seek_ack: AtomicU64,
+    seek_target: AtomicUsize,
+    /// Bits of the peak and RMS of the last output block as f32s
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        Self {
+            playing: AtomicBool::new(false),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            rate: AtomicU32::new(1.0f32.to_bits()),
+            stereo_width: AtomicU32::new(1.0f32.to_bits()),
+            position: AtomicUsize::new(0),
+            seek_epoch: AtomicU64::new(0),
+            seek_ack: AtomicU64::new(0),
+            seek_target: AtomicUsize::new(0),
+            peak: AtomicU32::new(0.0f32.to_bits()),
+            rms: AtomicU32::new(0.0f32.to_bits()),
+        }
+    }
+}
+
+impl PlaybackState {
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn playback_rate(&self) -> f32 {
+        f32::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// Sets the varispeed rate, clamped to `MIN_PLAYBACK_RATE..=MAX_PLAYBACK_RATE`.
+    /// The pitch changes along with the speed.
+    pub fn set_playback_rate(&self, rate: f32) {
+        let rate = if rate.is_nan() { 1.0 } else { rate };
+        let rate = rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn stereo_width(&self) -> f32 {
+        f32::from_bits(self.stereo_width.load(Ordering::Relaxed))
+    }
+
+    /// Sets the mid/side width the mix is played with, as in `Audio::set_stereo_width`:
+    /// 0.0 is mono, 1.0 unchanged, above 1.0 wider. Negative widths are treated as 0.0.
+    pub fn set_stereo_width(&self, width: f32) {
+        let width = if width.is_nan() { 1.0 } else { width.max(0.0) };
+        self.stereo_width.store(width.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Frame of the mix that is currently being played. While a seek hasn't reached the
+    /// callback yet (e.g. because playback is stopped) this is the seek target.
+    pub fn position(&self) -> usize {
+        if self.seek_pending() {
+            self.seek_target.load(Ordering::Acquire)
+        } else {
+            self.position.load(Ordering::Acquire)
+        }
+    }
+
+    /// Moves playback to `position`. Samples already queued for the old position are
+    /// dropped, so the jump is heard within one output buffer.
+    pub fn seek(&self, position: usize) {
+        self.seek_target.store(position, Ordering::Release);
+        self.seek_epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Levels of the last block handed to the output device
+    pub fn levels(&self) -> OutputLevels {
+        OutputLevels {
+            peak: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+            rms: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn set_levels(&self, levels: OutputLevels) {
+        self.peak.store(levels.peak.to_bits(), Ordering::Relaxed);
+        self.rms.store(levels.rms.to_bits(), Ordering::Relaxed);
+    }
+
+    fn seek_pending(&self) -> bool {
+        self.seek_epoch.load(Ordering::Acquire) != self.seek_ack.load(Ordering::Acquire)
+    }
+}
+
+/// Creates a ring buffer of at least `capacity_frames` stereo frames between a feeder,
+/// which copies the mix into it off the real-time thread, and a source for the callback.
+/// Playback resumes from the state's current position (or pending seek).
+pub fn playback_ring(
+    mix: Arc<Mutex<Audio>>,
+    state: Arc<PlaybackState>,
+    capacity_frames: usize,
+) -> (PlaybackFeeder, PlaybackSource) {
+    let (producer, consumer) = RingBuffer::new(capacity_frames.max(1) * 2);
+    let epoch = state.seek_epoch.load(Ordering::Acquire);
+    let read_position = state.position();
+    let feeder = PlaybackFeeder {
+        producer,
+        mix,
+        state: Arc::clone(&state),
+        read_position,
+        epoch,
+    };
+    let source = PlaybackSource {
+        consumer,
+        state,
+        window: [[0.0; 2]; 2],
+        window_len: 0,
+        phase: 0.0,
+    };
+    (feeder, source)
+}
+
+/// Writing end of the playback ring buffer, owned by a normal (non-real-time) thread.
+pub struct PlaybackFeeder {
+    producer: Producer<f32>,
+    mix: Arc<Mutex<Audio>>,
+    state: Arc<PlaybackState>,
+    /// Next frame of the mix to queue
+    read_position: usize,
+    /// Seek epoch `read_position` belongs to
+    epoch: u64,
+}
+
+impl PlaybackFeeder {
+    /// Queues as many interleaved frames of the mix as fit into the ring buffer and returns
+    /// how many were queued. Nothing is queued while a seek waits for the callback to flush.
+    pub fn fill(&mut self) -> usize {
+        let epoch = self.state.seek_epoch.load(Ordering::Acquire);
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.read_position = self.state.seek_target.load(Ordering::Acquire);
+        }
+        if self.state.seek_ack.load(Ordering::Acquire) != epoch {
+            return 0;
+        }
+
+        let mix = match self.mix.lock() {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("Playback mix mutex poisoned: {e}");
+                return 0;
+            }
+        };
+        let length = mix.left().len().min(mix.right().len());
+        if self.read_position > length {
+            // The mix got shorter than where we were, start over
+            self.read_position = 0;
+        }
+        let frames = (self.producer.slots() / 2).min(length - self.read_position);
+        if frames == 0 {
+            return 0;
+        }
+        let Ok(chunk) = self.producer.write_chunk_uninit(frames * 2) else {
+            return 0;
+        };
+        let range = self.read_position..self.read_position + frames;
+        let samples = mix.left()[range.clone()]
+            .iter()
+            .zip(&mix.right()[range])
+            .flat_map(|(&l, &r)| [l, r]);
+        chunk.fill_from_iter(samples);
+        self.read_position += frames;
+        frames
+    }
+
+    /// Whether the source was dropped (e.g. the output stream closed)
+    pub fn is_abandoned(&self) -> bool {
+        self.producer.is_abandoned()
+    }
+
+    /// Keeps the ring buffer topped up on a background thread until the source is dropped.
+    pub fn spawn(mut self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            debug!("Playback feeder started");
+            while !self.is_abandoned() {
+                self.fill();
+                thread::sleep(FEEDER_INTERVAL);
+            }
+            debug!("Playback feeder stopped");
+        })
+    }
+}
+
+/// Reading end of the playback ring buffer, used from the CPAL callback.
+/// Reading never blocks, locks or allocates.
+pub struct PlaybackSource {
+    consumer: Consumer<f32>,
+    state: Arc<PlaybackState>,
+    /// Frames at `position` and `position + 1` that varispeed interpolates between
+    window: [[f32; 2]; 2],
+    /// How many frames of `window` are loaded
+    window_len: usize,
+    /// Fractional position between the two window frames
+    phase: f32,
+}
+
+impl PlaybackSource {
+    /// Copies queued interleaved stereo samples into `output` and zeroes whatever could not
+    /// be filled (an underrun, or the end of the mix). Returns the number of frames copied.
+    pub fn read(&mut self, output: &mut [f32]) -> usize {
+        if self.state.seek_pending() {
+            let epoch = self.state.seek_epoch.load(Ordering::Acquire);
+            let queued = self.consumer.slots();
+            if let Ok(chunk) = self.consumer.read_chunk(queued) {
+                chunk.commit_all();
+            }
+            let target = self.state.seek_target.load(Ordering::Acquire);
+            self.state.position.store(target, Ordering::Release);
+            self.state.seek_ack.store(epoch, Ordering::Release);
+            self.window_len = 0;
+            self.phase = 0.0;
+        }
+
+        let rate = self.state.playback_rate();
+        if rate != 1.0 || self.window_len > 0 {
+            return self.read_varispeed(output, rate);
+        }
+
+        // Only whole frames, so the channels never swap
+        let samples = output.len().min(self.consumer.slots()) / 2 * 2;
+        let (filled, _) = self.consumer.pop_partial_slice(&mut output[..samples]);
+        let copied = filled.len();
+        output[copied..].fill(0.0);
+        let frames = copied / 2;
+        self.state.position.fetch_add(frames, Ordering::AcqRel);
+        frames
+    }
+
+    /// Like `read`, but steps through the queued frames `rate` frames per output frame,
+    /// linearly interpolating between neighbours. `position` follows the frames used up.
+    fn read_varispeed(&mut self, output: &mut [f32], rate: f32) -> usize {
+        let mut frames = 0;
+        let mut consumed = self.advance();
+        for out in output.chunks_exact_mut(2) {
+            while self.window_len < 2 {
+                let Some(frame) = self.pop_frame() else {
+                    break;
+                };
+                self.window[self.window_len] = frame;
+                self.window_len += 1;
+            }
+            if self.phase >= 1.0 || self.window_len < 2 {
+                // Underrun, or the end of the mix
+                break;
+            }
+            let [a, b] = self.window;
+            out[0] = a[0] + (b[0] - a[0]) * self.phase;
+            out[1] = a[1] + (b[1] - a[1]) * self.phase;
+            self.phase += rate;
+            frames += 1;
+            consumed += self.advance();
+        }
+        output[frames * 2..].fill(0.0);
+        self.state.position.fetch_add(consumed, Ordering::AcqRel);
+        frames
+    }
+
+    /// Moves past every frame `phase` has passed, as far as frames are available, and
+    /// returns how many that was
+    fn advance(&mut self) -> usize {
+        let mut consumed = 0;
+        while self.phase >= 1.0 && self.drop_frame() {
+            self.phase -= 1.0;
+            consumed += 1;
+        }
+        consumed
+    }
+
+    /// Moves past the oldest frame, taking it straight from the ring if none is loaded
+    fn drop_frame(&mut self) -> bool {
+        if self.window_len > 0 {
+            self.window[0] = self.window[1];
+            self.window_len -= 1;
+            true
+        } else {
+            self.pop_frame().is_some()
+        }
+    }
+
+    fn pop_frame(&mut self) -> Option<[f32; 2]> {
+        let chunk = self.consumer.read_chunk(2).ok()?;
+        let (first, second) = chunk.as_slices();
+        let frame = match first {
+            [l, r] => [*l, *r],
+            [l] => [*l, second[0]],
+            _ => [second[0], second[1]],
+        };
+        chunk.commit_all();
+        Some(frame)
+    }
+}
+
+/// Synthesizes a mono sine of `frequency` Hz lasting `duration_ms`, with short fades at
+/// both ends.
+pub fn synthesize_tone(frequency: f32, duration_ms: u32, sample_rate: u32) -> Vec<f32> {
+    let length = (duration_ms as u64 * sample_rate as u64 / 1000) as usize;
+    let fade = ((TONE_FADE_MS * sample_rate as f32 / 1000.0) as usize).clamp(1, length.max(1));
+    (0..length)
+        .map(|n| {
+            let phase = 2.0 * std::f32::consts::PI * frequency * n as f32 / sample_rate as f32;
+            let gain = (n.min(length - 1 - n) as f32 / fade as f32).min(1.0);
+            TONE_AMPLITUDE * gain * phase.sin()
+        })
+        .collect()
+}
+
+/// Creates a ring buffer of `capacity_frames` stereo frames for short sounds (e.g. preview
+/// tones) that are mixed on top of the output, whether or not playback is running.
+pub fn preview_ring(capacity_frames: usize) -> (PreviewSink, PreviewSource) {
+    let (producer, consumer) = RingBuffer::new(capacity_frames.max(1) * 2);
+    (PreviewSink { producer }, PreviewSource { consumer })
+}
+
+/// Writing end of the preview ring buffer, owned by the AudioController.
+pub struct PreviewSink {
+    producer: Producer<f32>,
+}
+
+impl PreviewSink {
+    /// Queues a mono sound on both channels, after whatever is still queued. Returns how many
+    /// frames fit; the rest is dropped.
+    pub fn play(&mut self, samples: &[f32]) -> usize {
+        let frames = (self.producer.slots() / 2).min(samples.len());
+        let Ok(chunk) = self.producer.write_chunk_uninit(frames * 2) else {
+            return 0;
+        };
+        chunk.fill_from_iter(samples[..frames].iter().flat_map(|&s| [s, s]));
+        frames
+    }
+}
+
+/// Reading end of the preview ring buffer, used from the CPAL callback.
+/// Reading never blocks, locks or allocates.
+pub struct PreviewSource {
+    consumer: Consumer<f32>,
+}
+
+impl PreviewSource {
+    /// Adds queued interleaved stereo samples onto `output` and returns the number of frames
+    /// added.
+    pub fn mix_into(&mut self, output: &mut [f32]) -> usize {
+        let samples = output.len().min(self.consumer.slots()) / 2 * 2;
+        let Ok(chunk) = self.consumer.read_chunk(samples) else {
+            return 0;
+        };
+        for (out, s) in output.iter_mut().zip(chunk) {
+            *out += s;
+        }
+        samples / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp_mix(frames: usize) -> Arc<Mutex<Audio>> {
+        let left: Vec<f32> = (0..frames).map(|i| i as f32).collect();
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+        Arc::new(Mutex::new(Audio::new(44100, left, right)))
+    }
+
+    #[test]
+    fn test_handoff_delivers_every_frame_in_order() {
+        let frames = 10_000;
+        let state = Arc::new(PlaybackState::default());
+        let (feeder, mut source) = playback_ring(ramp_mix(frames), Arc::clone(&state), 256);
+        let feeder_thread = feeder.spawn();
+
+        // Read in odd-sized blocks while the feeder keeps refilling the small buffer
+        let mut received = Vec::new();
+        let mut block = vec![0.0; 2 * 97];
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while received.len() < frames * 2 && std::time::Instant::now() < deadline {
+            let copied = source.read(&mut block);
+            received.extend_from_slice(&block[..copied * 2]);
+            assert!(block[copied * 2..].iter().all(|&s| s == 0.0));
+        }
+        assert_eq!(received.len(), frames * 2);
+        for (i, frame) in received.chunks_exact(2).enumerate() {
+            assert_eq!(frame, [i as f32, -(i as f32)]);
+        }
+        assert_eq!(state.position(), frames);
+
+        drop(source);
+        feeder_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_seek_flushes_queued_frames() {
+        let state = Arc::new(PlaybackState::default());
+        let (mut feeder, mut source) = playback_ring(ramp_mix(1000), Arc::clone(&state), 64);
+        assert_eq!(feeder.fill(), 64);
+
+        state.seek(500);
+        // The feeder waits for the flush before queueing from the new position
+        assert_eq!(feeder.fill(), 0);
+        let mut block = vec![0.0; 8];
+        assert_eq!(source.read(&mut block), 0);
+        assert_eq!(state.position(), 500);
+
+        assert_eq!(feeder.fill(), 64);
+        assert_eq!(source.read(&mut block), 4);
+        assert_eq!(block[..2], [500.0, -500.0]);
+        assert_eq!(state.position(), 504);
+    }
+
+    #[test]
+    fn test_new_ring_resumes_from_current_position() {
+        let state = Arc::new(PlaybackState::default());
+        let mix = ramp_mix(1000);
+        state.seek(300);
+        let (mut feeder, mut source) = playback_ring(Arc::clone(&mix), Arc::clone(&state), 64);
+        let mut block = vec![0.0; 4];
+        source.read(&mut block);
+        feeder.fill();
+        assert_eq!(source.read(&mut block), 2);
+        assert_eq!(block[0], 300.0);
+        drop((feeder, source));
+
+        // E.g. after the output stream is rebuilt with another buffer size
+        let (mut feeder, mut source) = playback_ring(mix, Arc::clone(&state), 64);
+        feeder.fill();
+        source.read(&mut block);
+        assert_eq!(block[0], 302.0);
+    }
+
+    #[test]
+    fn test_synthesized_tone_has_requested_dominant_frequency() {
+        let sr = 44100;
+        for freq in [110.0, 261.63, 440.0, 1046.5] {
+            let tone = synthesize_tone(freq, 300, sr);
+            assert_eq!(tone.len(), 13230);
+            assert!(tone.iter().all(|s| s.abs() <= TONE_AMPLITUDE));
+            // Starts and ends silent so there's no click
+            assert_eq!(tone[0], 0.0);
+            assert!(tone[tone.len() - 1].abs() < 1e-3);
+
+            let frame_length = 8192;
+            let spectrum = &crate::audio::autotune::spectral::stft(
+                &tone,
+                frame_length,
+                frame_length,
+                Default::default(),
+            )[0];
+            let peak_bin = (0..spectrum.len())
+                .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+                .unwrap();
+            let bin_hz = sr as f32 / frame_length as f32;
+            let peak_hz = peak_bin as f32 * bin_hz;
+            assert!(
+                (peak_hz - freq).abs() <= bin_hz,
+                "{} Hz -> {} Hz",
+                freq,
+                peak_hz
+            );
+        }
+    }
+
+    #[test]
+    fn test_preview_is_mixed_on_top_of_output() {
+        let (mut sink, mut source) = preview_ring(4);
+        // Only as much as fits is queued
+        assert_eq!(sink.play(&[0.25, 0.5, 0.75, 1.0, 1.25]), 4);
+
+        let mut output = vec![0.5; 6];
+        assert_eq!(source.mix_into(&mut output), 3);
+        assert_eq!(output, [0.75, 0.75, 1.0, 1.0, 1.25, 1.25]);
+        assert_eq!(source.mix_into(&mut output), 1);
+        assert_eq!(output[..2], [1.75, 1.75]);
+        assert_eq!(source.mix_into(&mut output), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::audio::Audio;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, error};
+
+/// Accumulates interleaved samples coming from a CPAL input stream.
+/// The buffer is shared so the capture callback can push into it while the
+/// AudioController keeps ownership of the recorder itself.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    sample_rate: u32,
+    channels: usize,
+    buffer: Arc<Mutex<Vec<f32>>>,
+}
+
+impl Recorder {
+    /// Starts a new, empty recording for an input with the given format.
+    pub fn start(sample_rate: u32, channels: usize) -> Self {
+        debug!(sample_rate, channels, "Starting recording");
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            buffer: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Appends a block of interleaved input samples.
+    /// This is called from within the CPAL input callback.
+    pub fn push_input(&self, input: &[f32]) {
+        match self.buffer.lock() {
+            Ok(mut buffer) => buffer.extend_from_slice(input),
+            Err(e) => error!("Recording buffer mutex poisoned: {e}"),
+        }
+    }
+
+    /// Number of frames (samples per channel) recorded so far.
+    pub fn frames_recorded(&self) -> usize {
+        self.buffer
+            .lock()
+            .map(|buffer| buffer.len() / self.channels)
+            .unwrap_or(0)
+    }
+
+    /// Stops the recording and returns the captured samples as stereo `Audio`.
+    /// Mono input is duplicated into both channels, and any channels past the
+    /// second are dropped.
+    pub fn stop(&self) -> Audio {
+        let samples = match self.buffer.lock() {
+            Ok(mut buffer) => std::mem::take(&mut *buffer),
+            Err(e) => {
+                error!("Recording buffer mutex poisoned: {e}");
+                Vec::new()
+            }
+        };
+
+        let n_frames = samples.len() / self.channels;
+        let mut left = Vec::with_capacity(n_frames);
+        let mut right = Vec::with_capacity(n_frames);
+        for frame in samples.chunks_exact(self.channels) {
+            left.push(frame[0]);
+            right.push(if self.channels > 1 {
+                frame[1]
+            } else {
+                frame[0]
+            });
+        }
+        debug!(n_frames, "Stopped recording");
+        Audio::new(self.sample_rate, left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_mono_input_yields_expected_length() {
+        let recorder = Recorder::start(44100, 1);
+        let block: Vec<f32> = (0..512).map(|i| i as f32 / 512.0).collect();
+        recorder.push_input(&block);
+        recorder.push_input(&block);
+
+        let audio = recorder.stop();
+        assert_eq!(audio.length(), 1024);
+        assert_eq!(audio.sample_rate(), 44100);
+        // Mono input is duplicated into both channels
+        assert_eq!(audio.left(), audio.right());
+        assert_eq!(audio.left()[..512], block[..]);
+    }
+
+    #[test]
+    fn test_recording_stereo_input_is_deinterleaved() {
+        let recorder = Recorder::start(48000, 2);
+        recorder.push_input(&[0.1, -0.1, 0.2, -0.2, 0.3, -0.3]);
+        assert_eq!(recorder.frames_recorded(), 3);
+
+        let audio = recorder.stop();
+        assert_eq!(audio.length(), 3);
+        assert_eq!(audio.left(), &[0.1, 0.2, 0.3]);
+        assert_eq!(audio.right(), &[-0.1, -0.2, -0.3]);
+    }
+
+    #[test]
+    fn test_stop_clears_buffer() {
+        let recorder = Recorder::start(44100, 2);
+        recorder.push_input(&[0.5; 8]);
+        let _ = recorder.stop();
+        assert_eq!(recorder.frames_recorded(), 0);
+        assert_eq!(recorder.stop().length(), 0);
+    }
+}
@@ -1,10 +1,16 @@
+use std::fmt;
 use std::str::FromStr;
 use tracing::debug;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Standard concert pitch of A4 in Hz
+pub const DEFAULT_REFERENCE_A4: f32 = 440.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Key {
     root: Note,
     scale: Scale,
+    /// Frequency of A4 in Hz the scale frequencies are tuned to
+    reference_a4: f32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -54,22 +60,54 @@ impl FromStr for Note {
     }
 }
 
+impl Note {
+    pub const ALL: [Note; 12] = [
+        Note::C,
+        Note::Cs,
+        Note::D,
+        Note::Ds,
+        Note::E,
+        Note::F,
+        Note::Fs,
+        Note::G,
+        Note::Gs,
+        Note::A,
+        Note::As,
+        Note::B,
+    ];
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Note::C => "C",
+            Note::Cs => "C#",
+            Note::D => "D",
+            Note::Ds => "D#",
+            Note::E => "E",
+            Note::F => "F",
+            Note::Fs => "F#",
+            Note::G => "G",
+            Note::Gs => "G#",
+            Note::A => "A",
+            Note::As => "A#",
+            Note::B => "B",
+        };
+        f.write_str(name)
+    }
+}
+
 impl Into<String> for Note {
     fn into(self) -> String {
-        match self {
-            Note::C => "C".to_string(),
-            Note::Cs => "C#".to_string(),
-            Note::D => "D".to_string(),
-            Note::Ds => "D#".to_string(),
-            Note::E => "E".to_string(),
-            Note::F => "F".to_string(),
-            Note::Fs => "F#".to_string(),
-            Note::G => "G".to_string(),
-            Note::Gs => "G#".to_string(),
-            Note::A => "A".to_string(),
-            Note::As => "A#".to_string(),
-            Note::B => "B".to_string(),
-        }
+        self.to_string()
+    }
+}
+
+impl TryFrom<&str> for Note {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
@@ -88,6 +126,37 @@ impl FromStr for Scale {
     }
 }
 
+impl Scale {
+    pub const ALL: [Scale; 5] = [
+        Scale::Major,
+        Scale::Minor,
+        Scale::Blues,
+        Scale::Pentatonic,
+        Scale::Chromatic,
+    ];
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scale::Major => "major",
+            Scale::Minor => "minor",
+            Scale::Blues => "blues",
+            Scale::Pentatonic => "pentatonic",
+            Scale::Chromatic => "chromatic",
+        };
+        f.write_str(name)
+    }
+}
+
+impl TryFrom<&str> for Scale {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl FromStr for Key {
     type Err = String;
 
@@ -95,24 +164,65 @@ impl FromStr for Key {
         let mut parts = s.split_whitespace();
         let root = parts.next().ok_or("Missing root note")?.parse::<Note>()?;
         let scale = parts.next().ok_or("Missing scale")?.parse::<Scale>()?;
-        Ok(Key { root, scale })
+        Ok(Key::new(root, scale))
+    }
+}
+
+/// Formats as "Root scale", e.g. "C# minor", which parses back into the same Key.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.root, self.scale)
+    }
+}
+
+impl TryFrom<&str> for Key {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
 impl Key {
     pub fn new(root: Note, scale: Scale) -> Self {
-        Self { root, scale }
+        Self {
+            root,
+            scale,
+            reference_a4: DEFAULT_REFERENCE_A4,
+        }
     }
-    pub fn get_midi_scale(&self, octave1: i8, octave2: i8) -> Vec<u8> {
-        let scale_intervals = match self.scale {
+    /// The same key tuned to A4 = `reference_a4` Hz, e.g. 432.0 or 442.0.
+    pub fn with_reference_a4(self, reference_a4: f32) -> Self {
+        Self {
+            reference_a4,
+            ..self
+        }
+    }
+    pub fn reference_a4(&self) -> f32 {
+        self.reference_a4
+    }
+    pub fn root(&self) -> Note {
+        self.root
+    }
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+    fn midi_to_frequency(&self, midi: u8) -> f32 {
+        midi_note_to_frequency_with_reference(midi as f32, self.reference_a4)
+    }
+    /// Semitone offsets from the root that make up the scale.
+    fn scale_intervals(&self) -> Vec<i8> {
+        match self.scale {
             Scale::Major => vec![0, 2, 4, 5, 7, 9, 11],
             Scale::Minor => vec![0, 2, 3, 5, 7, 8, 10],
             Scale::Blues => vec![0, 3, 5, 6, 7, 10],
             Scale::Pentatonic => vec![0, 2, 4, 7, 9],
             Scale::Chromatic => (0..12).collect(),
-        };
-
-        let root_midi = match self.root {
+        }
+    }
+    /// Pitch class of the root note (C = 0 ... B = 11).
+    fn root_pitch_class(&self) -> i8 {
+        match self.root {
             Note::C => 0,
             Note::Cs => 1,
             Note::D => 2,
@@ -125,7 +235,26 @@ impl Key {
             Note::A => 9,
             Note::As => 10,
             Note::B => 11,
-        };
+        }
+    }
+    /// The same scale with its root moved by `semitones` (wrapping through the 12 notes),
+    /// e.g. C major +7 is G major.
+    pub fn transpose(&self, semitones: i8) -> Key {
+        let pitch_class = (self.root_pitch_class() as i16 + semitones as i16).rem_euclid(12);
+        Key {
+            root: Note::ALL[pitch_class as usize],
+            ..*self
+        }
+    }
+    /// Whether the MIDI note belongs to this key, regardless of octave.
+    pub fn contains(&self, midi: u8) -> bool {
+        let pitch_class = (midi % 12) as i8;
+        let interval = (pitch_class - self.root_pitch_class()).rem_euclid(12);
+        self.scale_intervals().contains(&interval)
+    }
+    pub fn get_midi_scale(&self, octave1: i8, octave2: i8) -> Vec<u8> {
+        let scale_intervals = self.scale_intervals();
+        let root_midi = self.root_pitch_class();
 
         let mut midi_scale = Vec::new();
         for octave in octave1..=octave2 {
@@ -145,42 +274,92 @@ impl Key {
         let midi_scale = self.get_midi_scale(octave1, octave2);
         midi_scale
             .iter()
-            .map(|&m| 440.0 * 2f32.powf((m as f32 - 69.0) / 12.0))
+            .map(|&m| self.midi_to_frequency(m))
             .collect()
     }
+    /// Frequencies of every scale note between `f_min` and `f_max`, in ascending order.
+    /// The nearest scale note just outside each end is included too, so a pitch at the
+    /// edge of the range still has an in-key neighbour on both sides.
+    pub fn scale_frequencies_in_range(&self, f_min: f32, f_max: f32) -> Vec<f32> {
+        let frequencies: Vec<f32> = (0..=127u8)
+            .filter(|&m| self.contains(m))
+            .map(|m| self.midi_to_frequency(m))
+            .collect();
+        let start = frequencies
+            .iter()
+            .position(|&f| f >= f_min)
+            .unwrap_or(frequencies.len())
+            .saturating_sub(1);
+        let end = frequencies
+            .iter()
+            .rposition(|&f| f <= f_max)
+            .map_or(0, |i| (i + 2).min(frequencies.len()));
+        frequencies
+            .get(start..end)
+            .map(<[f32]>::to_vec)
+            .unwrap_or_default()
+    }
+    /// Finds the scale note closest to `freq` between the given octaves.
+    /// Returns the MIDI note and how far `freq` is from it in cents (positive when sharp),
+    /// or None if `freq` is not a positive frequency.
+    pub fn closest_scale_note(&self, freq: f32, octave1: i8, octave2: i8) -> Option<(u8, f32)> {
+        if freq <= 0.0 {
+            return None;
+        }
+        self.get_midi_scale(octave1, octave2)
+            .into_iter()
+            .map(|m| (m, hz_to_cents(freq, self.midi_to_frequency(m))))
+            .min_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+    }
     pub fn get_scale_note_names(&self, octave1: i8, octave2: i8) -> Vec<String> {
         let midi_scale = self.get_midi_scale(octave1, octave2);
-        midi_scale
-            .iter()
-            .map(|&m| {
-                let note_index = m % 12;
-                let octave = (m / 12) - 1;
-                let note_name = match note_index {
-                    0 => "C",
-                    1 => "C#",
-                    2 => "D",
-                    3 => "D#",
-                    4 => "E",
-                    5 => "F",
-                    6 => "F#",
-                    7 => "G",
-                    8 => "G#",
-                    9 => "A",
-                    10 => "A#",
-                    11 => "B",
-                    _ => unreachable!(),
-                };
-                format!("{}{}", note_name, octave)
-            })
-            .collect()
+        midi_scale.iter().map(|&m| midi_note_name(m)).collect()
     }
 }
 
+/// Name of a MIDI note with its octave, e.g. "A4" for 69.
+pub fn midi_note_name(midi: u8) -> String {
+    let note_name = match midi % 12 {
+        0 => "C",
+        1 => "C#",
+        2 => "D",
+        3 => "D#",
+        4 => "E",
+        5 => "F",
+        6 => "F#",
+        7 => "G",
+        8 => "G#",
+        9 => "A",
+        10 => "A#",
+        11 => "B",
+        _ => unreachable!(),
+    };
+    format!("{}{}", note_name, (midi / 12) as i8 - 1)
+}
+
+/// Distance of `freq` from `reference` in cents (1200 cents per octave).
+pub fn hz_to_cents(freq: f32, reference: f32) -> f32 {
+    1200.0 * (freq / reference).log2()
+}
+/// Frequency that lies `cents` away from `reference`.
+pub fn cents_to_hz(cents: f32, reference: f32) -> f32 {
+    reference * 2f32.powf(cents / 1200.0)
+}
+/// Fractional MIDI note of `freq`, with A4 = 440 Hz.
 pub fn frequency_to_midi_note(freq: f32) -> f32 {
-    69.0 + 12.0 * (freq / 440.0).log2()
+    frequency_to_midi_note_with_reference(freq, DEFAULT_REFERENCE_A4)
+}
+/// Like `frequency_to_midi_note`, with A4 (MIDI 69) at `reference_a4` Hz.
+pub fn frequency_to_midi_note_with_reference(freq: f32, reference_a4: f32) -> f32 {
+    69.0 + hz_to_cents(freq, reference_a4) / 100.0
 }
+/// Frequency of a (fractional) MIDI note, with A4 = 440 Hz.
 pub fn midi_note_to_frequency(midi_note: f32) -> f32 {
-    440.0 * 2f32.powf((midi_note - 69.0) / 12.0)
+    midi_note_to_frequency_with_reference(midi_note, DEFAULT_REFERENCE_A4)
+}
+/// Like `midi_note_to_frequency`, with A4 (MIDI 69) at `reference_a4` Hz.
+pub fn midi_note_to_frequency_with_reference(midi_note: f32, reference_a4: f32) -> f32 {
+    cents_to_hz((midi_note - 69.0) * 100.0, reference_a4)
 }
 #[allow(unused)]
 pub fn note_name_to_midi_note(name: &str) -> anyhow::Result<f32, String> {
@@ -219,3 +398,169 @@ pub fn note_name_to_midi_note(name: &str) -> anyhow::Result<f32, String> {
     }
     Ok(midi_note as f32)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_display_round_trips() {
+        for note in Note::ALL {
+            assert_eq!(note.to_string().parse::<Note>(), Ok(note));
+        }
+    }
+
+    #[test]
+    fn test_scale_display_round_trips() {
+        for scale in Scale::ALL {
+            assert_eq!(scale.to_string().parse::<Scale>(), Ok(scale));
+        }
+    }
+
+    #[test]
+    fn test_transpose_wraps_root_and_keeps_scale() {
+        let c_major = Key::new(Note::C, Scale::Major);
+        assert_eq!(c_major.transpose(7), Key::new(Note::G, Scale::Major));
+        assert_eq!(c_major.transpose(12), c_major);
+        assert_eq!(c_major.transpose(-3), Key::new(Note::A, Scale::Major));
+        assert_eq!(
+            Key::new(Note::A, Scale::Minor).transpose(5),
+            Key::new(Note::D, Scale::Minor)
+        );
+        let tuned = c_major.with_reference_a4(432.0).transpose(5);
+        assert_eq!(tuned.reference_a4(), 432.0);
+    }
+
+    #[test]
+    fn test_key_display_round_trips() {
+        for root in Note::ALL {
+            for scale in Scale::ALL {
+                let key = Key::new(root, scale);
+                assert_eq!(Key::from_str(&key.to_string()), Ok(key));
+                assert_eq!(Key::try_from(key.to_string().as_str()), Ok(key));
+            }
+        }
+        assert_eq!(Key::new(Note::Cs, Scale::Minor).to_string(), "C# minor");
+    }
+
+    #[test]
+    fn test_reference_a4_scales_every_frequency() {
+        let key = Key::new(Note::D, Scale::Minor);
+        assert_eq!(key.reference_a4(), DEFAULT_REFERENCE_A4);
+        let tuned = key.with_reference_a4(432.0);
+        let ratio = 432.0 / 440.0;
+
+        let standard = key.get_scale_frequencies(2, 5);
+        let lowered = tuned.get_scale_frequencies(2, 5);
+        assert_eq!(standard.len(), lowered.len());
+        for (a, b) in standard.iter().zip(&lowered) {
+            assert!((b / a - ratio).abs() < 1e-5, "{} -> {}", a, b);
+        }
+        // A4 itself lands on the reference
+        let a4 = tuned.closest_scale_note(432.0, 4, 4).unwrap();
+        assert_eq!(a4.0, 69);
+        assert!(a4.1.abs() < 1e-3);
+        // The free functions agree with the key
+        assert!((midi_note_to_frequency_with_reference(69.0, 432.0) - 432.0).abs() < 1e-3);
+        assert!((frequency_to_midi_note_with_reference(432.0, 432.0) - 69.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_closest_scale_note_reports_midi_and_cents() {
+        let key = Key::new(Note::C, Scale::Major);
+        let (midi, cents) = key.closest_scale_note(445.0, 2, 6).unwrap();
+        assert_eq!(midi, 69);
+        assert!((cents - 19.56).abs() < 0.1, "cents = {}", cents);
+
+        // C# is not in C major; 270 Hz lies between C4 and D4 and is closer to C4
+        let (midi, cents) = key.closest_scale_note(270.0, 2, 6).unwrap();
+        assert_eq!(midi, 60);
+        assert!(cents > 0.0);
+    }
+
+    #[test]
+    fn test_closest_scale_note_rejects_unvoiced() {
+        let key = Key::new(Note::C, Scale::Major);
+        assert_eq!(key.closest_scale_note(0.0, 2, 6), None);
+        assert_eq!(key.closest_scale_note(-10.0, 2, 6), None);
+    }
+
+    #[test]
+    fn test_scale_frequencies_in_range_covers_range() {
+        let key = Key::new(Note::C, Scale::Major);
+        let frequencies = key.scale_frequencies_in_range(250.0, 500.0);
+        // B3 and C5 are the neighbours just outside the range
+        assert!((frequencies[0] - 246.94).abs() < 0.01);
+        assert!((frequencies[frequencies.len() - 1] - 523.25).abs() < 0.01);
+        assert!(frequencies.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(frequencies.len(), 9);
+
+        let inner = &frequencies[1..frequencies.len() - 1];
+        assert!(inner.iter().all(|&f| (250.0..=500.0).contains(&f)));
+        assert!(
+            inner
+                .iter()
+                .all(|&f| { key.contains(frequency_to_midi_note(f).round() as u8) })
+        );
+
+        assert!(key.scale_frequencies_in_range(500.0, 250.0).is_empty());
+    }
+
+    #[test]
+    fn test_key_contains_checks_pitch_class() {
+        let key = Key::new(Note::C, Scale::Major);
+        assert!(key.contains(60));
+        assert!(key.contains(62));
+        assert!(!key.contains(61));
+        // Octave independent
+        assert!(key.contains(72));
+        assert!(!key.contains(73));
+
+        // With a root other than C the intervals wrap around the octave: C# is the 7th of D major
+        let key = Key::new(Note::D, Scale::Major);
+        assert!(key.contains(61));
+        assert!(!key.contains(60));
+    }
+
+    #[test]
+    fn test_chromatic_contains_every_note() {
+        let key = Key::new(Note::Fs, Scale::Chromatic);
+        assert!((0..=127).all(|m| key.contains(m)));
+    }
+
+    #[test]
+    fn test_contains_agrees_with_midi_scale() {
+        for root in Note::ALL {
+            for scale in Scale::ALL {
+                let key = Key::new(root, scale);
+                // Octaves -1..=7 fully cover every pitch class between MIDI 12 and 96
+                let midi_scale = key.get_midi_scale(-1, 7);
+                for m in 12..=96u8 {
+                    assert_eq!(key.contains(m), midi_scale.contains(&m), "{} {}", key, m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_one_octave_is_1200_cents() {
+        assert!((hz_to_cents(880.0, 440.0) - 1200.0).abs() < 1e-3);
+        assert!((hz_to_cents(220.0, 440.0) + 1200.0).abs() < 1e-3);
+        assert!((cents_to_hz(1200.0, 440.0) - 880.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_hz_cents_round_trip() {
+        for &freq in &[55.0, 123.4, 440.0, 987.6, 1975.5] {
+            let cents = hz_to_cents(freq, 261.63);
+            let back = cents_to_hz(cents, 261.63);
+            assert!((back - freq).abs() < freq * 1e-5, "{} -> {}", freq, back);
+        }
+    }
+
+    #[test]
+    fn test_midi_helpers_use_a440_reference() {
+        assert!((frequency_to_midi_note(440.0) - 69.0).abs() < 1e-4);
+        assert!((midi_note_to_frequency(60.0) - 261.6256).abs() < 1e-2);
+    }
+}
@@ -15,7 +15,9 @@ pub struct App {
 impl App {
     pub fn new() -> Self {
         let (audio_controller_sender, audio_controller_recv) =
-            mpsc::channel::<audio_controller::AudioCommand>(100);
+            mpsc::channel::<audio_controller::AudioCommand>(
+                audio_controller::COMMAND_CHANNEL_CAPACITY,
+            );
         let (track_manager_sender, track_manager_recv) =
             mpsc::channel::<components::track::TrackManagerCommand>(100);
         let result = crate::audio::audio_controller::AudioController::new(
@@ -68,6 +70,9 @@ impl eframe::App for App {
             .frame(panel_frame)
             .show(ctx, |ui| {
                 ui.style_mut().interaction.selectable_labels = false;
+                self.toolbar
+                    .set_output_levels(self.track_manager.output_levels());
+                self.toolbar.set_monitoring(self.track_manager.monitoring());
                 self.toolbar.show(ctx);
                 self.clip_manager.show(ctx);
                 self.track_manager
@@ -1,10 +1,32 @@
 use crate::audio::audio_controller::AudioCommand;
+use crate::audio::playback::OutputLevels;
 use tokio::sync::mpsc;
 use tracing::{debug, error};
 
+/// Tempo of a new project, in beats per minute
+const DEFAULT_BPM: f32 = 120.0;
+
+/// Level at the bottom of the master meter, in dBFS
+const METER_FLOOR_DB: f32 = -60.0;
+const METER_SIZE: egui::Vec2 = egui::vec2(120.0, 10.0);
+
+/// Position of `level` on the master meter, from 0.0 (at or below the floor) to 1.0 (full scale)
+fn meter_fraction(level: f32) -> f32 {
+    if level <= 0.0 {
+        return 0.0;
+    }
+    let db = 20.0 * level.log10();
+    (1.0 - db / METER_FLOOR_DB).clamp(0.0, 1.0)
+}
+
 pub struct Toolbar {
     zoom_level: f32,
+    bpm: f32,
     volume_level: u32, // Volume level from 0 to 200
+    stereo_width: u32, // Stereo width from 0 (mono) to 200, 100 is unchanged
+    recording: bool,
+    monitoring: bool,
+    output_levels: OutputLevels,
     audio_controller_sender: mpsc::Sender<AudioCommand>,
 }
 
@@ -12,13 +34,60 @@ impl Toolbar {
     pub fn new(audio_controller_sender: mpsc::Sender<AudioCommand>) -> Self {
         Toolbar {
             zoom_level: 1.0,
+            bpm: DEFAULT_BPM,
             volume_level: 100,
+            stereo_width: 100,
+            recording: false,
+            monitoring: false,
+            output_levels: OutputLevels::default(),
             audio_controller_sender,
         }
     }
     pub fn get_zoom_level(&self) -> f32 {
         self.zoom_level
     }
+    /// Project tempo in beats per minute, used for the quantize grid
+    pub fn get_bpm(&self) -> f32 {
+        self.bpm
+    }
+    /// Sets the levels shown on the master meter
+    pub fn set_output_levels(&mut self, levels: OutputLevels) {
+        self.output_levels = levels;
+    }
+    /// Sets whether the monitor button shows as on, as reported by the AudioController
+    pub fn set_monitoring(&mut self, monitoring: bool) {
+        self.monitoring = monitoring;
+    }
+
+    /// Draws the master meter: a bar for the RMS level and a tick for the peak
+    fn show_meter(&self, ui: &mut egui::Ui) {
+        let (rect, response) = ui.allocate_exact_size(METER_SIZE, egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, egui::Color32::from_gray(30));
+
+        let rms_width = rect.width() * meter_fraction(self.output_levels.rms);
+        let rms_rect = egui::Rect::from_min_size(rect.min, egui::vec2(rms_width, rect.height()));
+        painter.rect_filled(rms_rect, 2.0, egui::Color32::GREEN);
+
+        let peak_color = if self.output_levels.peak > 1.0 {
+            egui::Color32::RED
+        } else {
+            egui::Color32::YELLOW
+        };
+        let peak_x = rect.left() + rect.width() * meter_fraction(self.output_levels.peak);
+        painter.line_segment(
+            [
+                egui::pos2(peak_x, rect.top()),
+                egui::pos2(peak_x, rect.bottom()),
+            ],
+            egui::Stroke::new(1.5, peak_color),
+        );
+        response.on_hover_text(format!(
+            "Peak {:.1} dBFS, RMS {:.1} dBFS",
+            20.0 * self.output_levels.peak.max(1e-6).log10(),
+            20.0 * self.output_levels.rms.max(1e-6).log10()
+        ));
+    }
 
     pub fn show(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::top("toolbar")
@@ -51,6 +120,35 @@ impl Toolbar {
                             error!("Failed to send SetReadPosition command: {}", e);
                         }
                     }
+                    let record_button = egui::Button::new("⏺").selected(self.recording);
+                    if ui.add(record_button).on_hover_text("Record").clicked() {
+                        self.recording = !self.recording;
+                        let command = if self.recording {
+                            AudioCommand::StartRecording
+                        } else {
+                            AudioCommand::StopRecording
+                        };
+                        let result = self.audio_controller_sender.try_send(command);
+                        if let Err(e) = result {
+                            error!("Failed to send recording command: {}", e);
+                        }
+                    }
+                    let monitor_button = egui::Button::new("🎧").selected(self.monitoring);
+                    if ui
+                        .add(monitor_button)
+                        .on_hover_text("Monitor the input, autotuned")
+                        .clicked()
+                    {
+                        // The button follows the state the controller reports back, which
+                        // stays off if the input can't be opened
+                        let result = self
+                            .audio_controller_sender
+                            .try_send(AudioCommand::SetMonitorMode(!self.monitoring));
+                        if let Err(e) = result {
+                            error!("Failed to send SetMonitorMode command: {}", e);
+                        }
+                    }
+                    self.show_meter(ui);
                 });
                 ui.horizontal(|ui| {
                     ui.label("Zoom:");
@@ -60,15 +158,47 @@ impl Toolbar {
                             .logarithmic(true),
                     )
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Tempo:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.bpm)
+                            .range(20.0..=300.0)
+                            .speed(0.5)
+                            .suffix(" BPM"),
+                    );
+                });
                 ui.horizontal(|ui| {
                     ui.label("Volume:");
-                    ui.add(egui::Slider::new(&mut self.volume_level, 0..=200).text("%"));
+                    let response =
+                        ui.add(egui::Slider::new(&mut self.volume_level, 0..=200).text("%"));
+                    // Only on change, a command every frame would flood the controller
+                    if response.changed() {
+                        self.audio_controller_sender
+                            .try_send(AudioCommand::SetVolume(self.volume_level as f32 / 100.0))
+                            .unwrap_or_else(|e| {
+                                error!("Failed to send SetVolume command: {}", e);
+                            });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Width:");
+                    let response = ui
+                        .add(egui::Slider::new(&mut self.stereo_width, 0..=200).text("%"))
+                        .on_hover_text("Stereo width, double click to reset");
+                    if response.double_clicked() {
+                        self.stereo_width = 100;
+                    }
+                    if response.changed() || response.double_clicked() {
+                        let result =
+                            self.audio_controller_sender
+                                .try_send(AudioCommand::SetStereoWidth(
+                                    self.stereo_width as f32 / 100.0,
+                                ));
+                        if let Err(e) = result {
+                            error!("Failed to send SetStereoWidth command: {}", e);
+                        }
+                    }
                 });
-            });
-        self.audio_controller_sender
-            .try_send(AudioCommand::SetVolume(self.volume_level as f32 / 100.0))
-            .unwrap_or_else(|e| {
-                error!("Failed to send SetVolume command: {}", e);
             });
     }
 }
@@ -1,12 +1,34 @@
 use crate::{
-    audio::{Audio, audio_controller::AudioCommand, file::AudioFileData},
-    gui::components::{self, clips::ClipManager, track_menu::TrackMenu},
+    audio::{
+        Audio, Channel, PyinTask,
+        audio_controller::AudioCommand,
+        autotune::{MAX_F0, MIN_F0, pyin::PyinConfig},
+        file::AudioFileData,
+        playback::OutputLevels,
+    },
+    gui::components::{
+        self,
+        clips::ClipManager,
+        track_menu::{Debouncer, TrackMenu},
+    },
 };
 use egui::Sense;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tracing::{debug, error};
 
 const SAMPLES_PER_PIXEL: f32 = 441.0;
+/// Length of the tone played when a pitch grid row is clicked
+const PREVIEW_TONE_MS: u32 = 300;
+/// Width of the draggable trim handle at each clip edge, in pixels
+const TRIM_HANDLE_WIDTH: f32 = 6.0;
+/// Quietest level shown by a dB-scaled waveform, anything below is drawn on the center line
+const WAVEFORM_DB_FLOOR: f32 = -60.0;
+/// Waveform color of a new track
+const DEFAULT_TRACK_COLOR: egui::Color32 = egui::Color32::BLUE;
+/// Quiet period after the last pan change before the pan is sent and the mix re-rendered
+const PAN_DEBOUNCE: Duration = Duration::from_millis(150);
 /// Constant that defines the amount of pixels to the left of the timeline ruler
 /// and track
 pub const LEFT_SIDE_PADDING: f32 = 50.0;
@@ -16,10 +38,121 @@ pub fn calculate_pixels_per_second(sample_rate: u32, zoom_level: f32) -> f32 {
     sample_rate as f32 / SAMPLES_PER_PIXEL * zoom_level
 }
 
+/// How sample values map to the height of a track waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaveformScale {
+    #[default]
+    Linear,
+    /// `20 * log10(|s|)` from `WAVEFORM_DB_FLOOR` to 0 dB, so quiet detail stays visible
+    Decibel,
+}
+
+/// y coordinate of `sample` in a waveform drawn across `rect`: full scale (±1.0) reaches
+/// 45% of the height above or below the center line.
+fn sample_to_y(sample: f32, mode: WaveformScale, rect: egui::Rect) -> f32 {
+    let level = match mode {
+        WaveformScale::Linear => sample,
+        WaveformScale::Decibel => {
+            let db = 20.0 * sample.abs().max(f32::MIN_POSITIVE).log10();
+            let level = (1.0 - db / WAVEFORM_DB_FLOOR).clamp(0.0, 1.0);
+            level.copysign(sample)
+        }
+    };
+    rect.center().y - level * rect.height() * 0.45
+}
+
+/// Number of minor ticks between two major ticks of the timeline
+const MINOR_TICKS_PER_MAJOR: usize = 3;
+
+/// A mark on the timeline ruler or the pitch editor's time grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimelineTick {
+    /// Relative to the start of the timeline content
+    pub x: f32,
+    pub time_sec: f32,
+    /// Major ticks are labeled, minor ones subdivide the space between them
+    pub major: bool,
+}
+
+/// Time marks for a timeline `width` pixels wide, scrolled by `scroll_px`. Major marks are
+/// a power of two seconds apart, at least 50 px, with `MINOR_TICKS_PER_MAJOR` evenly spaced
+/// minor marks after each. The list covers the visible range plus a little margin, so
+/// callers still clip to their rect.
+pub fn timeline_ticks(width: f32, pixels_per_second: f32, scroll_px: f32) -> Vec<TimelineTick> {
+    let start_time = (scroll_px / pixels_per_second).max(0.0);
+    let first_mark_time = start_time.floor();
+    let visible_duration = width / pixels_per_second;
+    let last_mark_time = first_mark_time + visible_duration + 1.0;
+
+    let min_mark_spacing_px = 50.0;
+    let mut mark_interval = 1.0; // in seconds
+    while mark_interval * pixels_per_second < min_mark_spacing_px {
+        mark_interval *= 2.0;
+    }
+
+    let minor_interval = mark_interval / (MINOR_TICKS_PER_MAJOR + 1) as f32;
+    let mut ticks = Vec::new();
+    let mut t = (first_mark_time / mark_interval) as i32;
+    while (t as f32) <= last_mark_time / mark_interval {
+        let major_time = t as f32 * mark_interval;
+        for i in 0..=MINOR_TICKS_PER_MAJOR {
+            let time_sec = major_time + i as f32 * minor_interval;
+            ticks.push(TimelineTick {
+                x: time_sec * pixels_per_second - scroll_px,
+                time_sec,
+                major: i == 0,
+            });
+        }
+        t += 1;
+    }
+    ticks
+}
+
+/// Sample index under pixel `x` of a track waveform, with `x` counted from the left edge
+/// of the waveform. Pixels left of the first sample map to sample 0.
+fn pixel_to_sample(x: f32, scroll: f32, zoom: f32) -> usize {
+    ((x + scroll) / zoom * SAMPLES_PER_PIXEL).max(0.0) as usize
+}
+
+/// Pixel of a track waveform, counted from its left edge, where `sample` is drawn
+fn sample_to_pixel(sample: usize, scroll: f32, zoom: f32) -> f32 {
+    sample as f32 * zoom / SAMPLES_PER_PIXEL - scroll
+}
+
+/// Where `position` ends up once the samples in `removed` are taken out of the track
+fn position_after_removal(position: usize, removed: &Range<usize>) -> usize {
+    if position <= removed.start {
+        position
+    } else if position >= removed.end {
+        position - removed.len()
+    } else {
+        removed.start
+    }
+}
+
+/// Edge of a clip that a trim handle moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ClipEdge {
+    Start,
+    End,
+}
+
+/// A trim handle being dragged
+#[derive(Debug, Clone, Copy)]
+struct TrimDrag {
+    clip: usize,
+    edge: ClipEdge,
+    /// Horizontal distance dragged so far, in pixels
+    delta_px: f32,
+}
+
 /// Enum for cross-thread communication between the TrackManager and the AudioController
 pub enum TrackManagerCommand {
     AddAudioClip(AudioFileData),
+    AddRecording(Audio),
     SetReadPosition(usize),
+    SetOutputLevels(OutputLevels),
+    SetMonitoring(bool),
 }
 
 /// Struct that handles managing tracks and displaying in `egui`
@@ -28,6 +161,9 @@ pub struct TrackManager {
     horizontal_scroll: f32,
     receiver: mpsc::Receiver<TrackManagerCommand>,
     read_position: usize, // This is in samples
+    output_levels: OutputLevels,
+    monitoring: bool,
+    next_track_id: u32,
     audio_controller_sender: mpsc::Sender<crate::audio::audio_controller::AudioCommand>,
 }
 
@@ -42,17 +178,95 @@ impl TrackManager {
             tracks: Vec::new(),
             receiver,
             read_position: 0,
+            output_levels: OutputLevels::default(),
+            monitoring: false,
+            next_track_id: 0,
             audio_controller_sender,
         }
     }
-    /// Adds a new track to the TrackManager and returns its ID
+    /// Adds a new track at the end of the TrackManager and returns its ID.
+    /// IDs are never reused, even after tracks are removed.
     pub fn add_track(&mut self) -> u32 {
-        let track_id = self.tracks.len() as u32;
+        let track_id = self.next_track_id;
+        self.next_track_id += 1;
         let track = Track::new(track_id, self.audio_controller_sender.clone());
         track.send_update();
         self.tracks.push(track);
         track_id
     }
+    /// Removes the track at `index` and returns its ID
+    pub fn remove_track(&mut self, index: usize) -> Option<u32> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+        let track = self.tracks.remove(index);
+        self.audio_controller_sender
+            .try_send(AudioCommand::RemoveTrack(track.id))
+            .unwrap_or_else(|e| {
+                error!("Failed to send RemoveTrack command: {}", e);
+            });
+        Some(track.id)
+    }
+    /// Moves the track at index `from` to index `to`, shifting the tracks in between.
+    /// The AudioController is sent the new order so its mix matches the track list.
+    pub fn move_track(&mut self, from: usize, to: usize) {
+        if from >= self.tracks.len() || to >= self.tracks.len() || from == to {
+            return;
+        }
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        self.audio_controller_sender
+            .try_send(AudioCommand::ReorderTracks(self.track_ids()))
+            .unwrap_or_else(|e| {
+                error!("Failed to send ReorderTracks command: {}", e);
+            });
+    }
+    /// Sets the PYIN pitch range of every track to `fmin`-`fmax` Hz and restarts their
+    /// analyses, each on its own background thread. `finished_analyses` reports every
+    /// track once its new analysis is done.
+    pub fn reanalyze_all(&mut self, fmin: f32, fmax: f32) {
+        debug!(
+            fmin,
+            fmax,
+            n_tracks = self.tracks.len(),
+            "Re-analyzing all tracks"
+        );
+        for track in &mut self.tracks {
+            track.fmin = fmin;
+            track.fmax = fmax;
+            track.reanalyze();
+        }
+    }
+    /// IDs of the tracks whose PYIN analysis finished since the last call. Each of them is
+    /// sent to the AudioController again, so the mix is rendered with the new pitch data.
+    pub fn finished_analyses(&mut self) -> Vec<u32> {
+        self.tracks
+            .iter_mut()
+            .filter(|track| {
+                track
+                    .pyin_task
+                    .as_ref()
+                    .is_some_and(|task| task.is_finished())
+            })
+            .map(|track| {
+                track.pyin_task = None;
+                track.send_update();
+                track.id
+            })
+            .collect()
+    }
+    /// Latest master output levels reported by the AudioController
+    pub fn output_levels(&self) -> OutputLevels {
+        self.output_levels
+    }
+    /// Whether the AudioController last reported the input as being monitored
+    pub fn monitoring(&self) -> bool {
+        self.monitoring
+    }
+    /// IDs of the tracks in display order
+    pub fn track_ids(&self) -> Vec<u32> {
+        self.tracks.iter().map(|track| track.id).collect()
+    }
     /// Internal function to send commands to the AudioController from the TrackManager
     /// This is non-blocking so if there is nothing in the recv queue it moves on instantly
     /// this means that there may be slight inaccuracies at frame time
@@ -62,14 +276,31 @@ impl TrackManager {
             .unwrap_or_else(|e| {
                 error!("Failed to send BroadcastPosition command: {}", e);
             });
+        self.audio_controller_sender
+            .try_send(AudioCommand::BroadcastLevels)
+            .unwrap_or_else(|e| {
+                error!("Failed to send BroadcastLevels command: {}", e);
+            });
         while let Ok(command) = self.receiver.try_recv() {
             match command {
                 TrackManagerCommand::AddAudioClip(audio_file) => {
                     clip_manager.add_clip(audio_file);
                 }
+                TrackManagerCommand::AddRecording(audio) => {
+                    let mut audio_file = AudioFileData::from_audio(&audio);
+                    audio_file.file_path =
+                        format!("Recording {}", clip_manager.get_clips().len() + 1).into();
+                    clip_manager.add_clip(audio_file);
+                }
                 TrackManagerCommand::SetReadPosition(position) => {
                     self.read_position = position;
                 }
+                TrackManagerCommand::SetOutputLevels(levels) => {
+                    self.output_levels = levels;
+                }
+                TrackManagerCommand::SetMonitoring(monitoring) => {
+                    self.monitoring = monitoring;
+                }
             }
         }
     }
@@ -82,44 +313,39 @@ impl TrackManager {
                 ui.allocate_exact_size(egui::vec2(ruler_width, ruler_height), Sense::hover());
             let painter = ui.painter_at(ruler_rect);
             let pixels_per_second = calculate_pixels_per_second(44100, zoom_level);
-            let scroll_px = self.horizontal_scroll;
-            let start_time = (scroll_px / pixels_per_second).max(0.0);
-            let first_mark_time = start_time.floor();
-            let visible_duration = ruler_width / pixels_per_second;
-            let last_mark_time = first_mark_time + visible_duration + 1.0;
-
-            let min_mark_spacing_px = 50.0;
-            let mut mark_interval = 1.0; // in seconds
-            while mark_interval * pixels_per_second < min_mark_spacing_px {
-                mark_interval *= 2.0;
-            }
-
-            let mut t = (first_mark_time / mark_interval) as i32;
-            while (t as f32) <= last_mark_time / mark_interval {
-                let time_sec = t as f32 * mark_interval;
-
-                let x = LEFT_SIDE_PADDING + ruler_rect.left() + time_sec * pixels_per_second
-                    - scroll_px;
+            let ticks = timeline_ticks(ruler_width, pixels_per_second, self.horizontal_scroll);
+            for tick in ticks {
+                let x = LEFT_SIDE_PADDING + ruler_rect.left() + tick.x;
 
                 // Only draw if inside the ruler rect
-                if x >= ruler_rect.left() && x <= ruler_rect.right() {
+                if x < ruler_rect.left() || x > ruler_rect.right() {
+                    continue;
+                }
+                if !tick.major {
+                    // Short, faint marks from the bottom edge
                     painter.line_segment(
                         [
-                            egui::pos2(x, ruler_rect.top()),
+                            egui::pos2(x, ruler_rect.bottom() - ruler_height / 3.0),
                             egui::pos2(x, ruler_rect.bottom()),
                         ],
-                        egui::Stroke::new(1.0, egui::Color32::LIGHT_GRAY),
-                    );
-                    painter.text(
-                        egui::pos2(x + 2.0, ruler_rect.top() + 2.0),
-                        egui::Align2::LEFT_TOP,
-                        format!("{:.1}s", time_sec),
-                        egui::FontId::default(),
-                        egui::Color32::WHITE,
+                        egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
                     );
+                    continue;
                 }
-
-                t += 1;
+                painter.line_segment(
+                    [
+                        egui::pos2(x, ruler_rect.top()),
+                        egui::pos2(x, ruler_rect.bottom()),
+                    ],
+                    egui::Stroke::new(1.0, egui::Color32::LIGHT_GRAY),
+                );
+                painter.text(
+                    egui::pos2(x + 2.0, ruler_rect.top() + 2.0),
+                    egui::Align2::LEFT_TOP,
+                    format!("{:.1}s", tick.time_sec),
+                    egui::FontId::default(),
+                    egui::Color32::WHITE,
+                );
             }
         });
     }
@@ -153,6 +379,9 @@ impl TrackManager {
         ctx: &egui::Context,
     ) {
         self.audio_controller_communication(clip_manager);
+        for track_id in self.finished_analyses() {
+            debug!(track_id, "PYIN analysis finished");
+        }
 
         let response = egui::CentralPanel::default().show(ctx, |ui| {
             self.show_timeline_ruler(toolbar.get_zoom_level(), ui);
@@ -163,13 +392,15 @@ impl TrackManager {
             let mut i = 0;
             while i < self.tracks.len() {
                 let track = &mut self.tracks[i];
-                if track.show(i, toolbar.get_zoom_level(), self.horizontal_scroll, ui, ctx) {
-                    self.tracks.remove(i);
-                    self.audio_controller_sender
-                        .try_send(AudioCommand::RemoveTrack(i as u32))
-                        .unwrap_or_else(|e| {
-                            error!("Failed to send RemoveTrack command: {}", e);
-                        });
+                if track.show(
+                    i,
+                    toolbar.get_zoom_level(),
+                    toolbar.get_bpm(),
+                    self.horizontal_scroll,
+                    ui,
+                    ctx,
+                ) {
+                    self.remove_track(i);
                 } else {
                     i += 1;
                 }
@@ -191,12 +422,22 @@ impl TrackManager {
     }
 }
 
-#[derive(Clone)]
 pub struct Track {
     id: u32,
+    name: String,
+    color: egui::Color32, // Waveform color
     audio: Audio,
+    pyin_task: Option<PyinTask>,
     muted: bool,
     soloed: bool,
+    pan: f32,                 // -1.0 (left) to 1.0 (right)
+    pan_debounce: Debouncer,  // Sends the pan once the slider settles
+    fmin: f32,                // Lowest pitch PYIN looks for, in Hz
+    fmax: f32,                // Highest pitch PYIN looks for, in Hz
+    comparing_original: bool, // Last A/B state sent to the AudioController
+    clips: Vec<Range<usize>>, // Sample ranges of the clips dropped onto the track
+    trim_drag: Option<TrimDrag>,
+    waveform_scale: WaveformScale,
     menu: TrackMenu,
     audio_controller_sender: mpsc::Sender<AudioCommand>,
 }
@@ -204,16 +445,179 @@ pub struct Track {
 impl Track {
     pub fn new(id: u32, audio_controller_sender: mpsc::Sender<AudioCommand>) -> Self {
         let mut audio = Audio::new(44100, Vec::new(), Vec::new());
-        audio.perform_pyin_background();
+        let pyin_task = audio.perform_pyin_background();
         Track {
             id,
+            name: format!("Track {}", id + 1),
+            color: DEFAULT_TRACK_COLOR,
             audio,
+            pyin_task: Some(pyin_task),
             muted: false,
             soloed: false,
+            pan: 0.0,
+            pan_debounce: Debouncer::new(PAN_DEBOUNCE),
+            fmin: MIN_F0,
+            fmax: MAX_F0,
+            comparing_original: false,
+            clips: Vec::new(),
+            trim_drag: None,
+            waveform_scale: WaveformScale::default(),
             menu: TrackMenu::new(),
             audio_controller_sender,
         }
     }
+    /// Name shown in the control area
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn rename(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+    }
+    /// Sets the pan. The AudioController is only told once the pan hasn't changed for
+    /// `PAN_DEBOUNCE`, see `poll_pan`, so dragging the slider doesn't remix on every step.
+    fn set_pan(&mut self, pan: f32, now: Instant) {
+        self.pan = pan;
+        self.pan_debounce.mark_changed(now);
+    }
+    /// Sends the pan to the AudioController once it has settled, returns whether it did
+    fn poll_pan(&mut self, now: Instant) -> bool {
+        if !self.pan_debounce.poll(now) {
+            return false;
+        }
+        self.audio_controller_sender
+            .try_send(AudioCommand::SetTrackPan(self.id, self.pan))
+            .unwrap_or_else(|e| {
+                error!("Failed to send SetTrackPan command: {}", e);
+            });
+        true
+    }
+    /// Restarts PYIN analysis of the track audio, cancelling any analysis still running
+    fn reanalyze(&mut self) {
+        if let Some(task) = self.pyin_task.take()
+            && !task.is_finished()
+        {
+            debug!(track_id = self.id, "Cancelling in-flight PYIN analysis");
+            task.cancel();
+        }
+        self.pyin_task = Some(
+            self.audio
+                .perform_pyin_background_with_config(self.pyin_config()),
+        );
+    }
+    /// Analysis parameters for the track audio, with the track's pitch range
+    fn pyin_config(&self) -> PyinConfig {
+        PyinConfig {
+            fmin: self.fmin,
+            fmax: self.fmax,
+            ..Default::default()
+        }
+    }
+    /// Trims `samples` samples (at most the whole clip) off the `edge` of clip `clip`,
+    /// removing them from the track audio. Everything after them moves up to close the gap,
+    /// along with its frames of desired F0, tune mask and PYIN data. If the analysis was
+    /// current only the frames around the cut are re-analyzed.
+    fn trim_clip(&mut self, clip: usize, edge: ClipEdge, samples: usize) {
+        let Some(range) = self.clips.get(clip).cloned() else {
+            return;
+        };
+        let samples = samples.min(range.len());
+        if samples == 0 {
+            return;
+        }
+        let removed = match edge {
+            ClipEdge::Start => range.start..range.start + samples,
+            ClipEdge::End => range.end - samples..range.end,
+        };
+        debug!(track_id = self.id, clip, ?edge, ?removed, "Trimming clip");
+        let config = self.pyin_config();
+        let edited_from = self.audio.analysis_generation();
+        let was_current = self.audio.pyin_is_current();
+        self.audio.remove_region(removed.start, removed.len());
+        self.audio
+            .remove_region_frames(removed.start, removed.len(), config.hop_length);
+        self.clips = self
+            .clips
+            .iter()
+            .map(|clip| {
+                position_after_removal(clip.start, &removed)
+                    ..position_after_removal(clip.end, &removed)
+            })
+            .filter(|clip| !clip.is_empty())
+            .collect();
+        if was_current {
+            // Only the frames whose window spans the cut saw samples from both sides of it
+            self.audio
+                .analyze_region(removed.start.saturating_sub(1), 2, config, edited_from);
+        } else {
+            self.reanalyze();
+        }
+        self.send_update();
+    }
+    /// Draws a trim handle at both edges of every clip in the waveform `rect`. Returns the
+    /// trim to apply once a handle is released, as (clip, edge, samples).
+    fn show_trim_handles(
+        &mut self,
+        rect: egui::Rect,
+        zoom: f32,
+        scroll: f32,
+        ui: &mut egui::Ui,
+    ) -> Option<(usize, ClipEdge, usize)> {
+        let painter = ui.painter_at(rect);
+        let mut request = None;
+        for (i, clip) in self.clips.iter().enumerate() {
+            for (edge, sample) in [(ClipEdge::Start, clip.start), (ClipEdge::End, clip.end)] {
+                let x = rect.left() + sample_to_pixel(sample, scroll, zoom);
+                if x < rect.left() || x > rect.right() {
+                    continue;
+                }
+                let handle = egui::Rect::from_center_size(
+                    egui::pos2(x, rect.center().y),
+                    egui::vec2(TRIM_HANDLE_WIDTH, rect.height()),
+                );
+                let id = ui.make_persistent_id(("trim_handle", self.id, i, edge));
+                let response = ui
+                    .interact(handle, id, Sense::drag())
+                    .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+                painter.rect_filled(handle, 2.0, egui::Color32::LIGHT_GRAY);
+
+                if response.drag_started() {
+                    self.trim_drag = Some(TrimDrag {
+                        clip: i,
+                        edge,
+                        delta_px: 0.0,
+                    });
+                }
+                let Some(drag) = self.trim_drag.as_mut() else {
+                    continue;
+                };
+                if drag.clip != i || drag.edge != edge {
+                    continue;
+                }
+                drag.delta_px += response.drag_delta().x;
+                // Handles only move inwards, a clip can't grow past the audio it holds
+                let inward_px = match edge {
+                    ClipEdge::Start => drag.delta_px,
+                    ClipEdge::End => -drag.delta_px,
+                };
+                let samples = pixel_to_sample(inward_px, 0.0, zoom).min(clip.len());
+                let trimmed = match edge {
+                    ClipEdge::Start => clip.start..clip.start + samples,
+                    ClipEdge::End => clip.end - samples..clip.end,
+                };
+                let shade = egui::Rect::from_x_y_ranges(
+                    rect.left() + sample_to_pixel(trimmed.start, scroll, zoom)
+                        ..=rect.left() + sample_to_pixel(trimmed.end, scroll, zoom),
+                    rect.y_range(),
+                );
+                painter.rect_filled(shade, 0.0, egui::Color32::from_black_alpha(160));
+                if response.drag_stopped() {
+                    request = Some((i, edge, samples));
+                    self.trim_drag = None;
+                }
+            }
+        }
+        request
+    }
     pub fn send_update(&self) {
         debug!(track_id = self.id, "Sending UpdateTrackAudio command");
         let audio_data = self.audio.clone();
@@ -230,12 +634,49 @@ impl Track {
         &mut self,
         index: usize,
         zoom: f32,
+        bpm: f32,
         scroll: f32,
         ui: &mut egui::Ui,
         ctx: &egui::Context,
     ) -> bool {
         if self.menu.is_open() {
-            let staying_open = self.menu.show_menu(self.id, &mut self.audio, ui, ctx);
+            let staying_open = self.menu.show_menu(
+                self.id,
+                &mut self.audio,
+                &mut self.fmin,
+                &mut self.fmax,
+                bpm,
+                ctx,
+            );
+            if self.menu.compare_original() != self.comparing_original {
+                self.comparing_original = self.menu.compare_original();
+                self.audio_controller_sender
+                    .try_send(AudioCommand::SetCompareOriginal(
+                        self.id,
+                        self.comparing_original,
+                    ))
+                    .unwrap_or_else(|e| {
+                        error!("Failed to send SetCompareOriginal command: {}", e);
+                    });
+            }
+            if let Some(frequency) = self.menu.take_preview_request() {
+                self.audio_controller_sender
+                    .try_send(AudioCommand::PlayTone(frequency, PREVIEW_TONE_MS))
+                    .unwrap_or_else(|e| {
+                        error!("Failed to send PlayTone command: {}", e);
+                    });
+            }
+            if self.menu.take_reanalyze_request() {
+                debug!(
+                    track_id = self.id,
+                    self.fmin, self.fmax, "Re-analyzing track"
+                );
+                self.reanalyze();
+                self.send_update();
+            }
+            if self.menu.take_rerender_request() {
+                self.send_update();
+            }
             if !staying_open {
                 self.audio_controller_sender
                     .try_send(AudioCommand::SendTrack(self.audio.clone(), self.id))
@@ -254,7 +695,27 @@ impl Track {
                 // Left control area
                 ui.vertical(|ui| {
                     ui.set_min_width(LEFT_SIDE_PADDING - 7.0);
-                    ui.label(format!("Track {}", index + 1));
+                    ui.horizontal(|ui| {
+                        ui.style_mut().spacing.item_spacing.x = 2.0;
+                        egui::color_picker::color_edit_button_srgba(
+                            ui,
+                            &mut self.color,
+                            egui::color_picker::Alpha::Opaque,
+                        )
+                        .on_hover_text("Track color");
+                        if self.audio.pyin_failed() {
+                            ui.colored_label(egui::Color32::RED, "!").on_hover_text(
+                                "Pitch analysis failed, re-analyze from the track menu",
+                            );
+                        }
+                        let mut name = self.name.clone();
+                        let name_edit = egui::TextEdit::singleline(&mut name)
+                            .hint_text(format!("Track {}", index + 1))
+                            .desired_width(ui.available_width());
+                        if ui.add(name_edit).on_hover_text("Track name").changed() {
+                            self.rename(name);
+                        }
+                    });
                     if ui.button("Tune").on_hover_text("Autotune Track").clicked() {
                         self.menu.open();
 
@@ -284,13 +745,39 @@ impl Track {
                             self.muted = !self.muted;
                             self.send_update();
                         }
+
+                        let decibel = self.waveform_scale == WaveformScale::Decibel;
+                        let db_button = egui::Button::new("dB").selected(decibel).min_size(egui::vec2(20.0, 20.0));
+                        if ui.add(db_button).on_hover_text("Show the waveform in decibels").clicked() {
+                            self.waveform_scale = if decibel {
+                                WaveformScale::Linear
+                            } else {
+                                WaveformScale::Decibel
+                            };
+                        }
                     });
+                    ui.spacing_mut().slider_width = LEFT_SIDE_PADDING - 7.0;
+                    let mut pan = self.pan;
+                    let pan_response = ui
+                        .add(egui::Slider::new(&mut pan, -1.0..=1.0).show_value(false))
+                        .on_hover_text("Pan");
+                    if pan_response.double_clicked() {
+                        pan = 0.0;
+                    }
+                    if pan_response.changed() || pan_response.double_clicked() {
+                        self.set_pan(pan, Instant::now());
+                    }
+                    self.poll_pan(Instant::now());
+                    if self.pan_debounce.is_pending() {
+                        // Keep repainting so the quiet period is noticed without further input
+                        ui.ctx().request_repaint_after(PAN_DEBOUNCE);
+                    }
                     if ui.small_button("×").on_hover_text("Delete Track").clicked() {
                         wants_delete = true;
                     }
                 });
                 ui.visuals_mut().widgets.inactive.bg_fill = egui::Color32::TRANSPARENT;
-                let (drop_zone_rsp, payload) = ui.dnd_drop_zone::<AudioFileData, egui::Response>(
+                let (drop_zone_rsp, payload) = ui.dnd_drop_zone::<AudioFileData, _>(
                     egui::Frame::default().fill(egui::Color32::TRANSPARENT),
                     |ui| {
                         let desired_size = egui::vec2(ui.available_width(), ui.available_height());
@@ -301,37 +788,44 @@ impl Track {
                         painter.rect_filled(rect, 5.0, egui::Color32::from_rgb(50, 50, 50));
 
                         // Draw waveform (min/max per pixel)
-                        let samples = &self.audio.left();
                         let width = rect.width() as usize;
 
                         for x in 0..width{
-                            let sample_idx = ((x as f32 + scroll) / zoom * SAMPLES_PER_PIXEL) as usize;
-                            if sample_idx >= samples.len() {
+                            let start = pixel_to_sample(x as f32, scroll, zoom);
+                            let end = pixel_to_sample((x + 1) as f32, scroll, zoom).max(start + 1);
+                            let samples = self.audio.samples_range(Channel::Left, start, end);
+                            if samples.is_empty() {
                                 break;
                             }
-                            let v = samples[sample_idx]; // -1.0 .. 1.0
+                            // -1.0 .. 1.0
+                            let max = samples.iter().copied().fold(f32::MIN, f32::max);
+                            let min = samples.iter().copied().fold(f32::MAX, f32::min);
 
-                            let mid_y = rect.center().y;
-                            let amp = v * rect.height() * 0.45;
+                            let top = sample_to_y(max, self.waveform_scale, rect);
+                            let bottom = sample_to_y(min, self.waveform_scale, rect);
 
                             painter.line_segment(
                                 [
-                                egui::pos2(rect.left() + x as f32, mid_y - amp),
-                                egui::pos2(rect.left() + x as f32, mid_y + amp),
+                                egui::pos2(rect.left() + x as f32, top),
+                                egui::pos2(rect.left() + x as f32, bottom),
                                 ],
-                                egui::Stroke::new(1.0, egui::Color32::BLUE),
+                                egui::Stroke::new(1.0, self.color),
                             );
                         }
-                        response
+                        let trim = self.show_trim_handles(rect, zoom, scroll, ui);
+                        (response, trim)
                     },
                     );
+                if let (_, Some((clip, edge, samples))) = drop_zone_rsp.inner {
+                    self.trim_clip(clip, edge, samples);
+                }
                 // Handling audio clip drag and drop
                 if let Some(clip) = payload {
-                    if drop_zone_rsp.inner.hovered() {
+                    if drop_zone_rsp.inner.0.hovered() {
                         if let Some(pos) = ui.ctx().pointer_interact_pos() {
                             // Convert absolute position to time/sample index
-                            let relative_x = pos.x - drop_zone_rsp.inner.rect.left();
-                            let sample_index = ((relative_x / zoom) as usize) * 250;
+                            let relative_x = pos.x - track_left;
+                            let sample_index = pixel_to_sample(relative_x, scroll, zoom);
                             debug!(?pos, ?relative_x, ?sample_index, "Dropped clip at position");
                             let audio_data = clip.to_audio();
                             let result = self.audio.insert_audio_at(sample_index, &audio_data);
@@ -339,8 +833,9 @@ impl Track {
                                 error!("Failed to insert audio clip: {}", e);
                                 return;
                             }
+                            self.clips.push(sample_index..sample_index + audio_data.length());
                             debug!(audio = ?self.audio.length(), "Ending audio length after insertion");
-                            self.audio.perform_pyin_background();
+                            self.reanalyze();
                             self.send_update();
                         }
                     }
@@ -350,3 +845,294 @@ impl Track {
         wants_delete
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_manager() -> (TrackManager, mpsc::Receiver<AudioCommand>) {
+        let (_track_manager_sender, track_manager_receiver) = mpsc::channel(16);
+        let (audio_sender, audio_receiver) = mpsc::channel(64);
+        (
+            TrackManager::new(track_manager_receiver, audio_sender),
+            audio_receiver,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_recording_is_added_as_a_clip() {
+        let (track_manager_sender, track_manager_receiver) = mpsc::channel(16);
+        let (audio_sender, _audio_receiver) = mpsc::channel(64);
+        let mut manager = TrackManager::new(track_manager_receiver, audio_sender);
+        let mut clip_manager = ClipManager::new();
+        let take = Audio::new(44100, vec![0.25; 512], vec![-0.5; 512]);
+        for _ in 0..2 {
+            track_manager_sender
+                .try_send(TrackManagerCommand::AddRecording(take.clone()))
+                .unwrap();
+        }
+
+        manager.audio_controller_communication(&mut clip_manager);
+        let clips = clip_manager.get_clips();
+        let names: Vec<_> = clips.iter().map(|clip| clip.file_path.clone()).collect();
+        assert_eq!(
+            names,
+            ["Recording 1", "Recording 2"].map(std::path::PathBuf::from)
+        );
+        // Ready to be dragged onto a track like any loaded file
+        assert!(clips[0].to_audio().approx_eq(&take, 1e-6));
+    }
+
+    #[tokio::test]
+    async fn test_track_ids_never_collide() {
+        let (mut manager, _audio_receiver) = track_manager();
+        let first = manager.add_track();
+        let second = manager.add_track();
+        let third = manager.add_track();
+        assert_eq!(manager.remove_track(1), Some(second));
+        let fourth = manager.add_track();
+        manager.remove_track(0);
+        let fifth = manager.add_track();
+
+        let ids = manager.track_ids();
+        assert_eq!(ids, vec![third, fourth, fifth]);
+        let mut all = vec![first, second, third, fourth, fifth];
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), 5);
+    }
+
+    #[test]
+    fn test_timeline_ticks_spacing_and_positions() {
+        let major = |ticks: &[TimelineTick]| -> Vec<(f32, f32)> {
+            ticks
+                .iter()
+                .filter(|tick| tick.major)
+                .map(|tick| (tick.x, tick.time_sec))
+                .collect()
+        };
+
+        // 100 px per second: one mark per second
+        let ticks = timeline_ticks(300.0, 100.0, 0.0);
+        assert_eq!(
+            major(&ticks),
+            [
+                (0.0, 0.0),
+                (100.0, 1.0),
+                (200.0, 2.0),
+                (300.0, 3.0),
+                (400.0, 4.0)
+            ]
+        );
+
+        // 20 px per second: marks every 4 seconds (80 px), shifted by the scroll
+        let ticks = major(&timeline_ticks(200.0, 20.0, 170.0));
+        assert_eq!(ticks[0], (-10.0, 8.0));
+        for pair in ticks.windows(2) {
+            assert_eq!(pair[1].0 - pair[0].0, 80.0);
+            assert_eq!(pair[1].1 - pair[0].1, 4.0);
+        }
+        // Marks reach across the 200 px wide view
+        assert!(ticks.last().unwrap().0 + 80.0 >= 200.0);
+    }
+
+    #[test]
+    fn test_timeline_ticks_have_three_minor_ticks_per_major() {
+        let ticks = timeline_ticks(300.0, 100.0, 0.0);
+        let major_indices: Vec<usize> = (0..ticks.len()).filter(|&i| ticks[i].major).collect();
+        assert!(major_indices.len() >= 2);
+        for pair in major_indices.windows(2) {
+            assert_eq!(pair[1] - pair[0] - 1, 3);
+        }
+        // Evenly spaced quarters of the 100 px major interval
+        for pair in ticks.windows(2) {
+            assert_eq!(pair[1].x - pair[0].x, 25.0);
+        }
+    }
+
+    #[test]
+    fn test_sample_to_y_linear_and_decibel() {
+        let rect = egui::Rect::from_min_size(egui::pos2(0.0, 100.0), egui::vec2(200.0, 100.0));
+        // Center at y = 150, full scale 45 px away
+        for mode in [WaveformScale::Linear, WaveformScale::Decibel] {
+            assert_eq!(sample_to_y(0.0, mode, rect), 150.0);
+            assert_eq!(sample_to_y(1.0, mode, rect), 105.0);
+            assert_eq!(sample_to_y(-1.0, mode, rect), 195.0);
+        }
+
+        assert_eq!(sample_to_y(0.5, WaveformScale::Linear, rect), 127.5);
+        assert_eq!(sample_to_y(0.001, WaveformScale::Linear, rect), 149.955);
+
+        // -20 dB is a third of the way down the 60 dB range, the floor and below sit at center
+        let y = sample_to_y(0.1, WaveformScale::Decibel, rect);
+        assert!((y - 120.0).abs() < 1e-3, "{}", y);
+        let y = sample_to_y(-0.1, WaveformScale::Decibel, rect);
+        assert!((y - 180.0).abs() < 1e-3, "{}", y);
+        assert_eq!(sample_to_y(0.001, WaveformScale::Decibel, rect), 150.0);
+        assert_eq!(sample_to_y(1e-5, WaveformScale::Decibel, rect), 150.0);
+        // Quiet samples stand out far more than on the linear scale
+        assert!(sample_to_y(0.01, WaveformScale::Decibel, rect) < 140.0);
+    }
+
+    #[test]
+    fn test_pixel_to_sample_follows_zoom_and_scroll() {
+        assert_eq!(pixel_to_sample(0.0, 0.0, 1.0), 0);
+        assert_eq!(pixel_to_sample(10.0, 0.0, 1.0), 4410);
+        // Zooming in to 2x halves the samples per pixel
+        assert_eq!(pixel_to_sample(10.0, 0.0, 2.0), 2205);
+        assert_eq!(pixel_to_sample(10.0, 5.0, 1.0), 6615);
+        // Left of the first sample
+        assert_eq!(pixel_to_sample(-20.0, 5.0, 1.0), 0);
+        for (sample, scroll, zoom) in [(4410, 0.0, 1.0), (88200, 30.0, 0.5), (1000, 2.0, 3.0)] {
+            let x = sample_to_pixel(sample, scroll, zoom);
+            assert!(pixel_to_sample(x, scroll, zoom).abs_diff(sample) <= 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trim_clip_shortens_audio_and_moves_later_clips() {
+        let (sender, _receiver) = mpsc::channel(16);
+        let mut track = Track::new(0, sender);
+        let samples: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+        track.audio = Audio::new(44100, samples.clone(), samples);
+        track.clips = vec![100..400, 500..900];
+
+        // 50 samples off the start of the first clip
+        track.trim_clip(0, ClipEdge::Start, 50);
+        assert_eq!(track.audio.length(), 950);
+        assert_eq!(track.audio.left()[100], 150.0);
+        assert_eq!(track.clips, vec![100..350, 450..850]);
+
+        // The end of the second clip, asking for more than it holds removes the whole clip
+        track.trim_clip(1, ClipEdge::End, 1000);
+        assert_eq!(track.audio.length(), 550);
+        assert_eq!(track.audio.left()[449], 499.0);
+        assert_eq!(track.audio.left()[450], 900.0);
+        assert_eq!(track.clips, vec![100..350]);
+
+        // Unknown clips are ignored
+        track.trim_clip(5, ClipEdge::Start, 10);
+        assert_eq!(track.audio.length(), 550);
+    }
+
+    #[tokio::test]
+    async fn test_trim_clip_keeps_later_edits_on_their_samples() {
+        let (sender, _receiver) = mpsc::channel(16);
+        let mut track = Track::new(0, sender);
+        track.audio = Audio::synth_sine(220.0, 16000, 1.0);
+        track.audio.perform_pyin_with_config(track.pyin_config());
+        // Forget the analysis `Track::new` started on its empty audio
+        track.pyin_task = None;
+        let n_frames = track.audio.get_pyin().unwrap().f0().len();
+        // An edit at sample 12032 (frame 47), after the region about to be trimmed
+        let mut desired_f0 = vec![220.0; n_frames];
+        desired_f0[47] = 440.0;
+        let mut tune_mask = vec![true; n_frames];
+        tune_mask[47] = false;
+        track.audio.desired_f0 = Some(desired_f0);
+        track.audio.tune_mask = Some(tune_mask);
+        track.clips = vec![0..4000, 4000..16000];
+
+        // 10 hops off the start of the second clip, the edit moves to sample 9472 (frame 37)
+        track.trim_clip(1, ClipEdge::Start, 2560);
+        let desired_f0 = track.audio.desired_f0.as_ref().unwrap();
+        let tune_mask = track.audio.tune_mask.as_ref().unwrap();
+        assert_eq!(desired_f0.len(), n_frames - 10);
+        assert_eq!(desired_f0[37], 440.0);
+        assert_eq!(desired_f0.iter().filter(|&&f| f == 440.0).count(), 1);
+        assert!(!tune_mask[37]);
+        assert_eq!(tune_mask.iter().filter(|&&m| !m).count(), 1);
+
+        // Only the frames around the cut were re-analyzed, in place
+        assert!(track.pyin_task.is_none());
+        assert!(track.audio.pyin_is_current());
+        let pyin = track.audio.get_pyin().unwrap();
+        assert_eq!(pyin.f0().len(), desired_f0.len());
+    }
+
+    #[test]
+    fn test_pan_drag_sends_one_pan_once_settled() {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let mut track = Track::new(0, sender);
+        let start = Instant::now();
+        // A drag across the slider, one step per frame
+        for (i, pan) in [0.1, 0.2, 0.3].into_iter().enumerate() {
+            let now = start + Duration::from_millis(16 * i as u64);
+            track.set_pan(pan, now);
+            assert!(!track.poll_pan(now));
+        }
+        assert!(receiver.try_recv().is_err());
+
+        assert!(track.poll_pan(start + Duration::from_millis(32) + PAN_DEBOUNCE));
+        let command = receiver.try_recv().unwrap();
+        assert!(matches!(command, AudioCommand::SetTrackPan(0, pan) if pan == 0.3));
+        assert!(!track.poll_pan(start + Duration::from_secs(1)));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_rename_updates_track_name() {
+        let (sender, _receiver) = mpsc::channel(16);
+        let mut track = Track::new(2, sender);
+        assert_eq!(track.name(), "Track 3");
+        assert_eq!(track.color, DEFAULT_TRACK_COLOR);
+
+        track.rename("Lead vocal");
+        assert_eq!(track.name(), "Lead vocal");
+    }
+
+    #[tokio::test]
+    async fn test_reanalyze_all_updates_every_track() {
+        let (mut manager, mut audio_receiver) = track_manager();
+        let ids = [manager.add_track(), manager.add_track()];
+        for track in &mut manager.tracks {
+            track.audio = Audio::synth_sine(110.0, 16000, 0.5);
+        }
+        let (fmin, fmax) = (400.0, 2000.0);
+        manager.reanalyze_all(fmin, fmax);
+
+        let mut finished = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        while finished.len() < ids.len() && std::time::Instant::now() < deadline {
+            finished.extend(manager.finished_analyses());
+            // Also lets the spawned SendTrack tasks run
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        finished.sort_unstable();
+        assert_eq!(finished, ids);
+
+        for track in &manager.tracks {
+            assert_eq!((track.fmin, track.fmax), (fmin, fmax));
+            let pyin = track.audio.get_pyin().expect("analysis should be stored");
+            assert!(!pyin.f0().is_empty());
+            // The 110 Hz fundamental is below the new range
+            assert!(pyin.f0().iter().all(|&f| f == 0.0 || f >= fmin * 0.8));
+        }
+        tokio::task::yield_now().await;
+        let mut updated = Vec::new();
+        while let Ok(command) = audio_receiver.try_recv() {
+            if let AudioCommand::SendTrack(_, id) = command {
+                updated.push(id);
+            }
+        }
+        assert!(ids.iter().all(|id| updated.contains(id)));
+    }
+
+    #[tokio::test]
+    async fn test_move_track_reorders_and_notifies_controller() {
+        let (mut manager, mut audio_receiver) = track_manager();
+        for _ in 0..3 {
+            manager.add_track();
+        }
+        manager.move_track(0, 2);
+        assert_eq!(manager.track_ids(), vec![1, 2, 0]);
+
+        let mut reordered = None;
+        while let Ok(command) = audio_receiver.try_recv() {
+            if let AudioCommand::ReorderTracks(order) = command {
+                reordered = Some(order);
+            }
+        }
+        assert_eq!(reordered, Some(vec![1, 2, 0]));
+    }
+}
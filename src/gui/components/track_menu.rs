@@ -1,10 +1,75 @@
+use crate::audio::autotune::{self, HOP_LENGTH};
+use crate::audio::scales::{Key, Note, Scale};
 use crate::audio::{self, Audio};
-use crate::gui::components::track::calculate_pixels_per_second;
+use crate::gui::components::track::{calculate_pixels_per_second, timeline_ticks};
 use egui::Sense;
+use std::time::{Duration, Instant};
 use tracing::debug;
 
 const LEFT_SIDE_PADDING: f32 = 40.0;
 const VERTICAL_NOTE_SPACING: f32 = 15.0;
+const IN_KEY_ROW_COLOR: egui::Color32 = egui::Color32::GRAY;
+const OUT_OF_KEY_ROW_COLOR: egui::Color32 = egui::Color32::DARK_GRAY;
+const LOW_CONFIDENCE_COLOR: egui::Color32 = egui::Color32::GRAY;
+const HIGH_CONFIDENCE_COLOR: egui::Color32 = egui::Color32::BLUE;
+/// Quiet period after the last desired-pitch edit before the track is re-rendered
+const DESIRED_F0_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Coalesces a burst of changes into a single trigger once no change has
+/// happened for `interval`.
+#[derive(Clone, Debug)]
+pub struct Debouncer {
+    last_change: Option<Instant>,
+    interval: Duration,
+}
+
+impl Debouncer {
+    pub fn new(interval: Duration) -> Self {
+        Debouncer {
+            last_change: None,
+            interval,
+        }
+    }
+    /// Records a change at `now`, restarting the quiet period
+    pub fn mark_changed(&mut self, now: Instant) {
+        self.last_change = Some(now);
+    }
+    /// Whether a change is waiting for its quiet period to pass
+    pub fn is_pending(&self) -> bool {
+        self.last_change.is_some()
+    }
+    /// Returns true once, when `interval` has passed since the last change
+    pub fn poll(&mut self, now: Instant) -> bool {
+        match self.last_change {
+            Some(last) if now.duration_since(last) >= self.interval => {
+                self.last_change = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Grid line color for a note row: rows whose note is in `key` are drawn brighter.
+fn row_color_for_midi(midi: u8, key: Key) -> egui::Color32 {
+    if key.contains(midi) {
+        IN_KEY_ROW_COLOR
+    } else {
+        OUT_OF_KEY_ROW_COLOR
+    }
+}
+
+/// Color of a detected pitch dot: gray at the 0.5 voicing threshold, fading to
+/// bright blue as `prob` approaches 1.0.
+fn confidence_color(prob: f32) -> egui::Color32 {
+    let t = ((prob - 0.5) / 0.5).clamp(0.0, 1.0);
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    egui::Color32::from_rgb(
+        lerp(LOW_CONFIDENCE_COLOR.r(), HIGH_CONFIDENCE_COLOR.r()),
+        lerp(LOW_CONFIDENCE_COLOR.g(), HIGH_CONFIDENCE_COLOR.g()),
+        lerp(LOW_CONFIDENCE_COLOR.b(), HIGH_CONFIDENCE_COLOR.b()),
+    )
+}
 
 fn frame_to_screen(
     frame_idx: usize,
@@ -16,7 +81,7 @@ fn frame_to_screen(
     LEFT_SIDE_PADDING + rect.left() + time_sec * pixels_per_second - scroll_px
 }
 
-/// Map a MIDI value to a y coordinate using fixed spacing per note, taking
+/// Map a MIDI value to a y coordinate using `note_spacing` pixels per note, taking
 /// vertical_scroll into account. Higher MIDI -> smaller y.
 fn midi_to_y(
     midi: f32,
@@ -24,23 +89,24 @@ fn midi_to_y(
     min_midi: f32,
     max_midi: f32,
     vertical_scroll: f32,
+    note_spacing: f32,
 ) -> f32 {
     let clamped = midi.clamp(min_midi, max_midi);
 
-    // Highest MIDI at the top, lowest at the bottom, using VERTICAL_NOTE_SPACING
+    // Highest MIDI at the top, lowest at the bottom, note_spacing apart
     let top_midi = max_midi;
 
     // y position counted in "note steps" from the top
     let note_offset_from_top = top_midi - clamped; // 0 for top note, increasing downward
-    let y = rect.top() + note_offset_from_top * VERTICAL_NOTE_SPACING;
+    let y = rect.top() + note_offset_from_top * note_spacing;
 
     y + vertical_scroll
 }
 
-/// Height of the full note range based on fixed vertical spacing.
-fn note_range_to_height(min_midi: f32, max_midi: f32, _rect: egui::Rect) -> f32 {
+/// Height of the full note range at `note_spacing` pixels per note.
+fn note_range_to_height(min_midi: f32, max_midi: f32, note_spacing: f32) -> f32 {
     let note_span = (max_midi - min_midi).max(1.0);
-    note_span * VERTICAL_NOTE_SPACING
+    note_span * note_spacing
 }
 
 fn freq_to_y(
@@ -49,13 +115,21 @@ fn freq_to_y(
     min_midi: f32,
     max_midi: f32,
     vertical_scroll: f32,
+    note_spacing: f32,
 ) -> Option<f32> {
     if freq <= 0.0 {
         return None;
     }
 
     let note = audio::scales::frequency_to_midi_note(freq) as f32;
-    Some(midi_to_y(note, rect, min_midi, max_midi, vertical_scroll))
+    Some(midi_to_y(
+        note,
+        rect,
+        min_midi,
+        max_midi,
+        vertical_scroll,
+        note_spacing,
+    ))
 }
 
 fn y_to_freq(
@@ -64,8 +138,9 @@ fn y_to_freq(
     min_midi: f32,
     max_midi: f32,
     vertical_scroll: f32,
+    note_spacing: f32,
 ) -> Option<f32> {
-    // Invert the fixed-spacing mapping used in midi_to_y
+    // Invert the mapping used in midi_to_y
     let note_span = (max_midi - min_midi).max(1.0);
     if note_span == 0.0 {
         return None;
@@ -77,7 +152,7 @@ fn y_to_freq(
     let dy = y_adj - rect.top();
 
     // how many notes down from the top (0 at top note)
-    let note_offset_from_top = dy / VERTICAL_NOTE_SPACING;
+    let note_offset_from_top = dy / note_spacing;
 
     let top_midi = max_midi;
     let midi = top_midi - note_offset_from_top;
@@ -92,9 +167,20 @@ pub struct TrackMenu {
     horizontal_scroll: f32,
     vertical_scroll: f32,
     zoom_level: f32,
+    vertical_zoom: f32, // Scales VERTICAL_NOTE_SPACING
     cached_desired_f0: Option<Vec<f32>>,
     apply_autotune: bool,
     volume_level: u32, // Volume level from 0 to 200
+    key_root: Note,
+    key_scale: Scale,
+    reanalyze_requested: bool,
+    compare_original: bool, // True while the A/B button is held
+    preview_requested: Option<f32>,
+    quantize_subdivisions: u32, // Grid steps per beat, e.g. 2 for 1/8 notes in 4/4
+    glide_frames: usize,        // Length of the portamento between notes, in pitch frames
+    flatten_note: Note,
+    flatten_octave: u8,
+    rerender_debounce: Debouncer, // Re-render after desired pitch edits settle
 }
 
 impl TrackMenu {
@@ -104,23 +190,62 @@ impl TrackMenu {
             horizontal_scroll: 0.0,
             vertical_scroll: 0.0,
             zoom_level: 1.0,
+            vertical_zoom: 1.0,
             cached_desired_f0: None,
             apply_autotune: false,
             volume_level: 100,
+            key_root: Note::C,
+            key_scale: Scale::Major,
+            reanalyze_requested: false,
+            compare_original: false,
+            preview_requested: None,
+            quantize_subdivisions: 2,
+            glide_frames: 4,
+            flatten_note: Note::A,
+            flatten_octave: 3,
+            rerender_debounce: Debouncer::new(DESIRED_F0_DEBOUNCE),
         }
     }
+    /// Whether the original (untuned) audio should currently be heard
+    pub fn compare_original(&self) -> bool {
+        self.open && self.compare_original
+    }
+    /// Returns whether "Re-analyze" was clicked since the last call
+    pub fn take_reanalyze_request(&mut self) -> bool {
+        std::mem::take(&mut self.reanalyze_requested)
+    }
+    /// Returns the frequency of the grid row clicked since the last call, to be previewed
+    pub fn take_preview_request(&mut self) -> Option<f32> {
+        self.preview_requested.take()
+    }
+    /// Returns whether desired pitch edits have settled and the track should be
+    /// re-rendered, so it can be heard while the menu stays open
+    pub fn take_rerender_request(&mut self) -> bool {
+        self.rerender_debounce.poll(Instant::now())
+    }
+    pub fn key(&self) -> Key {
+        Key::new(self.key_root, self.key_scale)
+    }
     pub fn open(&mut self) {
         self.open = true;
     }
     pub fn is_open(&self) -> bool {
         self.open
     }
-    /// Shows a floating window where the autotune can be configured for a track
+    /// Pixels between two adjacent note rows at the current vertical zoom
+    fn note_spacing(&self) -> f32 {
+        VERTICAL_NOTE_SPACING * self.vertical_zoom
+    }
+    /// Shows a floating window where the autotune can be configured for a track.
+    /// `fmin`/`fmax` are the track's pitch detection range, editable from the menu.
+    /// `bpm` is the project tempo the quantize grid is based on.
     pub fn show_menu(
         &mut self,
         id: u32,
         audio: &mut Audio,
-        _ui: &mut egui::Ui,
+        fmin: &mut f32,
+        fmax: &mut f32,
+        bpm: f32,
         ctx: &egui::Context,
     ) -> bool {
         egui::Window::new(format!("Track {} Autotune", id + 1))
@@ -145,8 +270,19 @@ impl TrackMenu {
                                 },
                             );
                         });
-                        let apply_response =
-                            ui.checkbox(&mut self.apply_autotune, "Apply Autotune to this track");
+                        let (apply_response, compare_response) = ui
+                            .horizontal(|ui| {
+                                let apply = ui.checkbox(
+                                    &mut self.apply_autotune,
+                                    "Apply Autotune to this track",
+                                );
+                                let compare = ui
+                                    .button("A/B")
+                                    .on_hover_text("Hold to hear the original audio");
+                                (apply, compare)
+                            })
+                            .inner;
+                        self.compare_original = compare_response.is_pointer_button_down_on();
                         if apply_response.changed() {
                             if !self.apply_autotune {
                                 self.cached_desired_f0 = Some(
@@ -174,12 +310,182 @@ impl TrackMenu {
                                 egui::Slider::new(&mut self.zoom_level, 0.01..=3.0)
                                     .text("x")
                                     .logarithmic(true),
-                            )
+                            );
+                            ui.label("Vertical:");
+                            let old_spacing = self.note_spacing();
+                            let vertical = ui.add(
+                                egui::Slider::new(&mut self.vertical_zoom, 0.5..=4.0)
+                                    .text("x")
+                                    .logarithmic(true),
+                            );
+                            if vertical.changed() {
+                                // Keep the same notes at the top of the view
+                                self.vertical_scroll *= self.note_spacing() / old_spacing;
+                            }
                         });
                         ui.horizontal(|ui| {
                             ui.label("Volume:");
                             ui.add(egui::Slider::new(&mut self.volume_level, 0..=200).text("%"));
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("Pitch range:");
+                            ui.add(
+                                egui::Slider::new(fmin, 20.0..=1000.0)
+                                    .text("Hz min")
+                                    .logarithmic(true),
+                            );
+                            ui.add(
+                                egui::Slider::new(fmax, 100.0..=4000.0)
+                                    .text("Hz max")
+                                    .logarithmic(true),
+                            );
+                            if *fmax <= *fmin {
+                                *fmax = (*fmin * 2.0).min(4000.0);
+                            }
+                            if ui
+                                .button("Re-analyze")
+                                .on_hover_text("Re-run pitch detection with this range")
+                                .clicked()
+                            {
+                                self.reanalyze_requested = true;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Key:");
+                            egui::ComboBox::from_id_salt(("key_root", id))
+                                .selected_text(self.key_root.to_string())
+                                .show_ui(ui, |ui| {
+                                    for note in Note::ALL {
+                                        ui.selectable_value(
+                                            &mut self.key_root,
+                                            note,
+                                            note.to_string(),
+                                        );
+                                    }
+                                });
+                            egui::ComboBox::from_id_salt(("key_scale", id))
+                                .selected_text(self.key_scale.to_string())
+                                .show_ui(ui, |ui| {
+                                    for scale in Scale::ALL {
+                                        ui.selectable_value(
+                                            &mut self.key_scale,
+                                            scale,
+                                            scale.to_string(),
+                                        );
+                                    }
+                                });
+                            let pyin = audio.get_pyin();
+                            if ui
+                                .add_enabled(pyin.is_some(), egui::Button::new("Detect"))
+                                .on_hover_text("Pick the key that best fits the detected pitch")
+                                .clicked()
+                                && let Some(pyin) = pyin
+                            {
+                                let key = autotune::detect_key(&pyin);
+                                self.key_root = key.root();
+                                self.key_scale = key.scale();
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Quantize:");
+                            ui.add(
+                                egui::DragValue::new(&mut self.quantize_subdivisions)
+                                    .range(1..=8)
+                                    .suffix(" per beat"),
+                            );
+                            let quantize = ui
+                                .add_enabled(
+                                    audio.desired_f0.is_some(),
+                                    egui::Button::new("Quantize"),
+                                )
+                                .on_hover_text(format!(
+                                    "Move note starts of the desired pitch onto the grid at {} BPM",
+                                    bpm
+                                ));
+                            let frames_per_beat =
+                                autotune::frames_per_beat(bpm, audio.sample_rate(), HOP_LENGTH);
+                            if quantize.clicked()
+                                && let Some(desired_f0) = audio.desired_f0.as_mut()
+                            {
+                                autotune::quantize_f0_onsets(
+                                    desired_f0,
+                                    frames_per_beat,
+                                    self.quantize_subdivisions,
+                                );
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Glide:");
+                            ui.add(
+                                egui::DragValue::new(&mut self.glide_frames)
+                                    .range(1..=32)
+                                    .suffix(" frames"),
+                            );
+                            if ui
+                                .add_enabled(audio.desired_f0.is_some(), egui::Button::new("Glide"))
+                                .on_hover_text("Slide between notes of the desired pitch")
+                                .clicked()
+                                && let Some(desired_f0) = audio.desired_f0.as_mut()
+                            {
+                                autotune::apply_portamento(desired_f0, self.glide_frames);
+                            }
+                        });
+                        let pyin = audio.get_pyin();
+                        ui.add_enabled_ui(pyin.is_some(), |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Desired pitch:");
+                                let Some(pyin) = &pyin else {
+                                    return;
+                                };
+                                if ui
+                                    .button("Reset")
+                                    .on_hover_text("Follow the detected pitch")
+                                    .clicked()
+                                {
+                                    audio.desired_f0 = Some(autotune::passthrough_f0(pyin));
+                                }
+                                if ui
+                                    .button("Clear")
+                                    .on_hover_text("Mark every frame unvoiced")
+                                    .clicked()
+                                {
+                                    audio.desired_f0 = Some(autotune::cleared_f0(pyin));
+                                }
+                                egui::ComboBox::from_id_salt(("flatten_note", id))
+                                    .width(50.0)
+                                    .selected_text(self.flatten_note.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for note in Note::ALL {
+                                            ui.selectable_value(
+                                                &mut self.flatten_note,
+                                                note,
+                                                note.to_string(),
+                                            );
+                                        }
+                                    });
+                                ui.add(egui::DragValue::new(&mut self.flatten_octave).range(1..=7));
+                                let note_name =
+                                    format!("{}{}", self.flatten_note, self.flatten_octave);
+                                if ui
+                                    .button("Flatten")
+                                    .on_hover_text(format!(
+                                        "Hold every voiced frame on {}",
+                                        note_name
+                                    ))
+                                    .clicked()
+                                {
+                                    match audio::scales::note_name_to_midi_note(&note_name) {
+                                        Ok(midi) => {
+                                            let frequency =
+                                                audio::scales::midi_note_to_frequency(midi);
+                                            audio.desired_f0 =
+                                                Some(autotune::flat_f0(pyin, frequency));
+                                        }
+                                        Err(e) => debug!("Can't flatten to {}: {}", note_name, e),
+                                    }
+                                }
+                            });
+                        });
                     },
                 );
                 // Show timeline ruler for pitch data
@@ -190,45 +496,40 @@ impl TrackMenu {
                         .allocate_exact_size(egui::vec2(ruler_width, ruler_height), Sense::hover());
                     let painter = ui.painter_at(ruler_rect);
                     let pixels_per_second = calculate_pixels_per_second(44100, self.zoom_level);
-                    let scroll_px = self.horizontal_scroll;
-                    let start_time = (scroll_px / pixels_per_second).max(0.0);
-                    let first_mark_time = start_time.floor();
-                    let visible_duration = ruler_width / pixels_per_second;
-                    let last_mark_time = first_mark_time + visible_duration + 1.0;
-
-                    let min_mark_spacing_px = 50.0;
-                    let mut mark_interval = 1.0; // in seconds
-                    while mark_interval * pixels_per_second < min_mark_spacing_px {
-                        mark_interval *= 2.0;
-                    }
-
-                    let mut t = (first_mark_time / mark_interval) as i32;
-                    while (t as f32) <= last_mark_time / mark_interval {
-                        let time_sec = t as f32 * mark_interval;
-
-                        let x =
-                            LEFT_SIDE_PADDING + ruler_rect.left() + time_sec * pixels_per_second
-                                - scroll_px;
+                    let ticks =
+                        timeline_ticks(ruler_width, pixels_per_second, self.horizontal_scroll);
+                    for tick in ticks {
+                        let x = LEFT_SIDE_PADDING + ruler_rect.left() + tick.x;
 
                         // Only draw if inside the ruler rect
-                        if x >= ruler_rect.left() && x <= ruler_rect.right() {
+                        if x < ruler_rect.left() || x > ruler_rect.right() {
+                            continue;
+                        }
+                        if !tick.major {
+                            // Short, faint marks from the bottom edge
                             painter.line_segment(
                                 [
-                                    egui::pos2(x, ruler_rect.top()),
+                                    egui::pos2(x, ruler_rect.bottom() - ruler_height / 3.0),
                                     egui::pos2(x, ruler_rect.bottom()),
                                 ],
-                                egui::Stroke::new(1.0, egui::Color32::LIGHT_GRAY),
-                            );
-                            painter.text(
-                                egui::pos2(x + 2.0, ruler_rect.top() + 2.0),
-                                egui::Align2::LEFT_TOP,
-                                format!("{:.1}s", time_sec),
-                                egui::FontId::default(),
-                                egui::Color32::WHITE,
+                                egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
                             );
+                            continue;
                         }
-
-                        t += 1;
+                        painter.line_segment(
+                            [
+                                egui::pos2(x, ruler_rect.top()),
+                                egui::pos2(x, ruler_rect.bottom()),
+                            ],
+                            egui::Stroke::new(1.0, egui::Color32::LIGHT_GRAY),
+                        );
+                        painter.text(
+                            egui::pos2(x + 2.0, ruler_rect.top() + 2.0),
+                            egui::Align2::LEFT_TOP,
+                            format!("{:.1}s", tick.time_sec),
+                            egui::FontId::default(),
+                            egui::Color32::WHITE,
+                        );
                     }
                 });
                 // Handle horizontal scrolling
@@ -247,7 +548,8 @@ impl TrackMenu {
 
                     let mut rect = ui.max_rect();
                     rect.set_bottom(rect.top() + track_height);
-                    ui.allocate_rect(rect, Sense::hover());
+                    // Clicking a row previews its pitch, desired-pitch points are on top
+                    let grid_response = ui.allocate_rect(rect, Sense::click());
 
                     // Show note names on left using MIDI/freq helpers
                     let mut notes = audio::scales::Key::new(
@@ -273,7 +575,25 @@ impl TrackMenu {
                     .ok()
                     .unwrap_or(127.0) as f32;
 
-                    let total_note_height = note_range_to_height(min_midi, max_midi, rect);
+                    let note_spacing = self.note_spacing();
+                    let total_note_height = note_range_to_height(min_midi, max_midi, note_spacing);
+
+                    if grid_response.clicked()
+                        && let Some(pos) = grid_response.interact_pointer_pos()
+                        && let Some(freq) = y_to_freq(
+                            pos.y,
+                            rect,
+                            min_midi,
+                            max_midi,
+                            self.vertical_scroll,
+                            note_spacing,
+                        )
+                    {
+                        // Snap to the row under the pointer
+                        let midi = audio::scales::frequency_to_midi_note(freq).round();
+                        self.preview_requested = Some(audio::scales::midi_note_to_frequency(midi));
+                    }
+                    let key = self.key();
 
                     for note_name in notes.iter() {
                         let midi = audio::scales::note_name_to_midi_note(note_name)
@@ -283,7 +603,14 @@ impl TrackMenu {
                                 0.0
                             }) as f32;
 
-                        let y = midi_to_y(midi, rect, min_midi, max_midi, self.vertical_scroll);
+                        let y = midi_to_y(
+                            midi,
+                            rect,
+                            min_midi,
+                            max_midi,
+                            self.vertical_scroll,
+                            note_spacing,
+                        );
 
                         if y < rect.top() || y > rect.bottom() {
                             continue;
@@ -304,7 +631,7 @@ impl TrackMenu {
                                     egui::pos2(rect.left() + LEFT_SIDE_PADDING, y),
                                     egui::pos2(rect.right(), y),
                                 ],
-                                egui::Stroke::new(0.5, egui::Color32::DARK_GRAY),
+                                egui::Stroke::new(0.5, row_color_for_midi(midi as u8, key)),
                             );
                         }
                     }
@@ -312,28 +639,16 @@ impl TrackMenu {
                         // Draw vertical grid lines for time
                         let pixels_per_second = calculate_pixels_per_second(44100, self.zoom_level);
                         let scroll_px = self.horizontal_scroll;
-                        let start_time = (scroll_px / pixels_per_second).max(0.0);
-                        let first_mark_time = start_time.floor();
-                        let visible_duration = rect.width() / pixels_per_second;
-                        let last_mark_time = first_mark_time + visible_duration + 1.0;
-                        let min_mark_spacing_px = 50.0;
-                        let mut mark_interval = 1.0; // in seconds
-                        while mark_interval * pixels_per_second < min_mark_spacing_px {
-                            mark_interval *= 2.0;
-                        }
-                        let mut t = (first_mark_time / mark_interval) as i32;
-                        while (t as f32) <= last_mark_time / mark_interval {
-                            let time_sec = t as f32 * mark_interval;
-                            let x = LEFT_SIDE_PADDING + rect.left() + time_sec * pixels_per_second
-                                - scroll_px;
-                            // Only draw if inside the grid rect
+                        for tick in timeline_ticks(rect.width(), pixels_per_second, scroll_px) {
+                            let x = LEFT_SIDE_PADDING + rect.left() + tick.x;
+                            // Only draw if inside the grid rect, minor lines thinner
                             if x >= rect.left() + LEFT_SIDE_PADDING && x <= rect.right() {
+                                let width = if tick.major { 1.0 } else { 0.5 };
                                 painter.line_segment(
                                     [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
-                                    egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+                                    egui::Stroke::new(width, egui::Color32::DARK_GRAY),
                                 );
                             }
-                            t += 1;
                         }
                         if let Some(ref mut desired_f0) = audio.desired_f0 {
                             // Ensure same length as pyin
@@ -343,12 +658,12 @@ impl TrackMenu {
                         }
 
                         // Draw pitch data
-                        let blue = egui::Color32::BLUE;
                         let green = egui::Color32::GREEN;
 
                         for i in 0..pyin.f0().len() {
                             // ----- original pitch (non-editable) -----
-                            if pyin.voiced_prob()[i] >= 0.5 {
+                            let voiced_prob = pyin.voiced_prob()[i];
+                            if voiced_prob >= 0.5 {
                                 let x = frame_to_screen(i, rect, pixels_per_second, scroll_px);
                                 if x < rect.left() || x > rect.right() {
                                     continue;
@@ -359,9 +674,14 @@ impl TrackMenu {
                                     min_midi,
                                     max_midi,
                                     self.vertical_scroll,
+                                    note_spacing,
                                 ) {
                                     if y >= rect.top() && y <= rect.bottom() {
-                                        painter.circle_filled(egui::pos2(x, y), 1.5, blue);
+                                        painter.circle_filled(
+                                            egui::pos2(x, y),
+                                            1.5,
+                                            confidence_color(voiced_prob),
+                                        );
                                     }
                                 }
                             }
@@ -383,6 +703,7 @@ impl TrackMenu {
                                     min_midi,
                                     max_midi,
                                     self.vertical_scroll,
+                                    note_spacing,
                                 ) {
                                     if y < rect.top() || y > rect.bottom() {
                                         continue;
@@ -416,18 +737,28 @@ impl TrackMenu {
                                             min_midi,
                                             max_midi,
                                             self.vertical_scroll,
+                                            note_spacing,
                                         ) {
                                             desired_f0[i] = new_freq;
+                                            self.rerender_debounce.mark_changed(Instant::now());
                                         }
                                     }
                                 }
                             }
                         }
+                    } else if audio.pyin_failed() {
+                        painter.text(
+                            egui::pos2(rect.center().x, rect.center().y - 10.0),
+                            egui::Align2::CENTER_CENTER,
+                            "Pitch analysis failed, press Re-analyze to retry",
+                            egui::FontId::default(),
+                            egui::Color32::RED,
+                        );
                     } else {
                         painter.text(
                             egui::pos2(rect.center().x, rect.center().y - 10.0),
                             egui::Align2::CENTER_CENTER,
-                            "Pitch data being analazed...",
+                            format!("Pitch data being analazed... {}%", audio.pyin_progress()),
                             egui::FontId::default(),
                             egui::Color32::LIGHT_GRAY,
                         );
@@ -437,20 +768,96 @@ impl TrackMenu {
                     (rect, total_note_height)
                 });
                 let (rect, total_note_height) = response.inner;
-                if response.response.hovered() {
-                    if ctx.input(|i| i.raw_scroll_delta.y != 0.0) {
-                        let scroll_amount = ctx.input(|i| i.raw_scroll_delta.y);
-                        self.vertical_scroll += scroll_amount * 0.5;
-
-                        // Clamp so we don't scroll past the octaves (allow some overscroll)
-                        let max_scroll = 5.0;
-                        let min_scroll =
-                            (rect.height() - total_note_height - VERTICAL_NOTE_SPACING / 2.0)
-                                .min(0.0);
-                        self.vertical_scroll = self.vertical_scroll.clamp(min_scroll, max_scroll);
-                    }
+                if response.response.hovered() && ctx.input(|i| i.raw_scroll_delta.y != 0.0) {
+                    let scroll_amount = ctx.input(|i| i.raw_scroll_delta.y);
+                    self.vertical_scroll += scroll_amount * 0.5;
                 }
+                // Clamp every frame, zooming out can leave the scroll past the octaves
+                // (allow some overscroll)
+                let max_scroll = 5.0;
+                let min_scroll =
+                    (rect.height() - total_note_height - self.note_spacing() / 2.0).min(0.0);
+                self.vertical_scroll = self.vertical_scroll.clamp(min_scroll, max_scroll);
             });
+        if self.rerender_debounce.is_pending() {
+            // Keep repainting so the quiet period is noticed without further input
+            ctx.request_repaint_after(DESIRED_F0_DEBOUNCE);
+        }
         self.open
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer_coalesces_quick_edits() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(150));
+        assert!(!debouncer.poll(start));
+
+        debouncer.mark_changed(start);
+        debouncer.mark_changed(start + Duration::from_millis(50));
+        // Only 100ms since the second edit
+        assert!(!debouncer.poll(start + Duration::from_millis(150)));
+
+        let triggers = [200, 250, 400]
+            .iter()
+            .filter(|&&ms| debouncer.poll(start + Duration::from_millis(ms)))
+            .count();
+        assert_eq!(triggers, 1);
+        assert!(!debouncer.is_pending());
+    }
+
+    #[test]
+    fn test_row_color_highlights_in_key_notes() {
+        let key = Key::new(Note::C, Scale::Major);
+        for midi in [48, 60, 62, 64, 71, 72] {
+            assert_eq!(row_color_for_midi(midi, key), IN_KEY_ROW_COLOR, "{}", midi);
+        }
+        for midi in [49, 61, 63, 66, 70] {
+            assert_eq!(
+                row_color_for_midi(midi, key),
+                OUT_OF_KEY_ROW_COLOR,
+                "{}",
+                midi
+            );
+        }
+        assert_ne!(IN_KEY_ROW_COLOR, OUT_OF_KEY_ROW_COLOR);
+    }
+
+    #[test]
+    fn test_confidence_color_fades_from_gray_to_blue() {
+        assert_eq!(confidence_color(0.5), LOW_CONFIDENCE_COLOR);
+        assert_eq!(confidence_color(0.2), LOW_CONFIDENCE_COLOR);
+        assert_eq!(confidence_color(1.0), egui::Color32::BLUE);
+        let mid = confidence_color(0.75);
+        assert!(mid.b() > LOW_CONFIDENCE_COLOR.b() && mid.b() < 255);
+        assert!(mid.r() < LOW_CONFIDENCE_COLOR.r());
+    }
+
+    #[test]
+    fn test_doubling_note_spacing_doubles_row_distance() {
+        let rect = egui::Rect::from_min_size(egui::pos2(0.0, 50.0), egui::vec2(400.0, 300.0));
+        let distance = |spacing| {
+            midi_to_y(60.0, rect, 36.0, 84.0, -20.0, spacing)
+                - midi_to_y(67.0, rect, 36.0, 84.0, -20.0, spacing)
+        };
+        assert_eq!(distance(VERTICAL_NOTE_SPACING), 7.0 * VERTICAL_NOTE_SPACING);
+        assert_eq!(
+            distance(2.0 * VERTICAL_NOTE_SPACING),
+            2.0 * distance(VERTICAL_NOTE_SPACING)
+        );
+        assert_eq!(
+            note_range_to_height(36.0, 84.0, 2.0 * VERTICAL_NOTE_SPACING),
+            2.0 * note_range_to_height(36.0, 84.0, VERTICAL_NOTE_SPACING)
+        );
+
+        // y_to_freq inverts midi_to_y at any spacing
+        let spacing = 2.0 * VERTICAL_NOTE_SPACING;
+        let y = midi_to_y(67.0, rect, 36.0, 84.0, -20.0, spacing);
+        let freq = y_to_freq(y, rect, 36.0, 84.0, -20.0, spacing).unwrap();
+        assert!((audio::scales::frequency_to_midi_note(freq) - 67.0).abs() < 1e-3);
+    }
+}
@@ -1,3 +1,4 @@
 #[allow(unused)]
 pub mod audio;
 pub mod gui;
+pub mod pipeline;
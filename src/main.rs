@@ -1,8 +1,11 @@
 use clap::Parser;
+use std::path::PathBuf;
+use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt};
 
 mod audio;
 mod gui;
+mod pipeline;
 
 /// Initialize the logger with the specified log level
 fn init_logger(level: &str) {
@@ -22,6 +25,38 @@ struct Args {
     /// Log level filter (e.g., error, warn, info, debug)
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// Key to tune to when running without the GUI (e.g., "C major", "A minor")
+    #[arg(short, long, default_value = "C major")]
+    key: audio::scales::Key,
+
+    /// Pitch-shift engine used when running without the GUI (psola or phase_vocoder)
+    #[arg(long, default_value = "psola")]
+    engine: audio::autotune::ShiftEngine,
+
+    /// Tune a single WAV file instead of starting the GUI
+    #[arg(short, long, conflicts_with = "batch")]
+    input: Option<PathBuf>,
+
+    /// Path the tuned file is written to when using --input
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Write the pitch track detected in --input to this path as CSV
+    #[arg(long, value_name = "PATH", requires = "input")]
+    dump_f0: Option<PathBuf>,
+
+    /// Normalize tuned output to this approximate loudness in dB (e.g. -16)
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    normalize: Option<f32>,
+
+    /// Tune every WAV file in this directory instead of starting the GUI
+    #[arg(long, requires = "out")]
+    batch: Option<PathBuf>,
+
+    /// Directory the tuned files are written to in batch mode
+    #[arg(long)]
+    out: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -29,6 +64,28 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     init_logger(&args.log_level);
 
+    if let Some(input) = &args.input {
+        if args.output.is_none() && args.dump_f0.is_none() {
+            anyhow::bail!("--input requires --output and/or --dump-f0");
+        }
+        if let Some(csv) = &args.dump_f0 {
+            let n_frames = pipeline::dump_f0(input, csv)?;
+            info!("Wrote {} frames of pitch data to {:?}", n_frames, csv);
+        }
+        if let Some(output) = &args.output {
+            pipeline::tune_file(input, output, args.key, args.engine, args.normalize)?;
+            info!("Output file: {:?}", output);
+        }
+        return Ok(());
+    }
+
+    if let (Some(input_dir), Some(output_dir)) = (&args.batch, &args.out) {
+        let outputs =
+            pipeline::batch_tune(input_dir, output_dir, args.key, args.engine, args.normalize)?;
+        info!("Tuned {} files into {:?}", outputs.len(), output_dir);
+        return Ok(());
+    }
+
     gui::run().map_err(|e| anyhow::anyhow!("{}", e))?;
     Ok(())
 }
@@ -0,0 +1,156 @@
+use crate::audio::autotune::pyin::{PYINData, PyinConfig};
+use crate::audio::autotune::{self, HOP_LENGTH, ShiftEngine, pyin_cache};
+use crate::audio::{Audio, LOW_MONO_COMPATIBILITY, file::AudioFileData, scales::Key};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Runs the full autotune pipeline on `audio` without a GUI:
+/// PYIN analysis, snapping the detected pitch to `key`, then pitch shifting with `engine`.
+pub fn tune_audio(audio: &Audio, key: Key, engine: ShiftEngine) -> anyhow::Result<Audio> {
+    let mut audio = audio.clone();
+    audio.perform_pyin();
+    tune_analyzed(audio, key, engine)
+}
+
+/// The part of `tune_audio` after PYIN analysis, for audio that already has its PYIN data.
+fn tune_analyzed(mut audio: Audio, key: Key, engine: ShiftEngine) -> anyhow::Result<Audio> {
+    let pyin = audio
+        .get_pyin()
+        .ok_or_else(|| anyhow::anyhow!("PYIN analysis produced no data"))?;
+    debug!(n_frames = pyin.f0().len(), %key, "Snapping detected pitch to key");
+    let desired_f0 = autotune::snap_to_scale(pyin.f0(), key);
+    for note in autotune::correction_report(pyin.f0(), &desired_f0, &key) {
+        debug!(
+            start = note.start_time(audio.sample_rate()),
+            note = %note.note,
+            cents = note.cents,
+            "Note correction"
+        );
+    }
+    audio.desired_f0 = Some(desired_f0);
+    Ok(autotune::compute_shifted_audio_with_engine(&audio, engine)?)
+}
+
+/// Runs PYIN on `audio`, loaded from `input`, unless the file's sidecar cache
+/// (`<input>.pyin.json`) already holds the result. Fresh results are written to the cache.
+fn analyze_file(input: &Path, audio: &mut Audio) {
+    let config = PyinConfig::default();
+    if let Some(data) = pyin_cache::load(input, &config) {
+        debug!(?input, "Using cached PYIN analysis");
+        audio.set_pyin(data);
+        return;
+    }
+    audio.perform_pyin();
+    if let Some(data) = audio.get_pyin()
+        && let Err(e) = pyin_cache::save(input, &config, &data)
+    {
+        warn!(?input, "Failed to write PYIN cache: {}", e);
+    }
+}
+
+/// Loads `input`, tunes it to `key` with `engine` and saves the result to `output`.
+/// With `normalize_db` set, the result is scaled to that approximate loudness first.
+/// The PYIN analysis is cached next to `input`, see `analyze_file`.
+pub fn tune_file(
+    input: &Path,
+    output: &Path,
+    key: Key,
+    engine: ShiftEngine,
+    normalize_db: Option<f32>,
+) -> anyhow::Result<()> {
+    info!(?input, ?output, %engine, "Tuning file");
+    let file = AudioFileData::load(input)?;
+    let mut audio = file.to_audio();
+    analyze_file(input, &mut audio);
+    let mut tuned = tune_analyzed(audio, key, engine)?;
+    if let Some(target_db) = normalize_db {
+        tuned.normalize_loudness(target_db);
+    }
+    let compatibility = tuned.mono_compatibility();
+    if compatibility < LOW_MONO_COMPATIBILITY {
+        warn!(
+            ?output,
+            compatibility, "Output partly cancels out when played in mono"
+        );
+    }
+    // Keep the channel layout of the input, mono files are written back as mono
+    AudioFileData::from_audio_with_channels(&tuned, file.n_channels()).save(output)
+}
+
+/// Tunes every WAV file in `input_dir` to `key` with `engine`, writing each result into
+/// `output_dir` under the same file name. Returns the paths of the written files.
+pub fn batch_tune(
+    input_dir: &Path,
+    output_dir: &Path,
+    key: Key,
+    engine: ShiftEngine,
+    normalize_db: Option<f32>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    fs::create_dir_all(output_dir)?;
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(input_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        })
+        .collect();
+    inputs.sort();
+    info!(count = inputs.len(), ?input_dir, "Batch tuning files");
+
+    let mut outputs = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let file_name = input
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid input path {:?}", input))?;
+        // Always save with a lowercase extension, `AudioFileData::save` matches on "wav"
+        let output = output_dir.join(file_name).with_extension("wav");
+        tune_file(&input, &output, key, engine, normalize_db)?;
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+/// Writes a PYIN pitch track as CSV with one row per analysis frame:
+/// `time_seconds,f0_hz,voiced_flag,voiced_prob`. The time is the start of the frame.
+pub fn write_f0_csv<W: Write>(
+    pyin: &PYINData,
+    sample_rate: u32,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    writeln!(writer, "time_seconds,f0_hz,voiced_flag,voiced_prob")?;
+    for (i, ((f0, voiced), prob)) in pyin
+        .f0()
+        .iter()
+        .zip(pyin.voiced_flag())
+        .zip(pyin.voiced_prob())
+        .enumerate()
+    {
+        let time = (i * HOP_LENGTH) as f64 / sample_rate as f64;
+        writeln!(writer, "{:.6},{},{},{}", time, f0, *voiced as u8, prob)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Runs PYIN on `input` and dumps the detected pitch track to `output` as CSV.
+/// Returns the number of frames written. Uses and fills the same PYIN cache as `tune_file`.
+pub fn dump_f0(input: &Path, output: &Path) -> anyhow::Result<usize> {
+    info!(?input, ?output, "Dumping detected pitch track");
+    let mut audio = AudioFileData::load(input)?.to_audio();
+    analyze_file(input, &mut audio);
+    let pyin = audio
+        .get_pyin()
+        .ok_or_else(|| anyhow::anyhow!("PYIN analysis produced no data"))?;
+    write_f0_csv(
+        &pyin,
+        audio.sample_rate(),
+        BufWriter::new(File::create(output)?),
+    )?;
+    Ok(pyin.f0().len())
+}
@@ -0,0 +1,52 @@
+//! Integration test for the batch (no GUI) tuning pipeline:
+//! every WAV in an input directory should produce a tuned WAV in the output directory.
+
+use std::fs;
+use std::path::PathBuf;
+
+use autotune::audio::{Audio, autotune::ShiftEngine, file::AudioFileData, scales::Key};
+use autotune::pipeline;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let mut p = std::env::temp_dir();
+    p.push(format!("autotune_{}_{}", name, std::process::id()));
+    if p.exists() {
+        fs::remove_dir_all(&p).expect("failed to clear temp directory");
+    }
+    fs::create_dir_all(&p).expect("failed to create temp directory");
+    p
+}
+
+fn write_sine_wav(path: &PathBuf, freq: f32) -> anyhow::Result<()> {
+    let sample_rate = 16000;
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|n| 0.5 * (2.0 * std::f32::consts::PI * freq * n as f32 / sample_rate as f32).sin())
+        .collect();
+    let audio = Audio::new(sample_rate, samples.clone(), samples);
+    AudioFileData::from_audio(&audio).save(path)
+}
+
+#[test]
+fn batch_tune_writes_one_output_per_input() -> anyhow::Result<()> {
+    let input_dir = temp_dir("batch_in");
+    let output_dir = temp_dir("batch_out");
+    write_sine_wav(&input_dir.join("first.wav"), 230.0)?;
+    write_sine_wav(&input_dir.join("second.wav"), 450.0)?;
+    // Non-WAV files are ignored
+    fs::write(input_dir.join("notes.txt"), "not audio")?;
+
+    let key: Key = "C major".parse().map_err(anyhow::Error::msg)?;
+    let outputs = pipeline::batch_tune(&input_dir, &output_dir, key, ShiftEngine::Psola, None)?;
+
+    assert_eq!(outputs.len(), 2);
+    for name in ["first.wav", "second.wav"] {
+        let output = output_dir.join(name);
+        assert!(outputs.contains(&output), "missing output {:?}", output);
+        let tuned = AudioFileData::load(&output)?;
+        assert!(!tuned.is_empty(), "tuned output {:?} is empty", output);
+    }
+
+    fs::remove_dir_all(&input_dir)?;
+    fs::remove_dir_all(&output_dir)?;
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Integration test for exporting the detected pitch track as CSV.
+
+use std::fs;
+use std::path::PathBuf;
+
+use autotune::audio::autotune::pyin_cache;
+use autotune::audio::file::AudioFileData;
+use autotune::pipeline;
+
+fn temp_path(name: &str, ext: &str) -> PathBuf {
+    let mut p = std::env::temp_dir();
+    p.push(format!("autotune_{}_{}.{}", name, std::process::id(), ext));
+    p
+}
+
+#[test]
+fn dump_f0_writes_header_plus_one_row_per_frame() -> anyhow::Result<()> {
+    let input = temp_path("dump_f0_in", "wav");
+    let output = temp_path("dump_f0_out", "csv");
+
+    let sample_rate = 16000;
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|n| 0.5 * (2.0 * std::f32::consts::PI * 220.0 * n as f32 / sample_rate as f32).sin())
+        .collect();
+    AudioFileData::new(samples, sample_rate, 1)?.save(&input)?;
+
+    let n_frames = pipeline::dump_f0(&input, &output)?;
+    assert!(n_frames > 0);
+
+    let csv = fs::read_to_string(&output)?;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("time_seconds,f0_hz,voiced_flag,voiced_prob")
+    );
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), n_frames);
+    assert!(rows.iter().all(|row| row.split(',').count() == 4));
+
+    // The analysis was cached, a second dump reads it back
+    let cache = pyin_cache::cache_path(&input);
+    assert!(cache.exists());
+    assert_eq!(pipeline::dump_f0(&input, &output)?, n_frames);
+    assert_eq!(fs::read_to_string(&output)?, csv);
+
+    fs::remove_file(&input)?;
+    fs::remove_file(&cache)?;
+    fs::remove_file(&output)?;
+    Ok(())
+}